@@ -0,0 +1,264 @@
+/**
+ * LanceDB 目录的定期快照与恢复
+ * 快照是 db 目录的完整拷贝，保存在 ~/.imgsearch/backups/<name>
+ */
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use serde::Serialize;
+use tauri::{AppHandle, State, Wry};
+use tauri_plugin_store::Store;
+use walkdir::WalkDir;
+
+use crate::{
+    db::{self, IntegrityReport},
+    error::AppError,
+    maintenance::DB_MAINTENANCE_LOCK,
+    path_utils,
+    settings_command::{backup_settings, BackupSettings},
+    GlobalState,
+};
+
+static BACKUP_PREFIX: &str = "backup-";
+static PENDING_RESTORE_FILE: &str = "restore.pending";
+// lance 的版本清单目录，需先于数据文件拷贝
+static VERSIONS_DIR: &str = "_versions";
+// 调度器检查是否需要备份的间隔
+const CHECK_INTERVAL: Duration = Duration::from_secs(60 * 10);
+
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct BackupInfo {
+    pub name: String,
+    // unix 秒
+    pub created_at: u64,
+    pub size: u64,
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+fn dir_size(p: &Path) -> u64 {
+    WalkDir::new(p)
+        .into_iter()
+        .flatten()
+        .filter_map(|e| e.metadata().ok())
+        .filter(|m| m.is_file())
+        .map(|m| m.len())
+        .sum()
+}
+
+/**
+ * 拷贝目录。数据文件不会被修改，先拷贝版本清单再拷贝数据，
+ * 运行中拷贝时，清单引用的数据文件都已存在
+ */
+fn copy_dir(from: &Path, to: &Path) -> Result<(), AppError> {
+    let (versions, others): (Vec<_>, Vec<_>) = WalkDir::new(from)
+        .into_iter()
+        .collect::<Result<Vec<_>, _>>()?
+        .into_iter()
+        .filter(|e| e.file_type().is_file())
+        .map(|e| e.into_path())
+        .partition(|p| p.components().any(|c| c.as_os_str() == VERSIONS_DIR));
+
+    for p in versions.into_iter().chain(others) {
+        let rel = p
+            .strip_prefix(from)
+            .map_err(|e| AppError::Internal(format!("{e}")))?;
+        let target = to.join(rel);
+        if let Some(parent) = target.parent() {
+            std::fs::create_dir_all(parent).map_err(|e| AppError::io(e, parent))?;
+        }
+        std::fs::copy(&p, &target).map_err(|e| AppError::io(e, &p))?;
+    }
+
+    Ok(())
+}
+
+pub fn list_backups() -> Result<Vec<BackupInfo>, AppError> {
+    let dir = path_utils::backups_dir()?;
+    let mut r = vec![];
+
+    for entry in std::fs::read_dir(&dir).map_err(|e| AppError::io(e, &dir))? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().to_string();
+        let created_at = name
+            .strip_prefix(BACKUP_PREFIX)
+            .and_then(|s| s.parse::<u64>().ok());
+
+        if let (Some(created_at), true) = (created_at, entry.path().is_dir()) {
+            r.push(BackupInfo {
+                size: dir_size(&entry.path()),
+                name,
+                created_at,
+            });
+        }
+    }
+
+    // 最新的在前
+    r.sort_by(|a, b| b.created_at.cmp(&a.created_at));
+    Ok(r)
+}
+
+fn backup_path(name: &str) -> Result<PathBuf, AppError> {
+    let p = path_utils::backups_dir()?.join(name);
+    if !name.starts_with(BACKUP_PREFIX) || name.contains(['/', '\\']) || !p.is_dir() {
+        return Err(AppError::Config(format!("backup not found: {name}")));
+    }
+    Ok(p)
+}
+
+/**
+ * 创建快照，并按保留数量清理旧快照
+ */
+pub fn create_backup(keep: usize) -> Result<BackupInfo, AppError> {
+    let name = format!("{BACKUP_PREFIX}{}", now_secs());
+    let dir = path_utils::backups_dir()?;
+    let tmp = dir.join(format!(".{name}"));
+    let target = dir.join(&name);
+
+    // 先拷贝到临时目录，避免中断后留下不完整的快照
+    if let Err(e) = copy_dir(&path_utils::lancedb_dir()?, &tmp) {
+        let _ = std::fs::remove_dir_all(&tmp);
+        return Err(e);
+    }
+    std::fs::rename(&tmp, &target).map_err(|e| AppError::io(e, &tmp))?;
+    log::info!("create backup {}", target.display());
+
+    for old in list_backups()?.into_iter().skip(keep.max(1)) {
+        let p = dir.join(&old.name);
+        log::info!("remove expired backup {}", p.display());
+        std::fs::remove_dir_all(&p).map_err(|e| AppError::io(e, &p))?;
+    }
+
+    Ok(BackupInfo {
+        size: dir_size(&target),
+        name,
+        created_at: now_secs(),
+    })
+}
+
+/**
+ * 用快照替换 db 目录，调用时不能有打开的 table
+ * 原目录保留为 db.before-restore，以便手动找回
+ */
+pub fn restore(name: &str) -> Result<(), AppError> {
+    let src = backup_path(name)?;
+    let db_dir = path_utils::lancedb_dir()?;
+    let old = db_dir.with_extension("before-restore");
+
+    if old.exists() {
+        std::fs::remove_dir_all(&old).map_err(|e| AppError::io(e, &old))?;
+    }
+    std::fs::rename(&db_dir, &old).map_err(|e| AppError::io(e, &db_dir))?;
+
+    if let Err(e) = copy_dir(&src, &db_dir) {
+        // 恢复失败时还原
+        let _ = std::fs::remove_dir_all(&db_dir);
+        let _ = std::fs::rename(&old, &db_dir);
+        return Err(e);
+    }
+
+    log::info!("restore db from backup {name}");
+    Ok(())
+}
+
+/**
+ * 应用上次运行时登记的恢复，需在 init_db 之前调用
+ */
+pub fn apply_pending_restore() -> Result<(), AppError> {
+    let p = path_utils::backups_dir()?.join(PENDING_RESTORE_FILE);
+    if !p.exists() {
+        return Ok(());
+    }
+
+    let name = std::fs::read_to_string(&p).map_err(|e| AppError::io(e, &p))?;
+    path_utils::remove_file(&p)?;
+    restore(name.trim())
+}
+
+/**
+ * 定期备份，间隔与保留数量读取自 Settings.json
+ */
+pub fn schedule(settings_store: Arc<Store<Wry>>) {
+    tauri::async_runtime::spawn(async move {
+        loop {
+            let settings = backup_settings(&settings_store);
+            if settings.enabled {
+                if let Err(e) = backup_if_due(settings).await {
+                    log::error!("scheduled backup error, {e}");
+                }
+            }
+            tokio::time::sleep(CHECK_INTERVAL).await;
+        }
+    });
+}
+
+async fn backup_if_due(settings: BackupSettings) -> Result<(), AppError> {
+    let latest = list_backups()?.first().map(|b| b.created_at).unwrap_or(0);
+    if now_secs().saturating_sub(latest) < settings.interval_hours * 3600 {
+        return Ok(());
+    }
+
+    let _guard = DB_MAINTENANCE_LOCK.lock().await;
+    tauri::async_runtime::spawn_blocking(move || create_backup(settings.keep)).await??;
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn list_db_backups() -> Result<Vec<BackupInfo>, AppError> {
+    list_backups()
+}
+
+#[tauri::command]
+pub async fn create_db_backup(state: State<'_, GlobalState>) -> Result<BackupInfo, AppError> {
+    let settings = backup_settings(&state.settings_store);
+    let _guard = DB_MAINTENANCE_LOCK.lock().await;
+    tauri::async_runtime::spawn_blocking(move || create_backup(settings.keep)).await?
+}
+
+/**
+ * 恢复需要关闭 table，先登记，重启后在打开数据库前执行
+ */
+#[tauri::command]
+pub async fn restore_db_backup(name: String, app: AppHandle) -> Result<(), AppError> {
+    backup_path(&name)?;
+
+    let p = path_utils::backups_dir()?.join(PENDING_RESTORE_FILE);
+    std::fs::write(&p, &name).map_err(|e| AppError::io(e, &p))?;
+    log::info!("restore {name} scheduled, restarting");
+
+    app.restart();
+}
+
+#[tauri::command]
+pub async fn db_integrity(state: State<'_, GlobalState>) -> Result<IntegrityReport, AppError> {
+    Ok(state.db_integrity.read().await.clone())
+}
+
+/**
+ * 完整性检查失败后，由用户确认回滚到最新可读版本
+ */
+#[tauri::command]
+pub async fn rollback_db(state: State<'_, GlobalState>) -> Result<IntegrityReport, AppError> {
+    let mut report = state.db_integrity.write().await;
+    if report.rollback_version.is_none() {
+        return Err(AppError::Db(
+            "no readable version to roll back to".to_string(),
+        ));
+    }
+
+    db::rollback(&state.img_idx_tbl).await?;
+    *report = IntegrityReport {
+        ok: true,
+        ..Default::default()
+    };
+    Ok(report.clone())
+}
@@ -0,0 +1,642 @@
+/**
+ * imgsearch 命令行，与 GUI 共用 ~/.imgsearch 下的索引
+ *
+ * 只读命令（search/similar/stats/export）与 GUI 共享数据目录锁，
+ * 写入命令（add-dir/relocate-dir/reindex）需要独占锁，GUI 运行时会直接报错退出
+ */
+use std::{
+    collections::HashMap,
+    io::Write,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use imgsearch_client::{
+    backup, db,
+    error::AppError,
+    image_command::{
+        api, bench, idx, load_imgdirs, naming::RenameRule, roots, vector_index, ImgDir,
+        ImgDirOptions, ImgDirs, SearchModel, SizeThresholds,
+    },
+    maintenance,
+    mcp::{self, McpContext},
+    path_utils::{self, ScanOptions},
+    server::{imgsearch_server::ImgseachServer, new_server},
+    settings_command::{BackupSettings, IndexSettings, MaintenanceSettings},
+};
+use lancedb::Table;
+use serde_json::{Map, Value};
+
+static APP_IDENTIFIER: &str = "dev.imgsearch.client";
+static AUTH_STORE: &str = "Auth.json";
+static IMGDIR_STORE: &str = "ImgDirStore.json";
+static SETTINGS_STORE: &str = "Settings.json";
+
+static USAGE: &str = "usage: imgsearch-cli <command> [options]
+
+commands:
+  add-dir <root> [--rename] [imgdir options]
+                                  add an imgdir and index it
+  set-dir <root> [imgdir options] change an imgdir's options, applied on
+                                  the next index
+  relocate-dir <old> <new>        point an imgdir at its new location,
+                                  keeping its index
+  reindex [<root>]                index pending and retry failed images,
+                                  or re-index every image under <root>
+  search <text> [--top N]         search images by text
+  similar <path> [--top N]        search images similar to <path>
+  stats                           show index statistics
+  export [--out <file>]           export the index as JSON lines
+  index-stats                     show vector and scalar index statistics
+  bench [--rows N] [--times N]    time deletes/renames on a temporary table
+                                  with and without scalar indexes
+  mcp                             serve the Model Context Protocol over stdio
+  optimize                        compact the database and prune old versions
+  backups                         list database backups
+  backup                          back up the database now
+  restore-backup <name>           replace the database with a backup
+
+options:
+  --apikey <key>                  defaults to IMGSEARCH_APIKEY or the app's apikey
+
+imgdir options:
+  --write-xmp <true|false>        read and write .xmp sidecar files
+  --rename-template <template>    file name template for renames, using
+                                  {ai_name} {orig_name} {date} {counter}
+  --rename-max-len <N>            max file name length, without extension
+  --rename-case <case>            keep, lower, upper, snake or kebab
+  --transliterate <true|false>    transliterate non-ascii names to ascii
+  --include <glob>                only index matching paths, relative to root;
+                                  repeatable, an empty glob clears the list
+  --exclude <glob>                skip matching paths, relative to root;
+                                  repeatable, an empty glob clears the list
+  --skip-hidden <true|false>      skip files and dirs starting with '.'
+  --follow-symlinks <true|false>  follow symlinked files and dirs
+  --max-depth <N|none>            max dir depth below root, root is 0
+  --min-width <N>                 skip images narrower than N pixels, 0 for no limit
+  --min-height <N>                skip images shorter than N pixels, 0 for no limit
+  --min-bytes <N>                 skip files smaller than N bytes, 0 for no limit
+  --max-bytes <N>                 skip files larger than N bytes, 0 for no limit
+  --skip-transparent <true|false> skip fully transparent images";
+
+// 带值的选项，其余 --name 为开关
+static VALUE_OPTIONS: &[&str] = &[
+    "top",
+    "out",
+    "apikey",
+    "rows",
+    "times",
+    "write-xmp",
+    "rename-template",
+    "rename-max-len",
+    "rename-case",
+    "transliterate",
+    "skip-hidden",
+    "follow-symlinks",
+    "max-depth",
+    "min-width",
+    "min-height",
+    "min-bytes",
+    "max-bytes",
+    "skip-transparent",
+];
+// 可重复的选项
+static LIST_OPTIONS: &[&str] = &["include", "exclude"];
+
+struct Args {
+    positional: Vec<String>,
+    options: HashMap<String, String>,
+    lists: HashMap<String, Vec<String>>,
+    flags: Vec<String>,
+}
+
+impl Args {
+    fn parse(args: impl Iterator<Item = String>) -> Self {
+        let mut positional = Vec::new();
+        let mut options = HashMap::new();
+        let mut lists: HashMap<String, Vec<String>> = HashMap::new();
+        let mut flags = Vec::new();
+
+        let mut args = args.peekable();
+        while let Some(arg) = args.next() {
+            match arg.strip_prefix("--") {
+                Some(name) if VALUE_OPTIONS.contains(&name) => {
+                    if let Some(v) = args.next() {
+                        options.insert(name.to_string(), v);
+                    }
+                }
+                Some(name) if LIST_OPTIONS.contains(&name) => {
+                    if let Some(v) = args.next() {
+                        lists.entry(name.to_string()).or_default().push(v);
+                    }
+                }
+                Some(name) => flags.push(name.to_string()),
+                None => positional.push(arg),
+            }
+        }
+
+        Self {
+            positional,
+            options,
+            lists,
+            flags,
+        }
+    }
+
+    fn positional(&self, i: usize, name: &str) -> Result<&str, AppError> {
+        self.positional
+            .get(i)
+            .map(|s| s.as_str())
+            .ok_or_else(|| AppError::Config(format!("missing argument <{name}>\n\n{USAGE}")))
+    }
+
+    fn flag(&self, name: &str) -> bool {
+        self.flags.iter().any(|f| f == name)
+    }
+
+    fn top(&self) -> Result<usize, AppError> {
+        self.number("top", 10)
+    }
+
+    fn bool(&self, name: &str) -> Result<Option<bool>, AppError> {
+        self.options
+            .get(name)
+            .map(|v| {
+                v.parse()
+                    .map_err(|_| AppError::Config(format!("invalid --{name}: {v}")))
+            })
+            .transpose()
+    }
+
+    fn number(&self, name: &str, default: usize) -> Result<usize, AppError> {
+        match self.options.get(name) {
+            Some(v) => v
+                .parse()
+                .map_err(|_| AppError::Config(format!("invalid --{name}: {v}"))),
+            None => Ok(default),
+        }
+    }
+}
+
+fn main() {
+    let args = Args::parse(std::env::args().skip(1));
+
+    if let Err(e) = tauri::async_runtime::block_on(run(args)) {
+        eprintln!("{e}");
+        std::process::exit(1);
+    }
+}
+
+async fn run(args: Args) -> Result<(), AppError> {
+    let Some(command) = args.positional.first().cloned() else {
+        println!("{USAGE}");
+        return Ok(());
+    };
+
+    match command.as_str() {
+        "add-dir" => {
+            let _lock = path_utils::lock_data_dir(true)?;
+            add_dir(&args).await
+        }
+        "set-dir" => {
+            let _lock = path_utils::lock_data_dir(true)?;
+            set_dir(&args)
+        }
+        "relocate-dir" => {
+            let _lock = path_utils::lock_data_dir(true)?;
+            relocate_dir(&args).await
+        }
+        "reindex" => {
+            let _lock = path_utils::lock_data_dir(true)?;
+            reindex(&args).await
+        }
+        "search" => {
+            let _lock = path_utils::lock_data_dir(false)?;
+            let model = SearchModel {
+                keyword: args.positional(1, "text")?.to_string(),
+                top: args.top()?,
+                ..Default::default()
+            };
+            let r = api::search(&model, &require_server(&args)?, read_table().await?).await?;
+            print_results(&r);
+            Ok(())
+        }
+        "similar" => {
+            let _lock = path_utils::lock_data_dir(false)?;
+            let path = std::path::absolute(args.positional(1, "path")?)?;
+            let r = api::similar(
+                &path,
+                args.top()?,
+                &require_server(&args)?,
+                read_table().await?,
+            )
+            .await?;
+            print_results(&r);
+            Ok(())
+        }
+        "stats" => {
+            let _lock = path_utils::lock_data_dir(false)?;
+            let imgdirs = load_imgdirs(read_store(IMGDIR_STORE)?.into_iter().collect());
+            let r = api::stats(&imgdirs, read_table().await?).await?;
+            println!("{}", serde_json::to_string_pretty(&r)?);
+            Ok(())
+        }
+        "index-stats" => {
+            let _lock = path_utils::lock_data_dir(false)?;
+            let r = vector_index::report(read_table().await?).await?;
+            println!("{}", serde_json::to_string_pretty(&r)?);
+            Ok(())
+        }
+        "bench" => {
+            // 只写临时目录，持有共享锁避免与恢复备份等独占操作同时进行
+            let _lock = path_utils::lock_data_dir(false)?;
+            let rows = args.number("rows", 100_000)?;
+            let times = args.number("times", 100)?;
+            let r = bench::scalar_index_bench(rows, times).await?;
+            for item in r.iter() {
+                println!(
+                    "{:<16}\tindexed={}\t{:.2}ms",
+                    item.op, item.indexed, item.avg_ms
+                );
+            }
+            Ok(())
+        }
+        "export" => {
+            let _lock = path_utils::lock_data_dir(false)?;
+            export(&args).await
+        }
+        "optimize" => {
+            let _lock = path_utils::lock_data_dir(true)?;
+            let r = maintenance::optimize(open_table().await?, &maintenance_settings()?).await?;
+            println!("{}", serde_json::to_string_pretty(&r)?);
+            Ok(())
+        }
+        "backups" => {
+            let r = backup::list_backups()?;
+            println!("{}", serde_json::to_string_pretty(&r)?);
+            Ok(())
+        }
+        "backup" => {
+            let _lock = path_utils::lock_data_dir(false)?;
+            let settings = backup_settings()?;
+            let r = backup::create_backup(settings.keep)?;
+            println!("{}", r.name);
+            Ok(())
+        }
+        "restore-backup" => {
+            let _lock = path_utils::lock_data_dir(true)?;
+            backup::restore(args.positional(1, "name")?)
+        }
+        "mcp" => {
+            let _lock = path_utils::lock_data_dir(false)?;
+            let ctx = McpContext {
+                server: server(&args)?,
+                imgdirs: load_imgdirs(read_store(IMGDIR_STORE)?.into_iter().collect()),
+                img_idx_tbl: read_table().await?,
+            };
+            mcp::serve_stdio(ctx).await
+        }
+        _ => Err(AppError::Config(format!(
+            "unknown command: {command}\n\n{USAGE}"
+        ))),
+    }
+}
+
+async fn add_dir(args: &Args) -> Result<(), AppError> {
+    let root = std::path::absolute(args.positional(1, "root")?)?;
+    if !root.is_dir() {
+        return Err(AppError::Config(format!(
+            "not a directory: {}",
+            root.display()
+        )));
+    }
+    let root = root.display().to_string();
+    let rename = args.flag("rename");
+
+    let mut store = read_store(IMGDIR_STORE)?;
+    let mut imgdir = ImgDir {
+        name: Path::new(&root)
+            .file_name()
+            .map(|n| n.display().to_string())
+            .unwrap_or_else(|| root.clone()),
+        root: root.clone(),
+        rename,
+        create_time: None,
+        write_xmp: false,
+        rename_rule: RenameRule::default(),
+        scan: ScanOptions::default(),
+        thresholds: SizeThresholds::default(),
+    };
+    imgdir.apply(dir_options(args, &imgdir)?);
+    imgdir.scan.check(Path::new(&imgdir.root))?;
+    store.insert(root.clone(), serde_json::to_value(&imgdir)?);
+    write_store(IMGDIR_STORE, &store)?;
+
+    let imgdirs = load_imgdirs(store.into_iter().collect());
+    let overlap = roots::overlap(&imgdirs, &root);
+    if let Some(parent) = overlap.parent.as_ref() {
+        eprintln!("{root} is nested in {parent}, its images now belong to {root}");
+    }
+    for child in overlap.children.iter() {
+        eprintln!("{root} contains {child}, images in {child} stay with {child}");
+    }
+
+    let server = server(args)?;
+    if server.is_none() {
+        eprintln!("apikey not found, only thumbnails will be generated");
+    }
+
+    api::index_imgdir(
+        root,
+        rename,
+        &imgdirs,
+        server.as_ref(),
+        &settings()?,
+        open_table().await?,
+    )
+    .await
+}
+
+/**
+ * 命令行中的 imgdir 设置，未传的保持 imgdir 当前的值
+ */
+fn dir_options(args: &Args, imgdir: &ImgDir) -> Result<ImgDirOptions, AppError> {
+    let mut rule = imgdir.rename_rule.clone();
+    let mut rule_changed = false;
+    if let Some(template) = args.options.get("rename-template") {
+        rule.template = template.clone();
+        rule_changed = true;
+    }
+    if args.options.contains_key("rename-max-len") {
+        rule.max_len = args.number("rename-max-len", rule.max_len)?;
+        rule_changed = true;
+    }
+    if let Some(case) = args.options.get("rename-case") {
+        rule.case = serde_json::from_value(Value::String(case.clone()))
+            .map_err(|_| AppError::Config(format!("invalid --rename-case: {case}")))?;
+        rule_changed = true;
+    }
+    if let Some(transliterate) = args.bool("transliterate")? {
+        rule.transliterate = transliterate;
+        rule_changed = true;
+    }
+
+    let mut scan = imgdir.scan.clone();
+    let mut scan_changed = false;
+    // 空的 glob 用于清空列表
+    if let Some(include) = args.lists.get("include") {
+        scan.include = include.iter().filter(|g| !g.is_empty()).cloned().collect();
+        scan_changed = true;
+    }
+    if let Some(exclude) = args.lists.get("exclude") {
+        scan.exclude = exclude.iter().filter(|g| !g.is_empty()).cloned().collect();
+        scan_changed = true;
+    }
+    if let Some(skip_hidden) = args.bool("skip-hidden")? {
+        scan.skip_hidden = skip_hidden;
+        scan_changed = true;
+    }
+    if let Some(follow_symlinks) = args.bool("follow-symlinks")? {
+        scan.follow_symlinks = follow_symlinks;
+        scan_changed = true;
+    }
+    if let Some(depth) = args.options.get("max-depth") {
+        scan.max_depth = match depth.as_str() {
+            "none" => None,
+            _ => Some(args.number("max-depth", 0)?),
+        };
+        scan_changed = true;
+    }
+
+    let mut thresholds = imgdir.thresholds.clone();
+    let mut thresholds_changed = false;
+    if args.options.contains_key("min-width") {
+        thresholds.min_width = args.number("min-width", 0)? as u32;
+        thresholds_changed = true;
+    }
+    if args.options.contains_key("min-height") {
+        thresholds.min_height = args.number("min-height", 0)? as u32;
+        thresholds_changed = true;
+    }
+    if args.options.contains_key("min-bytes") {
+        thresholds.min_bytes = args.number("min-bytes", 0)? as u64;
+        thresholds_changed = true;
+    }
+    if args.options.contains_key("max-bytes") {
+        thresholds.max_bytes = args.number("max-bytes", 0)? as u64;
+        thresholds_changed = true;
+    }
+    if let Some(skip_transparent) = args.bool("skip-transparent")? {
+        thresholds.skip_transparent = skip_transparent;
+        thresholds_changed = true;
+    }
+
+    Ok(ImgDirOptions {
+        write_xmp: args.bool("write-xmp")?,
+        rename_rule: rule_changed.then_some(rule),
+        scan: scan_changed.then_some(scan),
+        thresholds: thresholds_changed.then_some(thresholds),
+    })
+}
+
+fn set_dir(args: &Args) -> Result<(), AppError> {
+    let root = args.positional(1, "root")?.to_string();
+
+    let mut store = read_store(IMGDIR_STORE)?;
+    let Some(v) = store.get(&root) else {
+        return Err(AppError::Config(format!("imgdir not found: {root}")));
+    };
+    let mut imgdir: ImgDir = serde_json::from_value(v.clone())?;
+    imgdir.apply(dir_options(args, &imgdir)?);
+    imgdir.scan.check(Path::new(&imgdir.root))?;
+    store.insert(root, serde_json::to_value(&imgdir)?);
+    write_store(IMGDIR_STORE, &store)?;
+
+    println!("{}", serde_json::to_string_pretty(&imgdir)?);
+    Ok(())
+}
+
+async fn relocate_dir(args: &Args) -> Result<(), AppError> {
+    let old = args.positional(1, "old")?.to_string();
+    let new = std::path::absolute(args.positional(2, "new")?)?;
+    if !new.is_dir() {
+        return Err(AppError::Config(format!(
+            "not a directory: {}",
+            new.display()
+        )));
+    }
+    let new = new.display().to_string();
+
+    let mut store = read_store(IMGDIR_STORE)?;
+    let Some(v) = store.remove(&old) else {
+        return Err(AppError::Config(format!("imgdir not found: {old}")));
+    };
+    if store.contains_key(&new) {
+        return Err(AppError::Config(format!("imgdir already exists: {new}")));
+    }
+    let mut imgdir: ImgDir = serde_json::from_value(v)?;
+    imgdir.root = new.clone();
+    store.insert(new.clone(), serde_json::to_value(&imgdir)?);
+
+    let imgdirs = load_imgdirs(store.clone().into_iter().collect());
+    api::relocate_root(&old, &new, &imgdirs, open_table().await?).await?;
+    write_store(IMGDIR_STORE, &store)
+}
+
+async fn reindex(args: &Args) -> Result<(), AppError> {
+    let imgdirs: ImgDirs = load_imgdirs(read_store(IMGDIR_STORE)?.into_iter().collect());
+    let server = require_server(args)?;
+    let settings = settings()?;
+    let table = open_table().await?;
+
+    match args.positional.get(1) {
+        Some(root) => {
+            let imgdir = imgdirs
+                .get(root)
+                .ok_or_else(|| AppError::Config(format!("imgdir not found: {root}")))?;
+
+            api::index_imgdir(
+                imgdir.root.clone(),
+                imgdir.rename,
+                &imgdirs,
+                Some(&server),
+                &settings,
+                table,
+            )
+            .await
+        }
+        None => {
+            api::on_start_up(table.clone(), &imgdirs, Some(&server), &settings).await?;
+            api::retry_failed(None, &imgdirs, &server, &settings, table).await
+        }
+    }
+}
+
+async fn export(args: &Args) -> Result<(), AppError> {
+    let r = idx::get_all(read_table().await?, None, None).await?;
+
+    let mut out: Box<dyn Write> = match args.options.get("out") {
+        Some(p) => Box::new(std::fs::File::create(p).map_err(|e| AppError::io(e, Path::new(p)))?),
+        None => Box::new(std::io::stdout().lock()),
+    };
+
+    for item in r.iter() {
+        serde_json::to_writer(&mut out, item)?;
+        out.write_all(b"\n")?;
+    }
+    out.flush()?;
+
+    Ok(())
+}
+
+fn print_results(r: &[idx::ImgSearchResult]) {
+    for item in r.iter() {
+        let score = item.score.map(|s| format!("{s:.4}")).unwrap_or_default();
+        println!(
+            "{score}\t{}\t{}",
+            item.path,
+            item.desc.as_deref().unwrap_or_default()
+        );
+    }
+}
+
+/**
+ * 持有独占锁的命令使用，打开时会迁移旧版本数据库并补建索引
+ */
+async fn open_table() -> Result<Arc<Table>, AppError> {
+    // 完整扫描只在 GUI 异常退出后的启动时进行
+    let (table, report) = db::init_db(false).await?;
+    warn_integrity(report);
+    Ok(Arc::new(table))
+}
+
+/**
+ * 只持有共享锁的命令使用，不写入数据库
+ */
+async fn read_table() -> Result<Arc<Table>, AppError> {
+    let (table, report) = db::open_db_read_only().await?;
+    warn_integrity(report);
+    Ok(Arc::new(table))
+}
+
+fn warn_integrity(report: db::IntegrityReport) {
+    if !report.ok {
+        eprintln!(
+            "warning: database integrity check failed, {}. open the app to roll back, or run restore-backup",
+            report.error.unwrap_or_default()
+        );
+    }
+}
+
+/**
+ * 优先使用 --apikey、IMGSEARCH_APIKEY，其次是 GUI 中设置的 apikey
+ */
+fn server(args: &Args) -> Result<Option<ImgseachServer>, AppError> {
+    let apikey = match args.options.get("apikey") {
+        Some(apikey) => Some(apikey.clone()),
+        None => match std::env::var("IMGSEARCH_APIKEY") {
+            Ok(apikey) => Some(apikey),
+            Err(_) => read_store(AUTH_STORE)?
+                .get("apikey")
+                .and_then(|v| v.as_str())
+                .map(|s| s.to_string()),
+        },
+    };
+
+    Ok(apikey.map(new_server))
+}
+
+fn require_server(args: &Args) -> Result<ImgseachServer, AppError> {
+    server(args)?.ok_or(AppError::ServerNotReady)
+}
+
+fn settings() -> Result<IndexSettings, AppError> {
+    match read_store(SETTINGS_STORE)?.remove("index") {
+        Some(v) => Ok(serde_json::from_value(v)?),
+        None => Ok(IndexSettings::default()),
+    }
+}
+
+fn maintenance_settings() -> Result<MaintenanceSettings, AppError> {
+    match read_store(SETTINGS_STORE)?.remove("maintenance") {
+        Some(v) => Ok(serde_json::from_value(v)?),
+        None => Ok(MaintenanceSettings::default()),
+    }
+}
+
+fn backup_settings() -> Result<BackupSettings, AppError> {
+    match read_store(SETTINGS_STORE)?.remove("backup") {
+        Some(v) => Ok(serde_json::from_value(v)?),
+        None => Ok(BackupSettings::default()),
+    }
+}
+
+/**
+ * GUI 通过 tauri-plugin-store 保存在应用数据目录下的 json 文件
+ */
+fn store_path(name: &str) -> Result<PathBuf, AppError> {
+    let dir = dirs::data_dir()
+        .ok_or_else(|| AppError::Config("data dir not found".to_string()))?
+        .join(APP_IDENTIFIER);
+    Ok(dir.join(name))
+}
+
+fn read_store(name: &str) -> Result<Map<String, Value>, AppError> {
+    let p = store_path(name)?;
+    if !p.exists() {
+        return Ok(Map::new());
+    }
+
+    let bs = std::fs::read(&p).map_err(|e| AppError::io(e, &p))?;
+    Ok(serde_json::from_slice(&bs)?)
+}
+
+fn write_store(name: &str, store: &Map<String, Value>) -> Result<(), AppError> {
+    let p = store_path(name)?;
+    if let Some(parent) = p.parent() {
+        std::fs::create_dir_all(parent).map_err(|e| AppError::io(e, parent))?;
+    }
+
+    std::fs::write(&p, serde_json::to_vec_pretty(store)?).map_err(|e| AppError::io(e, &p))?;
+    Ok(())
+}
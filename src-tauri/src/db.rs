@@ -1,15 +1,227 @@
-use lancedb::Table;
+use futures::TryStreamExt;
+use itertools::Itertools;
+use lancedb::{
+    query::{ExecutableQuery, QueryBase, Select},
+    Connection, Table,
+};
+use serde::Serialize;
 
-use crate::{error::AppError, image_command::get_table, path_utils};
+use crate::{
+    error::AppError,
+    image_command::{idx, meta, volume},
+    path_utils,
+};
 
-// 初始化 LanceDB，只调用一次
-pub async fn init_db() -> Result<Table, AppError> {
+/**
+ * 启动时的完整性检查结果
+ */
+#[derive(Serialize, Clone, Debug, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct IntegrityReport {
+    pub ok: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    // 检查失败时找到的最新可读版本，table 已切换到该版本（只读），确认后才回滚
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rollback_version: Option<u64>,
+}
+
+impl IntegrityReport {
+    /**
+     * 其他表无法打开时标记为失败，只能从快照恢复
+     */
+    pub fn degrade(&mut self, error: &AppError) {
+        self.ok = false;
+        self.rollback_version = None;
+        let error = error.to_string();
+        self.error = Some(match self.error.take() {
+            Some(e) => format!("{e}; {error}"),
+            None => error,
+        });
+    }
+}
+
+async fn connect() -> Result<Connection, AppError> {
     let db_path = path_utils::lancedb_dir()?;
-    let db_path = db_path.to_str().unwrap();
+    let db_path = db_path
+        .to_str()
+        .ok_or_else(|| AppError::Config(format!("invalid db path: {}", db_path.display())))?;
 
-    let db = lancedb::connect(db_path).execute().await?;
+    Ok(lancedb::connect(db_path).execute().await?)
+}
 
-    let table = get_table(&db).await?;
+// 初始化 LanceDB，只调用一次
+// full_check: 上次未正常退出时扫描全部数据，否则只检查 schema 与记录数
+pub async fn init_db(full_check: bool) -> Result<(Table, IntegrityReport), AppError> {
+    let db = connect().await?;
+
+    let table = idx::open_table(&db).await?;
+
+    let report = check_integrity(&table, full_check).await?;
 
+    // 检查失败时不做迁移与建索引，避免在损坏的版本上继续写入
+    if report.ok {
+        idx::prepare(&table).await?;
+    }
+
+    Ok((table, report))
+}
+
+/**
+ * 只读打开，不迁移、不建索引，用于命令行中只持有共享锁的命令，GUI 可能同时在写入
+ */
+pub async fn open_db_read_only() -> Result<(Table, IntegrityReport), AppError> {
+    let db = connect().await?;
+
+    let table = idx::open_existing_table(&db).await?;
+    if !idx::is_migrated(&table).await? {
+        return Err(AppError::Config(
+            "the database was created by an older version, open the app or run reindex to upgrade it"
+                .to_string(),
+        ));
+    }
+
+    let report = check_integrity(&table, false).await?;
+    Ok((table, report))
+}
+
+/**
+ * 数据库无法打开时，使用临时目录中的空表启动，由用户从快照恢复
+ * 表固定在创建时的版本，所有写入都会失败，避免写入的数据在重启后丢失
+ */
+async fn connect_degraded() -> Result<Connection, AppError> {
+    let dir = std::env::temp_dir().join(format!("imgsearch-degraded-{}", std::process::id()));
+    let dir = dir
+        .to_str()
+        .ok_or_else(|| AppError::Config(format!("invalid db path: {}", dir.display())))?;
+    Ok(lancedb::connect(dir).execute().await?)
+}
+
+async fn read_only(table: Table) -> Result<Table, AppError> {
+    let v = table.version().await?;
+    table.checkout(v).await?;
     Ok(table)
 }
+
+pub async fn init_degraded_db(error: &AppError) -> Result<(Table, IntegrityReport), AppError> {
+    let db = connect_degraded().await?;
+    let table = read_only(idx::open_table(&db).await?).await?;
+
+    Ok((
+        table,
+        IntegrityReport {
+            ok: false,
+            error: Some(error.to_string()),
+            rollback_version: None,
+        },
+    ))
+}
+
+/**
+ * 标签、相册与收藏所在的表
+ */
+pub async fn init_meta_db() -> Result<Table, AppError> {
+    let db = connect().await?;
+    meta::get_table(&db).await
+}
+
+pub async fn init_degraded_meta_db() -> Result<Table, AppError> {
+    let db = connect_degraded().await?;
+    read_only(meta::get_table(&db).await?).await
+}
+
+/**
+ * imgdir 所在卷的状态
+ */
+pub async fn init_root_db() -> Result<Table, AppError> {
+    let db = connect().await?;
+    volume::get_table(&db).await
+}
+
+pub async fn init_degraded_root_db() -> Result<Table, AppError> {
+    let db = connect_degraded().await?;
+    read_only(volume::get_table(&db).await?).await
+}
+
+/**
+ * 检查 schema 与记录数，full 时读取所有分片的 id 列，与记录数比对
+ */
+async fn verify(table: &Table, full: bool) -> Result<(), AppError> {
+    let schema = table.schema().await?;
+    if schema.field_with_name("id").is_err() {
+        return Err(AppError::Db("Missing column: id".to_string()));
+    }
+    let count = table.count_rows(None).await?;
+    if !full {
+        return Ok(());
+    }
+
+    let scanned = table
+        .query()
+        .select(Select::columns(&["id"]))
+        .execute()
+        .await?
+        .try_fold(0, |n, b| async move { Ok(n + b.num_rows()) })
+        .await?;
+
+    if count != scanned {
+        return Err(AppError::Db(format!(
+            "row count mismatch, expect {count}, scanned {scanned}"
+        )));
+    }
+    Ok(())
+}
+
+async fn check_integrity(table: &Table, full: bool) -> Result<IntegrityReport, AppError> {
+    let error = match verify(table, full).await {
+        Ok(()) => {
+            return Ok(IntegrityReport {
+                ok: true,
+                ..Default::default()
+            })
+        }
+        Err(e) => e.to_string(),
+    };
+    log::error!("img_idx integrity check failed, {error}");
+
+    let current = table.version().await?;
+    let versions = table
+        .list_versions()
+        .await?
+        .into_iter()
+        .map(|v| v.version)
+        .filter(|v| *v < current)
+        .sorted_unstable_by(|a, b| b.cmp(a));
+
+    for v in versions {
+        table.checkout(v).await?;
+        if verify(table, full).await.is_ok() {
+            log::warn!("img_idx version {v} is readable, waiting for rollback");
+            return Ok(IntegrityReport {
+                ok: false,
+                error: Some(error),
+                rollback_version: Some(v),
+            });
+        }
+    }
+
+    table.checkout_latest().await?;
+    Ok(IntegrityReport {
+        ok: false,
+        error: Some(error),
+        rollback_version: None,
+    })
+}
+
+/**
+ * 将已切换到的可读版本提交为最新版本
+ */
+pub async fn rollback(table: &Table) -> Result<u64, AppError> {
+    table.restore().await?;
+    let v = table.version().await?;
+    log::info!("img_idx rolled back, new version {v}");
+
+    // 启动时跳过的迁移
+    idx::prepare(table).await?;
+    Ok(v)
+}
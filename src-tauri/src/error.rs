@@ -1,37 +1,155 @@
 use std::sync::PoisonError;
 
 use lancedb::arrow::arrow_schema::ArrowError;
-use tauri::ipc::InvokeError;
+use serde::{ser::SerializeStruct, Serialize, Serializer};
 use tauri_plugin_http::reqwest;
 
 #[derive(thiserror::Error, Debug)]
 pub enum AppError {
     #[error("Network error: connect to host:{0} failed")]
     Network(String),
+    #[error("Http error: status {status}, {message}")]
+    Http { status: u16, message: String },
+    #[error("rate limited, retry after {retry_after:?} seconds")]
+    RateLimited { retry_after: Option<u64> },
     #[error("Internal error: {0}")]
     Internal(String),
+    #[error("Database error: {0}")]
+    Db(String),
+    #[error("IO error: {message}")]
+    Io {
+        message: String,
+        path: Option<String>,
+    },
+    #[error("image error: {0}")]
+    Image(String),
+    #[error("Config error: {0}")]
+    Config(String),
+    #[error("Locked: {0}")]
+    Locked(String),
     #[error("Authentication error: {0}")]
     Auth(String),
+    #[error("server not ready")]
+    ServerNotReady,
     #[error("rights limit error: {0}")]
     RightsLimit(String),
     #[error("image format error: {0}")]
     ImgFormat(String),
 }
 
+/**
+ * 错误的附加信息，前端据此决定展示方式
+ */
+#[derive(Serialize, Default, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ErrorDetails {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub status: Option<u16>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub retry_after: Option<u64>,
+}
+
+impl AppError {
+    /**
+     * 稳定的错误码，供前端判断错误类型
+     */
+    pub fn code(&self) -> &'static str {
+        match self {
+            AppError::Network(_) => "network",
+            AppError::Http { .. } => "http",
+            AppError::RateLimited { .. } => "rate_limited",
+            AppError::Internal(_) => "internal",
+            AppError::Db(_) => "db",
+            AppError::Io { .. } => "io",
+            AppError::Image(_) => "image",
+            AppError::Config(_) => "config",
+            AppError::Locked(_) => "locked",
+            AppError::Auth(_) => "auth",
+            AppError::ServerNotReady => "server_not_ready",
+            AppError::RightsLimit(_) => "rights_limit",
+            AppError::ImgFormat(_) => "img_format",
+        }
+    }
+
+    pub fn details(&self) -> ErrorDetails {
+        match self {
+            AppError::Http { status, .. } => ErrorDetails {
+                status: Some(*status),
+                ..Default::default()
+            },
+            AppError::RateLimited { retry_after } => ErrorDetails {
+                status: Some(429),
+                retry_after: *retry_after,
+                ..Default::default()
+            },
+            AppError::Io { path, .. } => ErrorDetails {
+                path: path.clone(),
+                ..Default::default()
+            },
+            _ => ErrorDetails::default(),
+        }
+    }
+
+    pub fn io(err: std::io::Error, path: &std::path::Path) -> Self {
+        AppError::Io {
+            message: format!("{err}"),
+            path: Some(path.display().to_string()),
+        }
+    }
+
+    /**
+     * 鉴权、额度、网络错误与具体图片无关，需要中止整个任务
+     */
+    pub fn is_fatal(&self) -> bool {
+        matches!(
+            self,
+            AppError::Network(_)
+                | AppError::Auth(_)
+                | AppError::ServerNotReady
+                | AppError::RightsLimit(_)
+                | AppError::RateLimited { .. }
+        )
+    }
+}
+
+/**
+ * 序列化为 { code, message, details }，tauri 命令返回给前端
+ */
+impl Serialize for AppError {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let mut s = serializer.serialize_struct("AppError", 3)?;
+        s.serialize_field("code", self.code())?;
+        s.serialize_field("message", &self.to_string())?;
+        s.serialize_field("details", &self.details())?;
+        s.end()
+    }
+}
+
 impl From<tauri_plugin_store::Error> for AppError {
     fn from(e: tauri_plugin_store::Error) -> Self {
-        AppError::Internal(format!("tauri_plugin_store::Error: {e}"))
+        AppError::Config(format!("tauri_plugin_store::Error: {e}"))
     }
 }
-impl From<AppError> for InvokeError {
-    fn from(e: AppError) -> InvokeError {
-        InvokeError::from_error(Box::new(e))
+
+impl From<tauri::Error> for AppError {
+    fn from(e: tauri::Error) -> Self {
+        AppError::Internal(format!("tauri::Error: {e}"))
     }
 }
 
 impl From<reqwest::Error> for AppError {
     fn from(err: reqwest::Error) -> Self {
-        if let Some(url) = err.url() {
+        if let Some(status) = err.status() {
+            AppError::Http {
+                status: status.as_u16(),
+                message: format!("{err}"),
+            }
+        } else if let Some(url) = err.url() {
             AppError::Network(url.to_string())
         } else {
             AppError::Internal(format!("{err}"))
@@ -47,36 +165,53 @@ impl<T> From<PoisonError<T>> for AppError {
 
 impl From<std::io::Error> for AppError {
     fn from(err: std::io::Error) -> Self {
-        AppError::Internal(format!("{err}"))
+        AppError::Io {
+            message: format!("{err}"),
+            path: None,
+        }
     }
 }
 
 impl From<serde_json::Error> for AppError {
     fn from(err: serde_json::Error) -> Self {
-        AppError::Internal(format!("{err}"))
+        AppError::Config(format!("{err}"))
     }
 }
 
 impl From<image::ImageError> for AppError {
     fn from(err: image::ImageError) -> Self {
-        AppError::Internal(format!("{err}"))
+        match err {
+            image::ImageError::Decoding(_) | image::ImageError::Unsupported(_) => {
+                AppError::ImgFormat(format!("{err}"))
+            }
+            _ => AppError::Image(format!("{err}")),
+        }
     }
 }
 
 impl From<fast_image_resize::ResizeError> for AppError {
     fn from(err: fast_image_resize::ResizeError) -> Self {
-        AppError::Internal(format!("{err}"))
+        AppError::Image(format!("{err}"))
     }
 }
 
 impl From<lancedb::Error> for AppError {
     fn from(err: lancedb::Error) -> Self {
-        AppError::Internal(format!("{err}"))
+        AppError::Db(format!("{err}"))
     }
 }
 
 impl From<ArrowError> for AppError {
     fn from(err: ArrowError) -> Self {
-        AppError::Internal(format!("{err}"))
+        AppError::Db(format!("{err}"))
+    }
+}
+
+impl From<parquet::errors::ParquetError> for AppError {
+    fn from(err: parquet::errors::ParquetError) -> Self {
+        AppError::Io {
+            message: format!("{err}"),
+            path: None,
+        }
     }
 }
@@ -1,104 +1,136 @@
 use std::{
-    path::Path,
+    path::{Path, PathBuf},
     sync::{Arc, OnceLock},
-    time::Duration,
+    time::{Duration, Instant},
 };
 
+use futures::{channel::mpsc, future, stream, SinkExt, Stream, StreamExt, TryStreamExt};
 use itertools::{multizip, Itertools};
 use lancedb::Table;
 use moka::future::Cache;
-use serde::Deserialize;
-use tauri::Wry;
-use tauri_plugin_store::Store;
+use serde::Serialize;
 
 use crate::{
     error::AppError,
-    image_command::{idx, utils, RenameModel, SearchModel},
-    path_utils,
+    image_command::{
+        idx, journal, naming::RenameRule, roots, utils, volume, ImgDir, ImgDirs, RenameModel,
+        SearchModel,
+    },
+    path_utils::{self, ScanOptions},
     server::{imgsearch_server::ImgseachServer, ImageIndexResp, ImageIndexer},
+    settings_command::IndexSettings,
 };
 
-use idx::{update_path, update_path_prefix, ImgSearchResult, IndexModel};
-use utils::gen_thumbnail;
-
-#[warn(dead_code)]
-#[derive(Deserialize)]
-struct ImgDir {
-    name: String,
-    root: String,
-    #[serde(alias = "enableRename")]
-    rename: bool,
-}
+use idx::{
+    update_path, update_path_prefix, ImgSearchResult, IndexModel, STATUS_FAILED, STATUS_INDEXED,
+    STATUS_PENDING, STATUS_SKIPPED,
+};
+use utils::{gen_thumbnail, SizeThresholds};
 
-pub async fn on_start_up(table: Arc<Table>, imgdir_store: Arc<Store<Wry>>) -> Result<(), AppError> {
+pub async fn on_start_up(
+    table: Arc<Table>,
+    imgdirs: &ImgDirs,
+    server: Option<&ImgseachServer>,
+    settings: &IndexSettings,
+) -> Result<(), AppError> {
     let cache = get_indexing_paths();
 
-    let all: Vec<ImgSearchResult> = idx::get_all(table.clone(), Some(false)).await?;
+    let all: Vec<ImgSearchResult> = idx::get_by_status(table.clone(), STATUS_PENDING).await?;
     for p in all.iter() {
         cache.insert(p.path.clone(), None).await;
     }
     let r = all.into_iter().into_group_map_by(|r| r.root.clone());
 
     for (root, paths) in r.into_iter() {
-        let imgdir_store = imgdir_store.clone();
-        let r = imgdir_store.get(&root);
-
-        if r.is_none() {
+        let Some(imgdir) = imgdirs.get(&root) else {
             // 说明有残余的图片索引
             log::warn!("root={} not found in imgdir_store", &root);
-            idx::remove_path_like(table.clone(), &root).await?;
+            idx::remove_by_root(table.clone(), &root).await?;
             continue;
-        }
+        };
 
-        let imgdir = serde_json::from_value::<ImgDir>(r.unwrap().take())?;
+        // 卷未连接时保留记录，重新连接后由 resume_root 继续
+        let Some(server) = server.filter(|_| volume::is_available(&root)) else {
+            for p in paths.iter() {
+                cache.invalidate(p.path.as_str()).await;
+            }
+            continue;
+        };
 
-        let mut r = Vec::new(); // (id, path, thumbnail, root)
-        for chunks in paths.chunks(5) {
-            let ipt = &mut chunks
-                .iter()
-                .map(|c| {
-                    (
-                        c.id.clone(),
-                        c.path.clone(),
-                        c.thumbnail.clone(),
-                        c.root.clone(),
-                    )
-                })
-                .collect::<Vec<_>>();
+        upload_pending(&paths, imgdir, server, settings, table.clone()).await?;
+    }
 
-            r.append(ipt);
-        }
+    Ok(())
+}
 
-        loop {
-            r = r
-                .into_iter()
-                .filter(|p| cache.contains_key(p.1.as_str()))
-                .collect::<Vec<_>>();
+/**
+ * 上传已有缩略图的图片
+ */
+async fn upload_pending(
+    paths: &[ImgSearchResult],
+    imgdir: &ImgDir,
+    server: &ImgseachServer,
+    settings: &IndexSettings,
+    img_idx_tbl: Arc<Table>,
+) -> Result<(), AppError> {
+    let cache = get_indexing_paths();
+    for p in paths.iter() {
+        cache.insert(p.path.clone(), None).await;
+    }
 
-            if r.is_empty() {
-                break;
-            }
+    let ipts = paths
+        .chunks(settings.batch_size(Some(server)))
+        .map(|chunk| {
+            chunk
+                .iter()
+                .map(|c| (c.id.clone(), c.path.clone(), c.thumbnail.clone()))
+                .collect::<Vec<_>>()
+        })
+        .collect::<Vec<_>>();
 
-            let chunk = if r.len() >= 5 {
-                r.drain(0..5)
-            } else {
-                r.drain(0..)
-            };
+    upload_concurrently(
+        stream::iter(ipts).map(Ok),
+        imgdir.rename.then_some(&imgdir.rename_rule),
+        server,
+        settings,
+        img_idx_tbl,
+    )
+    .await
+}
 
-            let ipt = chunk.map(|c| (c.0, c.1, c.2)).collect::<Vec<_>>();
+/**
+ * 卷重新连接后继续索引该 root 中未完成与失败的图片
+ */
+pub async fn resume_root(
+    root: &str,
+    imgdirs: &ImgDirs,
+    server: Option<&ImgseachServer>,
+    settings: &IndexSettings,
+    img_idx_tbl: Arc<Table>,
+) -> Result<(), AppError> {
+    let (Some(imgdir), Some(server)) = (imgdirs.get(root), server) else {
+        return Ok(());
+    };
 
-            index_images(&ipt, imgdir.rename, state.clone()).await?;
+    let filter = |status: &str| {
+        Some(format!(
+            "root = {} and status = {}",
+            idx::sql_str(root),
+            idx::sql_str(status)
+        ))
+    };
 
-            for p in ipt.iter() {
-                cache.invalidate(p.1.as_str()).await;
-            }
-        }
+    let pending = idx::get_all(img_idx_tbl.clone(), None, filter(STATUS_PENDING)).await?;
+    log::info!("resume root {root}, {} pending images", pending.len());
+    upload_pending(&pending, imgdir, server, settings, img_idx_tbl.clone()).await?;
 
-        if !r.is_empty() {
-            for p in r.iter() {
-                cache.invalidate(p.1.as_str()).await;
-            }
-        }
+    let failed = idx::get_all(img_idx_tbl.clone(), None, filter(STATUS_FAILED))
+        .await?
+        .into_iter()
+        .map(|r| r.id)
+        .collect::<Vec<_>>();
+    if !failed.is_empty() {
+        retry_failed(Some(failed), imgdirs, server, settings, img_idx_tbl).await?;
     }
 
     Ok(())
@@ -119,33 +151,305 @@ fn get_indexing_paths() -> &'static Cache<String, Option<String>> {
 
 async fn save_empty_image(
     root: &str,
-    paths: Vec<&Path>,
+    thumbnails: Vec<(PathBuf, String, PathBuf)>, // path, sign, thumbnail
+    img_idx_tbl: Arc<Table>,
+) -> Result<Vec<(String, String, String)>, AppError> {
+    if thumbnails.is_empty() {
+        return Ok(vec![]);
+    }
+
+    let idxes = thumbnails
+        .iter()
+        .map(|(p, sign, t)| idx::ImgIdx::new_empty(p, root.to_string(), sign.clone(), t))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    // 重新索引的图片沿用原来的 id
+    let ids = idx::save_batch(img_idx_tbl, idxes).await?;
+
+    Ok(multizip((ids, &thumbnails))
+        .map(|(id, (p, _, t))| (id, p.display().to_string(), t.display().to_string()))
+        .collect::<Vec<_>>())
+}
+
+/**
+ * 在阻塞线程池中生成缩略图（最多 workers 个同时进行），每凑够一批就写入空索引并发送给上传方，
+ * 使缩略图生成与上传并行
+ */
+fn spawn_thumbnail_pipeline(
+    root: String,
+    paths: Vec<PathBuf>,
+    thresholds: SizeThresholds,
+    workers: usize,
+    batch_size: usize,
+    buffer: usize,
+    img_idx_tbl: Arc<Table>,
+) -> mpsc::Receiver<Result<Vec<(String, String, String)>, AppError>> {
+    let (mut tx, rx) = mpsc::channel(buffer);
+
+    tauri::async_runtime::spawn(async move {
+        let cache = get_indexing_paths();
+        let root = Arc::new(root);
+        let thresholds = Arc::new(thresholds);
+
+        let thumbnails = stream::iter(paths)
+            .filter(|p| future::ready(cache.contains_key(p.display().to_string().as_str())))
+            .map(|p| {
+                let root = root.clone();
+                let thresholds = thresholds.clone();
+                tauri::async_runtime::spawn_blocking(move || {
+                    let r = match thresholds.check(&p) {
+                        Ok(Some(reason)) => Ok(Thumbnail::Skipped(reason)),
+                        Ok(None) => gen_thumbnail(&root, &p).map(|(s, t)| Thumbnail::Created(s, t)),
+                        Err(e) => Err(e),
+                    };
+                    (p, r)
+                })
+            })
+            .buffered(workers)
+            .chunks(batch_size);
+
+        futures::pin_mut!(thumbnails);
+        while let Some(chunk) = thumbnails.next().await {
+            let r = save_thumbnails(&root, chunk, img_idx_tbl.clone()).await;
+            let failed = r.is_err();
+
+            if tx.send(r).await.is_err() || failed {
+                break;
+            }
+        }
+    });
+
+    rx
+}
+
+enum Thumbnail {
+    // sign, thumbnail
+    Created(String, PathBuf),
+    // 跳过的原因
+    Skipped(String),
+}
+
+/**
+ * 单张图片失败或被跳过时记录原因，不影响同批次的其他图片
+ */
+async fn save_thumbnails(
+    root: &str,
+    chunk: Vec<Result<(PathBuf, Result<Thumbnail, AppError>), tauri::Error>>,
     img_idx_tbl: Arc<Table>,
 ) -> Result<Vec<(String, String, String)>, AppError> {
-    let mut thumbnails = Vec::with_capacity(paths.len());
-    let mut signs = Vec::with_capacity(paths.len());
+    let cache = get_indexing_paths();
 
-    for path in paths.iter() {
-        let (sign, thumbnail_path) = gen_thumbnail(root, path)?;
-        signs.push(sign);
-        thumbnails.push(thumbnail_path);
+    let mut thumbnails = Vec::with_capacity(chunk.len());
+    let mut failed = Vec::new();
+    for r in chunk.into_iter() {
+        match r {
+            Ok((path, Ok(Thumbnail::Created(sign, thumbnail_path)))) => {
+                thumbnails.push((path, sign, thumbnail_path))
+            }
+            Ok((path, Ok(Thumbnail::Skipped(reason)))) => {
+                log::info!("skip image, path = {}, {reason}", path.display());
+                cache.invalidate(path.display().to_string().as_str()).await;
+
+                match idx::ImgIdx::new_skipped(&path, root.to_string(), reason) {
+                    Ok(f) => failed.push(f),
+                    Err(e) => log::error!("{e}"),
+                }
+            }
+            Ok((path, Err(e))) => {
+                log::warn!("gen thumbnail failed, path = {}, {e}", path.display());
+                cache.invalidate(path.display().to_string().as_str()).await;
+
+                match idx::ImgIdx::new_failed(&path, root.to_string(), e.to_string()) {
+                    Ok(f) => failed.push(f),
+                    Err(e) => log::error!("{e}"),
+                }
+            }
+            Err(e) => log::error!("thumbnail worker error: {e}"),
+        }
     }
 
-    let idxes = multizip((&paths, signs, &thumbnails))
-        .map(|(p, sign, t)| idx::ImgIdx::new_empty(p, root.to_string(), sign, t.as_path()))
-        .collect::<Vec<_>>();
+    if !failed.is_empty() {
+        idx::save_batch(img_idx_tbl.clone(), failed).await?;
+    }
 
-    let ids = idxes.iter().map(|i| i.id.clone()).collect::<Vec<_>>();
+    save_empty_image(root, thumbnails, img_idx_tbl).await
+}
 
-    idx::save_batch(img_idx_tbl, idxes).await?;
+/**
+ * 生成缩略图并写入空索引，server 可用时上传建立索引
+ */
+async fn index_paths(
+    root: String,
+    paths: Vec<PathBuf>,
+    thresholds: &SizeThresholds,
+    rename: Option<&RenameRule>,
+    server: Option<&ImgseachServer>,
+    settings: &IndexSettings,
+    img_idx_tbl: Arc<Table>,
+) -> Result<(), AppError> {
+    let cache = get_indexing_paths();
+    for p in paths.iter() {
+        cache.insert(p.display().to_string(), None).await;
+    }
 
-    Ok(multizip((ids, &paths, &thumbnails))
-        .map(|(id, p, t)| (id, p.display().to_string(), t.display().to_string()))
-        .collect::<Vec<_>>())
+    let started = Instant::now();
+    let total = paths.len();
+    let batch_size = settings.batch_size(server);
+
+    let rx = spawn_thumbnail_pipeline(
+        root,
+        paths,
+        thresholds.clone(),
+        settings.thumbnail_workers(),
+        batch_size,
+        settings.upload_concurrency(),
+        img_idx_tbl.clone(),
+    );
+
+    match server {
+        Some(server) => {
+            upload_concurrently(rx, rename, server, settings, img_idx_tbl.clone()).await?;
+        }
+        None => {
+            rx.try_for_each(|ipt| async move {
+                for p in ipt.iter() {
+                    cache.invalidate(p.1.as_str()).await;
+                }
+                Ok(())
+            })
+            .await?;
+        }
+    }
+
+    let elapsed = started.elapsed();
+    log::info!(
+        "index {total} images in {elapsed:?}, {:.2} images/s, thumbnail workers = {}, batch size = {batch_size}, upload concurrency = {}",
+        total as f64 / elapsed.as_secs_f64().max(0.001),
+        settings.thumbnail_workers(),
+        settings.upload_concurrency()
+    );
+
+    Ok(())
+}
+
+/**
+ * 最多同时 upload_concurrency 个批次在上传
+ */
+async fn upload_concurrently<S>(
+    ipts: S,
+    rename: Option<&RenameRule>,
+    server: &ImgseachServer,
+    settings: &IndexSettings,
+    img_idx_tbl: Arc<Table>,
+) -> Result<(), AppError>
+where
+    S: Stream<Item = Result<Vec<(String, String, String)>, AppError>>,
+{
+    let cache = get_indexing_paths();
+    let rename = RenameAction::new(rename, settings);
+
+    ipts.try_for_each_concurrent(settings.upload_concurrency(), |ipt| {
+        let img_idx_tbl = img_idx_tbl.clone();
+        async move {
+            index_chunked(&ipt, rename, server, img_idx_tbl).await?;
+
+            for p in ipt.iter() {
+                cache.invalidate(p.1.as_str()).await;
+            }
+            Ok(())
+        }
+    })
+    .await
+}
+
+/**
+ * 批次在生成时按当时的批大小切分，上传前按服务端当前接受的批大小重新拆分；
+ * 遇到 413 时服务端限制已调小，剩余图片按新的批大小继续上传
+ */
+async fn index_chunked(
+    ipt: &[(String, String, String)], // id, path, thumbnail
+    rename: RenameAction<'_>,
+    server: &ImgseachServer,
+    img_idx_tbl: Arc<Table>,
+) -> Result<(), AppError> {
+    let mut rest = ipt;
+    while !rest.is_empty() {
+        let size = server
+            .max_batch_size()
+            .map_or(rest.len(), |n| n.clamp(1, rest.len()));
+        let (chunk, tail) = rest.split_at(size);
+        if index_or_mark_failed(chunk, rename, server, img_idx_tbl.clone()).await? {
+            rest = tail;
+        }
+    }
+
+    Ok(())
+}
+
+/**
+ * 整批上传失败时逐张重试，定位并记录失败的图片，其他图片继续索引
+ * 鉴权、额度、网络错误直接返回；请求过大时返回 false，由调用方按新的批大小重新拆分
+ */
+async fn index_or_mark_failed(
+    ipt: &[(String, String, String)], // id, path, thumbnail
+    rename: RenameAction<'_>,
+    server: &ImgseachServer,
+    img_idx_tbl: Arc<Table>,
+) -> Result<bool, AppError> {
+    let e = match index_images(ipt, rename, server, img_idx_tbl.clone()).await {
+        Ok(()) => return Ok(true),
+        Err(e) if e.is_fatal() => return Err(e),
+        // 上传期间服务端限制被调小（413），整批按新的批大小重试，不逐张重试
+        Err(_) if server.max_batch_size().is_some_and(|n| n < ipt.len()) => return Ok(false),
+        Err(e) => e,
+    };
+
+    if ipt.len() == 1 {
+        log::warn!("index image failed, path = {}, {e}", ipt[0].1);
+        idx::mark_failed(img_idx_tbl, &[ipt[0].0.clone()], &e.to_string()).await?;
+        return Ok(true);
+    }
+
+    log::warn!("index batch failed, retry one by one, {e}");
+    for p in ipt.iter() {
+        match index_images(std::slice::from_ref(p), rename, server, img_idx_tbl.clone()).await {
+            Ok(()) => {}
+            Err(e) if e.is_fatal() => return Err(e),
+            Err(e) => {
+                log::warn!("index image failed, path = {}, {e}", p.1);
+                idx::mark_failed(img_idx_tbl.clone(), &[p.0.clone()], &e.to_string()).await?;
+            }
+        }
+    }
+
+    Ok(true)
 }
+
+/**
+ * 索引时如何处理服务端建议的文件名
+ */
+#[derive(Clone, Copy, Debug)]
+enum RenameAction<'a> {
+    Off,
+    // 按规则直接重命名磁盘上的文件
+    Apply(&'a RenameRule),
+    // 保存为待确认的重命名建议
+    Stage,
+}
+
+impl<'a> RenameAction<'a> {
+    fn new(rename: Option<&'a RenameRule>, settings: &IndexSettings) -> Self {
+        match (rename, settings.stage_renames) {
+            (None, _) => RenameAction::Off,
+            (Some(rule), false) => RenameAction::Apply(rule),
+            (Some(_), true) => RenameAction::Stage,
+        }
+    }
+}
+
 async fn index_images(
     ipt: &[(String, String, String)], // id, path, thumbnail
-    rename: bool,
+    rename: RenameAction<'_>,
     server: &ImgseachServer,
     img_idx_tbl: Arc<Table>,
 ) -> Result<(), AppError> {
@@ -162,75 +466,180 @@ async fn index_images(
 
     let thumbnails = ipt.iter().map(|(_, _, t)| Path::new(t)).collect::<Vec<_>>();
 
-    let r = server.indexes(thumbnails, rename).await?;
+    let r = server
+        .indexes(thumbnails, !matches!(rename, RenameAction::Off))
+        .await?;
+    if r.len() != ipt.len() {
+        return Err(AppError::Internal(format!(
+            "image_indexes response count mismatch, expect {}, got {}",
+            ipt.len(),
+            r.len()
+        )));
+    }
+
+    let batch = journal::new_batch();
+    let mut models = Vec::with_capacity(ipt.len());
+    // 重命名过的图片，(原路径, 新路径)
+    let mut moved = vec![];
+    for (p, ImageIndexResp { vec, desc, name }) in ipt.into_iter().zip(r.into_iter()) {
+        // 上传期间被删除的图片不再保存
+        let Some(renamed) = cache.get(&p.1).await else {
+            continue;
+        };
+        // 上传期间被用户重命名的图片，以新路径为准
+        let current = renamed.unwrap_or_else(|| p.1.clone());
+
+        let (path, suggested_name) = match (rename, name) {
+            (RenameAction::Apply(rule), Some(name)) => {
+                let current_path = Path::new(&current);
+                match path_utils::rename(current_path, rule.candidates(&name, current_path)) {
+                    Ok(new_path) => {
+                        let new_path = new_path.display().to_string();
+                        journal::record(&batch, &current, &new_path, journal::SOURCE_INDEX);
+                        (new_path, None)
+                    }
+                    Err(e) => {
+                        log::error!("rename error: {e}");
+                        (current, None)
+                    }
+                }
+            }
+            (RenameAction::Stage, Some(name)) => (current, Some(name)),
+            _ => (current, None),
+        };
 
-    let ipt = ipt
-        .iter()
-        .filter(|p| cache.contains_key(&p.1))
-        .collect::<Vec<_>>();
+        if path != p.1 {
+            moved.push((p.1.clone(), path));
+        }
 
-    if ipt.is_empty() {
+        models.push(IndexModel {
+            id: p.0.clone(),
+            desc,
+            img_vec: vec.clone(),
+            vec,
+            desc_source: idx::DESC_SOURCE_SERVER.to_string(),
+            suggested_name,
+        });
+    }
+
+    if models.is_empty() {
         return Ok(());
     }
 
-    let id_path = if rename {
-        let mut ps = Vec::with_capacity(ipt.len());
-
-        for (p, r) in ipt.iter().zip(r.iter()) {
-            let cc = cache.get(&p.0).await;
-            let old_path = if let Some(Some(ref pp)) = cc {
-                pp
-            } else {
-                &p.0
-            };
-
-            let new_path = if let Some(newname) = &r.name {
-                let new_path = path_utils::rename(Path::new(old_path), newname);
-                if let Ok(new_path) = new_path {
-                    new_path.display().to_string()
-                } else {
-                    log::error!("rename error: {}", new_path.err().unwrap());
-                    old_path.to_string()
-                }
-            } else {
-                p.1.clone()
-            };
+    let r = keep_user_desc(models, server, img_idx_tbl.clone()).await?;
+    idx::save_indexes(img_idx_tbl.clone(), r).await?;
+    for (old, new) in moved.iter() {
+        update_path(img_idx_tbl.clone(), old, new).await?;
+    }
 
-            ps.push((&p.0, new_path));
-        }
+    Ok(())
+}
 
-        ps
-    } else {
-        let mut ps = Vec::with_capacity(ipt.len());
-        for p in ipt.iter() {
-            let cc = cache.get(&p.0).await;
-            if let Some(Some(ref new_path)) = cc {
-                ps.push((&p.0, new_path.clone()));
-            } else {
-                ps.push((&p.0, p.1.to_string()));
+/**
+ * 用户编辑过描述的图片保留描述，需要混合时用新的图片向量重新混合
+ */
+async fn keep_user_desc(
+    models: Vec<IndexModel>,
+    server: &ImgseachServer,
+    img_idx_tbl: Arc<Table>,
+) -> Result<Vec<IndexModel>, AppError> {
+    let ids = models.iter().map(|m| idx::sql_str(&m.id)).join(",");
+    let filter = format!(
+        "id in ({ids}) and desc_source <> {}",
+        idx::sql_str(idx::DESC_SOURCE_SERVER)
+    );
+    let user = idx::get_records(img_idx_tbl, Some(filter)).await?;
+    if user.is_empty() {
+        return Ok(models);
+    }
+
+    let mut user = user
+        .into_iter()
+        .map(|r| (r.id, (r.desc, r.desc_source)))
+        .collect::<std::collections::HashMap<_, _>>();
+
+    let mut r = Vec::with_capacity(models.len());
+    for mut m in models.into_iter() {
+        if let Some((Some(desc), desc_source)) = user.remove(&m.id) {
+            if desc_source == idx::DESC_SOURCE_BLENDED {
+                let text_vec = server.text_vectorize(&desc).await?;
+                m.vec = blend(&m.img_vec, &text_vec);
             }
+            m.desc = desc;
+            m.desc_source = desc_source;
         }
-        ps
-    };
+        r.push(m);
+    }
+    Ok(r)
+}
 
-    let r = multizip((id_path, r.into_iter()))
-        .map(|(p, ImageIndexResp { vec, desc, .. })| {
-            let path = Path::new(&p.1);
-            let filename = path.file_name().unwrap().display().to_string();
-
-            IndexModel {
-                id: p.0.to_string(),
-                name: filename,
-                path: p.1.to_string(),
-                desc,
-                vec,
-            }
-        })
+// 混合时图片向量的权重，其余为描述向量
+const IMG_VEC_WEIGHT: f32 = 0.7;
+
+/**
+ * 按权重混合图片与描述向量并归一化
+ */
+fn blend(img_vec: &[f32], text_vec: &[f32]) -> Vec<f32> {
+    let v = img_vec
+        .iter()
+        .zip(text_vec.iter())
+        .map(|(i, t)| i * IMG_VEC_WEIGHT + t * (1.0 - IMG_VEC_WEIGHT))
         .collect::<Vec<_>>();
 
-    idx::save_indexes(img_idx_tbl.clone(), r).await?;
+    let norm = v.iter().map(|f| f * f).sum::<f32>().sqrt();
+    if norm > 0.0 {
+        v.into_iter().map(|f| f / norm).collect()
+    } else {
+        v
+    }
+}
 
-    Ok(())
+/**
+ * 用户编辑描述，之后重新索引不会覆盖
+ * reembed: 用描述向量与图片向量混合后的向量作为 embedding，使描述影响搜索结果
+ */
+pub async fn edit_description(
+    id: &str,
+    desc: &str,
+    reembed: bool,
+    server: Option<&ImgseachServer>,
+    img_idx_tbl: Arc<Table>,
+) -> Result<(), AppError> {
+    let filter = format!("id = {}", idx::sql_str(id));
+    let record = idx::get_records(img_idx_tbl.clone(), Some(filter))
+        .await?
+        .into_iter()
+        .next()
+        .ok_or_else(|| AppError::Config(format!("image not found: {id}")))?;
+
+    // 旧记录没有 img_embedding，未混合过的 embedding 即图片向量
+    let img_vec = match record.img_vec {
+        Some(v) => Some(v),
+        None if record.desc_source != idx::DESC_SOURCE_BLENDED => record.vec,
+        None => None,
+    };
+
+    let (desc_source, vecs) = match (reembed, img_vec) {
+        (true, Some(img_vec)) => {
+            let server = server.ok_or(AppError::ServerNotReady)?;
+            let text_vec = server.text_vectorize(desc).await?;
+            (
+                idx::DESC_SOURCE_BLENDED,
+                Some((blend(&img_vec, &text_vec), img_vec)),
+            )
+        }
+        (true, None) => {
+            return Err(AppError::Config(format!("image not indexed yet: {id}")));
+        }
+        // 取消混合时恢复图片向量
+        (false, Some(img_vec)) if record.desc_source == idx::DESC_SOURCE_BLENDED => {
+            (idx::DESC_SOURCE_USER, Some((img_vec.clone(), img_vec)))
+        }
+        (false, _) => (idx::DESC_SOURCE_USER, None),
+    };
+
+    log::info!("edit description of {id}, source: {desc_source}");
+    idx::update_desc(img_idx_tbl, id, desc, desc_source, vecs).await
 }
 
 pub async fn search(
@@ -238,75 +647,241 @@ pub async fn search(
     server: &ImgseachServer,
     img_idx_tbl: Arc<Table>,
 ) -> Result<Vec<idx::ImgSearchResult>, AppError> {
-    let r = server.text_vectorize(&model.keyword).await?;
-    let r = idx::search(img_idx_tbl, &r, model.top).await?;
+    search_filtered(model, server, img_idx_tbl, None).await
+}
+
+/**
+ * filter: img_idx 上的筛选条件，如标签、相册转换后的签名列表
+ */
+pub async fn search_filtered(
+    model: &SearchModel,
+    server: &ImgseachServer,
+    img_idx_tbl: Arc<Table>,
+    filter: Option<String>,
+) -> Result<Vec<idx::ImgSearchResult>, AppError> {
+    let v = server.text_vectorize(&model.keyword).await?;
+    let r = idx::search(
+        img_idx_tbl,
+        &v,
+        model.top,
+        model.nprobes,
+        model.refine_factor,
+        filter,
+    )
+    .await?;
     Ok(r)
 }
 
-pub async fn index_imgdir(
-    root: String,
-    rename: bool,
-    server: Option<&ImgseachServer>,
+/**
+ * 以图搜图，已索引的图片直接使用已有向量，否则上传临时缩略图获取向量
+ */
+pub async fn similar(
+    path: &Path,
+    top: usize,
+    server: &ImgseachServer,
     img_idx_tbl: Arc<Table>,
-) -> Result<(), AppError> {
-    let imgs = path_utils::find_all_images(Path::new(&root))?;
+) -> Result<Vec<idx::ImgSearchResult>, AppError> {
+    let v = match idx::get_embedding(img_idx_tbl.clone(), &path.display().to_string()).await? {
+        Some(v) => v,
+        None => {
+            let p = path.to_path_buf();
+            let thumbnail =
+                tauri::async_runtime::spawn_blocking(move || utils::gen_temp_thumbnail(&p))
+                    .await??;
+            let r = server.indexes(vec![thumbnail.as_path()], false).await;
+            if let Err(e) = std::fs::remove_file(&thumbnail) {
+                log::warn!("remove temp thumbnail error: {e}");
+            }
 
-    let cache = get_indexing_paths();
-    for p in imgs.iter() {
-        cache.insert(p.display().to_string(), None).await;
-    }
+            r?.into_iter()
+                .next()
+                .map(|r| r.vec)
+                .ok_or_else(|| AppError::Internal("empty image_indexes response".to_string()))?
+        }
+    };
 
-    let mut r: Vec<(String, String, String)> = Vec::new();
-    for chunks in imgs.chunks(5) {
-        let ipt = &mut (save_empty_image(
-            &root,
-            chunks.iter().map(|p| p.as_path()).collect(),
+    let r = idx::search(img_idx_tbl, &v, top, None, None, None).await?;
+    Ok(r)
+}
+
+#[derive(Serialize, Debug)]
+pub struct IndexStats {
+    pub total: usize,
+    pub indexed: usize,
+    pub pending: usize,
+    pub failed: usize,
+    pub skipped: usize,
+    // root -> count
+    pub roots: Vec<(String, usize)>,
+}
+
+pub async fn stats(imgdirs: &ImgDirs, img_idx_tbl: Arc<Table>) -> Result<IndexStats, AppError> {
+    let status_filter = |s: &str| Some(format!("status = {}", idx::sql_str(s)));
+
+    let mut roots = Vec::with_capacity(imgdirs.len());
+    for root in imgdirs.keys().sorted() {
+        let c = idx::count(
             img_idx_tbl.clone(),
+            Some(format!("root = {}", idx::sql_str(root))),
         )
-        .await?);
+        .await?;
+        roots.push((root.clone(), c));
+    }
+
+    Ok(IndexStats {
+        total: idx::count(img_idx_tbl.clone(), None).await?,
+        indexed: idx::count(img_idx_tbl.clone(), status_filter(STATUS_INDEXED)).await?,
+        pending: idx::count(img_idx_tbl.clone(), status_filter(STATUS_PENDING)).await?,
+        failed: idx::count(img_idx_tbl.clone(), status_filter(STATUS_FAILED)).await?,
+        skipped: idx::count(img_idx_tbl.clone(), status_filter(STATUS_SKIPPED)).await?,
+        roots,
+    })
+}
+
+/**
+ * root 的扫描选项、阈值与重命名规则，root 未保存时使用默认值
+ */
+fn imgdir_options(imgdirs: &ImgDirs, root: &str) -> (ScanOptions, SizeThresholds, RenameRule) {
+    imgdirs
+        .get(root)
+        .map(|d| (d.scan.clone(), d.thresholds.clone(), d.rename_rule.clone()))
+        .unwrap_or_default()
+}
+
+/**
+ * 修正 root 及其下嵌套 root 中图片的归属，之前被上层 root 索引的记录改为属于最深的 root
+ */
+async fn fix_ownership(
+    imgdirs: &ImgDirs,
+    root: &str,
+    img_idx_tbl: Arc<Table>,
+) -> Result<(), AppError> {
+    let roots = imgdirs
+        .keys()
+        .chain(std::iter::once(&root.to_string()))
+        .cloned()
+        .collect::<Vec<_>>();
 
-        r.append(ipt);
+    let mut owners = roots::children(&roots, root);
+    owners.push(root);
+    for r in owners {
+        let ancestors = roots::ancestors(&roots, r);
+        idx::reassign_root(img_idx_tbl.clone(), r, &ancestors, r).await?;
     }
 
-    loop {
-        r = r
-            .into_iter()
-            .filter(|p| cache.contains_key(p.1.as_str()))
-            .collect::<Vec<_>>();
+    Ok(())
+}
 
-        if r.is_empty() {
-            break;
-        }
+/**
+ * 索引 root 下的全部图片，嵌套的其他 imgdir 中的图片由其自身索引
+ */
+pub async fn index_imgdir(
+    root: String,
+    rename: bool,
+    imgdirs: &ImgDirs,
+    server: Option<&ImgseachServer>,
+    settings: &IndexSettings,
+    img_idx_tbl: Arc<Table>,
+) -> Result<(), AppError> {
+    let (scan, thresholds, rule) = imgdir_options(imgdirs, &root);
+    fix_ownership(imgdirs, &root, img_idx_tbl.clone()).await?;
 
-        let chunk = if r.len() >= 5 {
-            r.drain(0..5)
-        } else {
-            r.drain(0..)
-        };
+    let imgs = path_utils::find_all_images(Path::new(&root), &scan)?
+        .into_iter()
+        .filter(|p| roots::is_owner(imgdirs, &root, p))
+        .collect::<Vec<_>>();
 
-        let paths = chunk
-            .as_ref()
-            .iter()
-            .map(|p| Path::new(&p.1))
-            .collect::<Vec<_>>();
-        let ipt = save_empty_image(&root, paths, img_idx_tbl.clone()).await?;
+    index_paths(
+        root,
+        imgs,
+        &thresholds,
+        rename.then_some(&rule),
+        server,
+        settings,
+        img_idx_tbl,
+    )
+    .await
+}
 
-        index_images(&ipt, rename, server, img_idx_tbl.clone()).await?;
+/**
+ * 移除 root，imgdirs 为移除后剩余的 imgdir
+ * 存在上层 root 时记录与缩略图转给上层 root，否则一并删除
+ */
+pub async fn remove_root(
+    root: &str,
+    imgdirs: &ImgDirs,
+    img_idx_tbl: Arc<Table>,
+) -> Result<(), AppError> {
+    let c = get_indexing_paths();
+    let children = roots::children(imgdirs.keys(), root)
+        .into_iter()
+        .map(|r| r.to_string())
+        .collect::<Vec<_>>();
+    let owned = root.to_string();
+    let _ = c.invalidate_entries_if(move |key, _| {
+        let p = Path::new(key);
+        p.starts_with(&owned) && !children.iter().any(|c| p.starts_with(c))
+    });
+
+    if let Some(parent) = roots::ancestors(imgdirs.keys(), root).first() {
+        log::info!("root {root} is nested in {parent}, move its images to {parent}");
+        return idx::reassign_root(img_idx_tbl, root, &[root], parent).await;
+    }
+
+    // 缩略图可能来自已移除的嵌套 root，按记录逐个删除
+    let filter = format!("root = {}", idx::sql_str(root));
+    let thumbnails = idx::get_all(img_idx_tbl.clone(), None, Some(filter))
+        .await?
+        .into_iter()
+        .map(|r| r.thumbnail)
+        .filter(|t| !t.is_empty())
+        .collect::<Vec<_>>();
 
-        for p in ipt.iter() {
-            cache.invalidate(p.1.as_str()).await;
+    idx::remove_by_root(img_idx_tbl, root).await?;
+    for t in thumbnails {
+        let p = Path::new(&t);
+        if p.exists() {
+            if let Err(e) = path_utils::remove_file(p) {
+                log::warn!("remove thumbnail error, {e}");
+            }
         }
     }
+    utils::remove_dir(root)?;
+
     Ok(())
 }
 
-pub async fn remove_root(root: &str, img_idx_tbl: Arc<Table>) -> Result<(), AppError> {
-    idx::remove_by_root(img_idx_tbl, root).await?;
-    utils::remove_dir(root)?;
-
+/**
+ * root 目录整体移动到 new，imgdirs 为修改后的 imgdir
+ * 记录中保存的是相对路径，只需修改 root，不重新索引
+ */
+pub async fn relocate_root(
+    old: &str,
+    new: &str,
+    imgdirs: &ImgDirs,
+    img_idx_tbl: Arc<Table>,
+) -> Result<(), AppError> {
     let c = get_indexing_paths();
-    let root = root.to_string();
-    let _ = c.invalidate_entries_if(move |key, _| key.starts_with(root.as_str()));
+    let owned = old.to_string();
+    let _ = c.invalidate_entries_if(move |key, _| Path::new(key).starts_with(&owned));
+
+    // 缩略图目录以 root 的签名命名，随 root 一起移动
+    let (old_dir, new_dir) = (
+        path_utils::sign(old.as_bytes()),
+        path_utils::sign(new.as_bytes()),
+    );
+    let moved = path_utils::move_thumbnail_dir(&old_dir, &new_dir)?;
+    let thumbnail_dirs = moved.as_ref().map(|(f, t)| (f.as_path(), t.as_path()));
+    if let Err(e) = idx::relocate_root(img_idx_tbl.clone(), old, new, thumbnail_dirs).await {
+        if moved.is_some() {
+            if let Err(e) = path_utils::move_thumbnail_dir(&new_dir, &old_dir) {
+                log::error!("failed to move thumbnails back to {old_dir}: {e}");
+            }
+        }
+        return Err(e);
+    }
+    // 新位置可能嵌套在其他 root 中，或包含其他 root
+    fix_ownership(imgdirs, new, img_idx_tbl).await?;
 
     Ok(())
 }
@@ -314,7 +889,8 @@ pub async fn delete_path(path: String, img_idx_tbl: Arc<Table>) -> Result<(), Ap
     let path = Arc::new(path);
     let c = get_indexing_paths();
     let p = path.clone();
-    let _ = c.invalidate_entries_if(move |key, _| key.starts_with(p.as_str()));
+    // 按路径组件比较，删除 /foo/bar 不影响 /foo/bar2
+    let _ = c.invalidate_entries_if(move |key, _| Path::new(key).starts_with(p.as_str()));
 
     let r = idx::remove_path_like(img_idx_tbl, path.as_str()).await?;
 
@@ -347,69 +923,206 @@ pub async fn rename(model: RenameModel, img_idx_tbl: Arc<Table>) -> Result<(), A
     Ok(())
 }
 
+/**
+ * 文件变化时增量索引，嵌套 root 的监听会同时触发，只处理归属于 root 的图片
+ */
 pub async fn modify_content(
     root: String,
     paths: Vec<String>,
     rename: bool,
+    imgdirs: &ImgDirs,
     server: Option<&ImgseachServer>,
+    settings: &IndexSettings,
     img_idx_tbl: Arc<Table>,
 ) -> Result<(), AppError> {
+    let (scan, thresholds, rule) = imgdir_options(imgdirs, &root);
     let paths = paths
         .into_iter()
-        .filter(|p| path_utils::is_support_file(Path::new(p)))
+        .map(PathBuf::from)
+        .filter(|p| path_utils::is_support_file(p))
+        .filter(|p| roots::is_owner(imgdirs, &root, p))
+        .filter(|p| match scan.is_ignored(Path::new(&root), p) {
+            Ok(ignored) => !ignored,
+            Err(e) => {
+                log::warn!("check ignore rules error, {e}");
+                true
+            }
+        })
         .collect::<Vec<_>>();
-    let cache = get_indexing_paths();
-    for p in paths.iter() {
-        cache.insert(p.clone(), None).await;
-    }
-
-    let mut r: Vec<(String, String, String)> = Vec::new();
-    for chunks in paths.chunks(5) {
-        let ipt = &mut (save_empty_image(
-            &root,
-            chunks.iter().map(Path::new).collect(),
-            img_idx_tbl.clone(),
-        )
-        .await?);
 
-        r.append(ipt);
-    }
+    index_paths(
+        root,
+        paths,
+        &thresholds,
+        rename.then_some(&rule),
+        server,
+        settings,
+        img_idx_tbl,
+    )
+    .await?;
 
     if server.is_none() {
-        return Err(AppError::Auth("server not ready".to_string()));
+        return Err(AppError::ServerNotReady);
     }
 
-    let server = server.unwrap();
+    Ok(())
+}
+
+pub async fn list_failed(img_idx_tbl: Arc<Table>) -> Result<Vec<ImgSearchResult>, AppError> {
+    idx::get_by_status(img_idx_tbl, STATUS_FAILED).await
+}
+
+pub async fn list_skipped(img_idx_tbl: Arc<Table>) -> Result<Vec<ImgSearchResult>, AppError> {
+    idx::get_by_status(img_idx_tbl, STATUS_SKIPPED).await
+}
 
-    loop {
-        r = r
+/**
+ * 重试失败的图片，ids 为空时重试全部
+ * 已有缩略图的直接上传，缩略图生成失败的重新生成
+ */
+pub async fn retry_failed(
+    ids: Option<Vec<String>>,
+    imgdirs: &ImgDirs,
+    server: &ImgseachServer,
+    settings: &IndexSettings,
+    img_idx_tbl: Arc<Table>,
+) -> Result<(), AppError> {
+    let failed = match ids {
+        Some(ids) => idx::get_by_ids(img_idx_tbl.clone(), &ids)
+            .await?
             .into_iter()
-            .filter(|p| cache.contains_key(p.1.as_str()))
-            .collect::<Vec<_>>();
+            .filter(|r| r.status == STATUS_FAILED)
+            .collect::<Vec<_>>(),
+        None => idx::get_by_status(img_idx_tbl.clone(), STATUS_FAILED).await?,
+    };
 
-        if r.is_empty() {
-            break;
-        }
+    let cache = get_indexing_paths();
 
-        let chunk = if r.len() >= 5 {
-            r.drain(0..5)
-        } else {
-            r.drain(0..)
+    for (root, rs) in failed.into_iter().into_group_map_by(|r| r.root.clone()) {
+        let Some(imgdir) = imgdirs.get(&root) else {
+            log::warn!("root={root} not found in imgdir_store, skip retry");
+            continue;
         };
+        if !volume::is_available(&root) {
+            log::warn!("root={root} is offline, skip retry");
+            continue;
+        }
 
-        let paths = chunk
-            .as_ref()
-            .iter()
-            .map(|p| Path::new(&p.1))
-            .collect::<Vec<_>>();
-        let ipt = save_empty_image(&root, paths, img_idx_tbl.clone()).await?;
+        let (uploads, regens): (Vec<_>, Vec<_>) =
+            rs.into_iter().partition(|r| !r.thumbnail.is_empty());
 
-        index_images(&ipt, rename, server, img_idx_tbl.clone()).await?;
+        let regens = regens
+            .into_iter()
+            .map(|r| PathBuf::from(r.path))
+            .collect::<Vec<_>>();
+        index_paths(
+            root.clone(),
+            regens,
+            &imgdir.thresholds,
+            imgdir.rename.then_some(&imgdir.rename_rule),
+            Some(server),
+            settings,
+            img_idx_tbl.clone(),
+        )
+        .await?;
 
-        for p in chunk.as_ref() {
-            cache.invalidate(p.1.as_str()).await;
+        for p in uploads.iter() {
+            cache.insert(p.path.clone(), None).await;
         }
+        let ipts = uploads
+            .chunks(settings.batch_size(Some(server)))
+            .map(|chunk| {
+                chunk
+                    .iter()
+                    .map(|c| (c.id.clone(), c.path.clone(), c.thumbnail.clone()))
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+
+        upload_concurrently(
+            stream::iter(ipts).map(Ok),
+            imgdir.rename.then_some(&imgdir.rename_rule),
+            server,
+            settings,
+            img_idx_tbl.clone(),
+        )
+        .await?;
     }
 
     Ok(())
 }
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct AppliedRename {
+    pub id: String,
+    pub from: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub to: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/**
+ * 待确认的重命名建议
+ */
+pub async fn list_rename_proposals(
+    root: Option<&str>,
+    img_idx_tbl: Arc<Table>,
+) -> Result<Vec<ImgSearchResult>, AppError> {
+    let mut filter = "suggested_name is not null".to_string();
+    if let Some(root) = root {
+        filter.push_str(&format!(" and root = {}", idx::sql_str(root)));
+    }
+    idx::get_all(img_idx_tbl, None, Some(filter)).await
+}
+
+/**
+ * 按建议重命名磁盘上的文件并更新索引，失败的保留建议
+ */
+pub async fn apply_rename_proposals(
+    ids: &[String],
+    imgdirs: &ImgDirs,
+    img_idx_tbl: Arc<Table>,
+) -> Result<Vec<AppliedRename>, AppError> {
+    let proposals = idx::get_by_ids(img_idx_tbl.clone(), ids).await?;
+    let mut r = Vec::with_capacity(proposals.len());
+    let batch = journal::new_batch();
+
+    for p in proposals.into_iter() {
+        let Some(name) = p.suggested_name else {
+            continue;
+        };
+
+        let rule = imgdirs
+            .get(&p.root)
+            .map(|d| d.rename_rule.clone())
+            .unwrap_or_default();
+        let current = Path::new(&p.path);
+        match path_utils::rename(current, rule.candidates(&name, current)) {
+            Ok(new_path) => {
+                let new_path = new_path.display().to_string();
+                journal::record(&batch, &p.path, &new_path, journal::SOURCE_PROPOSAL);
+                update_path(img_idx_tbl.clone(), &p.path, &new_path).await?;
+                idx::clear_suggested_name(img_idx_tbl.clone(), std::slice::from_ref(&p.id)).await?;
+                r.push(AppliedRename {
+                    id: p.id,
+                    from: p.path,
+                    to: Some(new_path),
+                    error: None,
+                });
+            }
+            Err(e) => {
+                log::warn!("apply rename proposal error, {e}");
+                r.push(AppliedRename {
+                    id: p.id,
+                    from: p.path,
+                    to: None,
+                    error: Some(e.to_string()),
+                });
+            }
+        }
+    }
+
+    Ok(r)
+}
@@ -0,0 +1,329 @@
+/**
+ * 索引的导出与导入，便于迁移到其他机器而不用重新计算向量
+ * 归档为 tar.gz，包含 manifest.json、records.jsonl 与 thumbnails/
+ */
+use std::{
+    collections::{BTreeSet, HashMap},
+    fs::File,
+    io::{BufRead, BufReader, Read},
+    path::{Component, Path, PathBuf},
+    sync::Arc,
+};
+
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+use lancedb::Table;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    error::AppError,
+    image_command::{
+        idx::{self, ImgIdx, DESC_SOURCE_SERVER, STATUS_INDEXED},
+        utils,
+    },
+    path_utils::{self, ScanOptions},
+    uuid_utils,
+};
+
+static ARCHIVE_VERSION: u32 = 1;
+static MANIFEST_ENTRY: &str = "manifest.json";
+static RECORDS_ENTRY: &str = "records.jsonl";
+static THUMBNAIL_ENTRY_DIR: &str = "thumbnails";
+
+#[derive(Serialize, Deserialize)]
+struct Manifest {
+    version: u32,
+    roots: Vec<String>,
+    count: usize,
+}
+
+#[derive(Serialize, Deserialize)]
+struct ArchiveRecord {
+    root: String,
+    // 相对 root 的路径，以 / 分隔
+    rel_path: String,
+    sign: String,
+    desc: Option<String>,
+    vec: Vec<f32>,
+    // 归档中的缩略图条目
+    thumbnail: Option<String>,
+}
+
+#[derive(Serialize, Debug, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct ImportReport {
+    pub imported: usize,
+    // 在新位置找不到对应文件的记录数
+    pub missing: usize,
+    // 缩略图无法保存或生成的记录数
+    pub failed: usize,
+    pub roots: Vec<String>,
+}
+
+pub async fn export(archive: &Path, img_idx_tbl: Arc<Table>) -> Result<usize, AppError> {
+    let records = idx::get_records(
+        img_idx_tbl,
+        Some(format!("status = {}", idx::sql_str(STATUS_INDEXED))),
+    )
+    .await?;
+
+    let archive = archive.to_path_buf();
+    tauri::async_runtime::spawn_blocking(move || write_archive(&archive, records)).await?
+}
+
+fn write_archive(archive: &Path, records: Vec<ImgIdx>) -> Result<usize, AppError> {
+    let f = File::create(archive).map_err(|e| AppError::io(e, archive))?;
+    let mut tar = tar::Builder::new(GzEncoder::new(f, Compression::default()));
+
+    let mut roots = BTreeSet::new();
+    let mut lines = Vec::new();
+    let mut count = 0;
+
+    for r in records.into_iter() {
+        let Some(vec) = r.vec else {
+            continue;
+        };
+
+        let thumbnail = Path::new(&r.thumbnail);
+        let thumbnail = if !r.thumbnail.is_empty() && thumbnail.is_file() {
+            let ext = thumbnail
+                .extension()
+                .map(|e| e.to_string_lossy().to_string())
+                .unwrap_or_default();
+            let name = format!("{THUMBNAIL_ENTRY_DIR}/{}.{ext}", r.id);
+            tar.append_path_with_name(thumbnail, &name)
+                .map_err(|e| AppError::io(e, thumbnail))?;
+            Some(name)
+        } else {
+            None
+        };
+
+        serde_json::to_writer(
+            &mut lines,
+            &ArchiveRecord {
+                rel_path: rel_path(Path::new(&r.root), Path::new(&r.path)),
+                root: r.root.clone(),
+                sign: r.sign,
+                desc: r.desc,
+                vec,
+                thumbnail,
+            },
+        )?;
+        lines.push(b'\n');
+
+        roots.insert(r.root);
+        count += 1;
+    }
+
+    let manifest = Manifest {
+        version: ARCHIVE_VERSION,
+        roots: roots.into_iter().collect(),
+        count,
+    };
+    append_bytes(
+        &mut tar,
+        MANIFEST_ENTRY,
+        &serde_json::to_vec_pretty(&manifest)?,
+    )?;
+    append_bytes(&mut tar, RECORDS_ENTRY, &lines)?;
+
+    tar.into_inner()?.finish()?;
+
+    log::info!("export {count} records to {}", archive.display());
+    Ok(count)
+}
+
+fn append_bytes<W: std::io::Write>(
+    tar: &mut tar::Builder<W>,
+    name: &str,
+    data: &[u8],
+) -> Result<(), AppError> {
+    let mut header = tar::Header::new_gnu();
+    header.set_size(data.len() as u64);
+    header.set_mode(0o644);
+    header.set_cksum();
+    tar.append_data(&mut header, name, data)?;
+    Ok(())
+}
+
+fn rel_path(root: &Path, path: &Path) -> String {
+    let rel = path.strip_prefix(root).unwrap_or(path);
+    rel.components()
+        .filter_map(|c| match c {
+            Component::Normal(s) => Some(s.to_string_lossy().to_string()),
+            _ => None,
+        })
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/**
+ * 导入归档，root_map 将导出时的 root 映射到本机的新位置，未映射的保持原位置
+ * 先按相对路径匹配文件，签名不一致时在新 root 下按签名查找
+ */
+pub async fn import(
+    archive: &Path,
+    root_map: HashMap<String, String>,
+    img_idx_tbl: Arc<Table>,
+) -> Result<ImportReport, AppError> {
+    let archive = archive.to_path_buf();
+    let (records, report) =
+        tauri::async_runtime::spawn_blocking(move || read_archive(&archive, &root_map)).await??;
+
+    for (i, chunk) in records.chunks(1000).enumerate() {
+        if let Err(e) = idx::save_batch(img_idx_tbl.clone(), chunk.to_vec()).await {
+            // 未写入索引的记录不会再引用这些缩略图
+            remove_thumbnails(records[i * 1000..].iter().map(|r| Path::new(&r.thumbnail)));
+            return Err(e);
+        }
+    }
+
+    log::info!("import index: {report:?}");
+    Ok(report)
+}
+
+fn read_archive(
+    archive: &Path,
+    root_map: &HashMap<String, String>,
+) -> Result<(Vec<ImgIdx>, ImportReport), AppError> {
+    let f = File::open(archive).map_err(|e| AppError::io(e, archive))?;
+    let mut tar = tar::Archive::new(GzDecoder::new(f));
+
+    let mut manifest: Option<Manifest> = None;
+    let mut records = Vec::new();
+    let mut thumbnails: HashMap<String, Vec<u8>> = HashMap::new();
+
+    for entry in tar.entries()? {
+        let mut entry = entry?;
+        let name = entry.path()?.to_string_lossy().to_string();
+
+        if name == MANIFEST_ENTRY {
+            manifest = Some(serde_json::from_reader(&mut entry)?);
+        } else if name == RECORDS_ENTRY {
+            for line in BufReader::new(&mut entry).lines() {
+                let line = line?;
+                if !line.is_empty() {
+                    records.push(serde_json::from_str::<ArchiveRecord>(&line)?);
+                }
+            }
+        } else if name.starts_with(THUMBNAIL_ENTRY_DIR) {
+            let mut bs = Vec::new();
+            entry.read_to_end(&mut bs)?;
+            thumbnails.insert(name, bs);
+        }
+    }
+
+    let manifest =
+        manifest.ok_or_else(|| AppError::Config("invalid archive: no manifest".to_string()))?;
+    if manifest.version > ARCHIVE_VERSION {
+        return Err(AppError::Config(format!(
+            "unsupported archive version: {}",
+            manifest.version
+        )));
+    }
+
+    let mut report = ImportReport::default();
+    let mut signs_by_root: HashMap<String, HashMap<String, PathBuf>> = HashMap::new();
+    let mut roots = BTreeSet::new();
+    let mut res: Vec<ImgIdx> = Vec::with_capacity(records.len());
+
+    for r in records.into_iter() {
+        let root = root_map.get(&r.root).unwrap_or(&r.root).clone();
+
+        let located = match locate(&root, &r, &mut signs_by_root) {
+            Ok(located) => located,
+            Err(e) => {
+                // 中止导入时删除已保存的缩略图
+                remove_thumbnails(res.iter().map(|r| Path::new(&r.thumbnail)));
+                return Err(e);
+            }
+        };
+        let Some(path) = located else {
+            report.missing += 1;
+            continue;
+        };
+
+        let thumbnail = match r.thumbnail.as_ref().and_then(|t| thumbnails.get(t)) {
+            Some(bs) => utils::save_thumbnail(&root, bs),
+            None => utils::gen_thumbnail(&root, &path).map(|(_, t)| t),
+        };
+        let thumbnail = match thumbnail {
+            Ok(thumbnail) => thumbnail,
+            Err(e) => {
+                log::warn!("import thumbnail failed, path = {}, {e}", path.display());
+                report.failed += 1;
+                continue;
+            }
+        };
+
+        res.push(ImgIdx {
+            id: uuid_utils::get(),
+            name: path
+                .file_name()
+                .map(|n| n.display().to_string())
+                .unwrap_or_default(),
+            path: path.display().to_string(),
+            root: root.clone(),
+            sign: r.sign,
+            thumbnail: thumbnail.display().to_string(),
+            idxed: true,
+            desc: r.desc,
+            img_vec: Some(r.vec.clone()),
+            vec: Some(r.vec),
+            error: None,
+            status: STATUS_INDEXED.to_string(),
+            desc_source: DESC_SOURCE_SERVER.to_string(),
+            suggested_name: None,
+        });
+        roots.insert(root);
+    }
+
+    report.imported = res.len();
+    report.roots = roots.into_iter().collect();
+    Ok((res, report))
+}
+
+fn remove_thumbnails<'a>(thumbnails: impl Iterator<Item = &'a Path>) {
+    for t in thumbnails {
+        if let Err(e) = std::fs::remove_file(t) {
+            log::warn!("remove thumbnail failed, path = {}, {e}", t.display());
+        }
+    }
+}
+
+fn locate(
+    root: &str,
+    r: &ArchiveRecord,
+    signs_by_root: &mut HashMap<String, HashMap<String, PathBuf>>,
+) -> Result<Option<PathBuf>, AppError> {
+    let p = r
+        .rel_path
+        .split('/')
+        .fold(PathBuf::from(root), |p, s| p.join(s));
+
+    if p.is_file() {
+        let bs = std::fs::read(&p).map_err(|e| AppError::io(e, &p))?;
+        if path_utils::sign(&bs) == r.sign {
+            return Ok(Some(p));
+        }
+    }
+
+    if !signs_by_root.contains_key(root) {
+        let mut signs = HashMap::new();
+        if Path::new(root).is_dir() {
+            for p in path_utils::find_all_images(Path::new(root), &ScanOptions::default())? {
+                match std::fs::read(&p) {
+                    Ok(bs) => {
+                        signs.insert(path_utils::sign(&bs), p);
+                    }
+                    Err(e) => log::warn!("read {} error: {e}", p.display()),
+                }
+            }
+        }
+        signs_by_root.insert(root.to_string(), signs);
+    }
+
+    Ok(signs_by_root
+        .get(root)
+        .and_then(|signs| signs.get(&r.sign))
+        .cloned())
+}
@@ -0,0 +1,190 @@
+/**
+ * 在临时数据库上对比有无标量索引时的增删改耗时
+ */
+use std::{path::PathBuf, sync::Arc, time::Instant};
+
+use arrow_array::RecordBatchIterator;
+use lancedb::Table;
+use serde::Serialize;
+
+use crate::{
+    error::AppError,
+    image_command::idx::{self, ImgIdx, DESC_SOURCE_SERVER, STATUS_INDEXED, STATUS_PENDING},
+};
+
+static BENCH_TABLE_NAME: &str = "img_idx";
+const ROOTS: usize = 10;
+const DIRS: usize = 100;
+const INSERT_CHUNK: usize = 10_000;
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct BenchResult {
+    pub indexed: bool,
+    pub op: String,
+    pub times: usize,
+    pub avg_ms: f64,
+}
+
+fn record(i: usize) -> ImgIdx {
+    let root = format!("/bench/root{}", i % ROOTS);
+    let idxed = i % 2 == 0;
+    ImgIdx {
+        id: format!("{i:08}"),
+        name: format!("img{i}.jpg"),
+        path: format!("{root}/dir{}/img{i}.jpg", i % DIRS),
+        root,
+        sign: format!("{i:064x}"),
+        thumbnail: "".to_string(),
+        idxed,
+        desc: None,
+        vec: None,
+        error: None,
+        status: if idxed {
+            STATUS_INDEXED
+        } else {
+            STATUS_PENDING
+        }
+        .to_string(),
+        desc_source: DESC_SOURCE_SERVER.to_string(),
+        suggested_name: None,
+        img_vec: None,
+    }
+}
+
+async fn create_table(rows: usize, indexed: bool) -> Result<(Arc<Table>, PathBuf), AppError> {
+    let dir = std::env::temp_dir().join(format!(
+        "imgsearch-bench-{}-{}",
+        std::process::id(),
+        if indexed { "indexed" } else { "plain" }
+    ));
+    if dir.exists() {
+        std::fs::remove_dir_all(&dir).map_err(|e| AppError::io(e, &dir))?;
+    }
+
+    let uri = dir.display().to_string();
+    let db = lancedb::connect(&uri).execute().await?;
+    let table = Arc::new(
+        db.create_empty_table(BENCH_TABLE_NAME, idx::get_schema().clone())
+            .execute()
+            .await?,
+    );
+
+    for start in (0..rows).step_by(INSERT_CHUNK) {
+        let records = (start..(start + INSERT_CHUNK).min(rows))
+            .map(record)
+            .collect();
+        // 直接追加，不经过 save_batch，避免触发向量索引训练
+        let batch = idx::record_batch(records)?;
+        let schema = batch.schema();
+        table
+            .add(Box::new(RecordBatchIterator::new(
+                vec![Ok(batch)].into_iter(),
+                schema,
+            )))
+            .execute()
+            .await?;
+    }
+
+    // 数据写入后再建索引，索引覆盖全部数据
+    if indexed {
+        idx::ensure_scalar_indexes(&table).await?;
+    }
+
+    Ok((table, dir))
+}
+
+async fn time_op<F, Fut>(
+    results: &mut Vec<BenchResult>,
+    indexed: bool,
+    op: &str,
+    times: usize,
+    mut f: F,
+) -> Result<(), AppError>
+where
+    F: FnMut(usize) -> Fut,
+    Fut: std::future::Future<Output = Result<(), AppError>>,
+{
+    let start = Instant::now();
+    for i in 0..times {
+        f(i).await?;
+    }
+    let avg_ms = start.elapsed().as_secs_f64() * 1000.0 / times.max(1) as f64;
+
+    log::info!("bench {op} indexed={indexed}: {avg_ms:.2}ms");
+    results.push(BenchResult {
+        indexed,
+        op: op.to_string(),
+        times,
+        avg_ms,
+    });
+    Ok(())
+}
+
+/**
+ * rows: 表的记录数，times: 每种操作的执行次数
+ */
+pub async fn scalar_index_bench(rows: usize, times: usize) -> Result<Vec<BenchResult>, AppError> {
+    let rows = rows.max(ROOTS);
+    // 每次操作的记录在表中均匀分布
+    let step = (rows / times.max(1)).max(1);
+    let mut results = vec![];
+
+    for indexed in [false, true] {
+        let (table, dir) = create_table(rows, indexed).await?;
+
+        time_op(&mut results, indexed, "get_by_path", times, |i| {
+            let table = table.clone();
+            async move {
+                let r = record((i * step) % rows);
+                idx::get_by_path(table, &r.path).await?;
+                Ok(())
+            }
+        })
+        .await?;
+
+        time_op(&mut results, indexed, "count_by_status", times, |_| {
+            let table = table.clone();
+            async move {
+                let filter = format!("status = {}", idx::sql_str(STATUS_PENDING));
+                idx::count(table, Some(filter)).await?;
+                Ok(())
+            }
+        })
+        .await?;
+
+        time_op(&mut results, indexed, "update_path", times, |i| {
+            let table = table.clone();
+            async move {
+                let r = record((i * step) % rows);
+                let new = r.path.replace("/img", "/renamed");
+                idx::update_path(table, &r.path, &new).await
+            }
+        })
+        .await?;
+
+        time_op(&mut results, indexed, "remove_path", times, |i| {
+            let table = table.clone();
+            async move {
+                // 与 update_path 错开，删除未被重命名的记录
+                let r = record((i * step + 1) % rows);
+                idx::remove_path_like(table, &r.path).await?;
+                Ok(())
+            }
+        })
+        .await?;
+
+        time_op(&mut results, indexed, "remove_by_root", 1, |_| {
+            let table = table.clone();
+            async move { idx::remove_by_root(table, &record(0).root).await }
+        })
+        .await?;
+
+        drop(table);
+        if let Err(e) = std::fs::remove_dir_all(&dir) {
+            log::warn!("remove bench dir {} error, {e}", dir.display());
+        }
+    }
+
+    Ok(results)
+}
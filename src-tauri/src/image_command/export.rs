@@ -0,0 +1,256 @@
+/**
+ * 将搜索结果或筛选后的索引导出为 csv / jsonl / parquet，供分析使用
+ */
+use std::{fs::File, path::Path, sync::Arc};
+
+use arrow_array::{
+    builder::{FixedSizeListBuilder, Float32Builder, StringBuilder},
+    ArrayRef, RecordBatch,
+};
+use itertools::Itertools;
+use lancedb::{
+    arrow::arrow_schema::{DataType, Field, Schema},
+    Table,
+};
+use serde::Deserialize;
+
+use crate::{
+    error::AppError,
+    image_command::{
+        api,
+        idx::{self, sql_str, DIM},
+        SearchModel,
+    },
+    server::imgsearch_server::ImgseachServer,
+};
+
+#[derive(Deserialize, Clone, Copy, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    Csv,
+    Jsonl,
+    Parquet,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ExportModel {
+    pub out: String,
+    pub format: ExportFormat,
+    // 有关键词时导出搜索结果，否则按 root / status 筛选
+    pub keyword: Option<String>,
+    pub top: Option<usize>,
+    pub root: Option<String>,
+    pub status: Option<String>,
+    #[serde(default)]
+    pub include_embedding: bool,
+}
+
+struct ExportRow {
+    id: String,
+    name: String,
+    path: String,
+    root: String,
+    status: String,
+    desc: Option<String>,
+    score: Option<f32>,
+    vec: Option<Vec<f32>>,
+}
+
+pub async fn export(
+    model: &ExportModel,
+    server: Option<&ImgseachServer>,
+    img_idx_tbl: Arc<Table>,
+) -> Result<usize, AppError> {
+    let rows = match &model.keyword {
+        Some(keyword) => {
+            let server = server.ok_or(AppError::ServerNotReady)?;
+            let search = SearchModel {
+                keyword: keyword.clone(),
+                top: model.top.unwrap_or(100),
+                ..Default::default()
+            };
+            let r = api::search(&search, server, img_idx_tbl.clone()).await?;
+
+            let mut vecs = if model.include_embedding && !r.is_empty() {
+                let ids = r.iter().map(|r| sql_str(&r.id)).join(",");
+                idx::get_records(img_idx_tbl, Some(format!("id in ({ids})")))
+                    .await?
+                    .into_iter()
+                    .map(|r| (r.id, r.vec))
+                    .collect()
+            } else {
+                std::collections::HashMap::new()
+            };
+
+            r.into_iter()
+                .map(|r| ExportRow {
+                    vec: vecs.remove(&r.id).flatten(),
+                    id: r.id,
+                    name: r.name,
+                    path: r.path,
+                    root: r.root,
+                    status: r.status,
+                    desc: r.desc,
+                    score: r.score,
+                })
+                .collect::<Vec<_>>()
+        }
+        None => {
+            let filter = [
+                model
+                    .root
+                    .as_ref()
+                    .map(|r| format!("root = {}", sql_str(r))),
+                model
+                    .status
+                    .as_ref()
+                    .map(|s| format!("status = {}", sql_str(s))),
+            ]
+            .into_iter()
+            .flatten()
+            .join(" and ");
+
+            idx::get_records(img_idx_tbl, (!filter.is_empty()).then_some(filter))
+                .await?
+                .into_iter()
+                .map(|r| ExportRow {
+                    id: r.id,
+                    name: r.name,
+                    path: r.path,
+                    root: r.root,
+                    status: r.status,
+                    desc: r.desc,
+                    score: None,
+                    vec: r.vec,
+                })
+                .collect::<Vec<_>>()
+        }
+    };
+
+    let count = rows.len();
+    let out = model.out.clone();
+    let format = model.format;
+    let include_embedding = model.include_embedding;
+    tauri::async_runtime::spawn_blocking(move || {
+        write(Path::new(&out), format, rows, include_embedding)
+    })
+    .await??;
+
+    log::info!("export {count} rows to {} as {format:?}", model.out);
+    Ok(count)
+}
+
+fn write(
+    out: &Path,
+    format: ExportFormat,
+    rows: Vec<ExportRow>,
+    include_embedding: bool,
+) -> Result<(), AppError> {
+    // csv 不支持列表，向量以 json 字符串写入
+    let batch = to_batch(rows, include_embedding, matches!(format, ExportFormat::Csv))?;
+    let f = File::create(out).map_err(|e| AppError::io(e, out))?;
+
+    match format {
+        ExportFormat::Csv => {
+            let mut w = arrow_csv::WriterBuilder::new().with_header(true).build(f);
+            w.write(&batch)?;
+        }
+        ExportFormat::Jsonl => {
+            let mut w = arrow_json::LineDelimitedWriter::new(f);
+            w.write(&batch)?;
+            w.finish()?;
+        }
+        ExportFormat::Parquet => {
+            let mut w = parquet::arrow::ArrowWriter::try_new(f, batch.schema(), None)?;
+            w.write(&batch)?;
+            w.close()?;
+        }
+    }
+
+    Ok(())
+}
+
+fn to_batch(
+    rows: Vec<ExportRow>,
+    include_embedding: bool,
+    embedding_as_json: bool,
+) -> Result<RecordBatch, AppError> {
+    let mut id_builder = StringBuilder::new();
+    let mut name_builder = StringBuilder::new();
+    let mut path_builder = StringBuilder::new();
+    let mut root_builder = StringBuilder::new();
+    let mut status_builder = StringBuilder::new();
+    let mut desc_builder = StringBuilder::new();
+    let mut score_builder = Float32Builder::new();
+    let mut vec_builder = FixedSizeListBuilder::new(
+        Float32Builder::with_capacity(DIM as usize * rows.len()),
+        DIM,
+    );
+    let mut vec_json_builder = StringBuilder::new();
+
+    for r in rows.into_iter() {
+        id_builder.append_value(r.id);
+        name_builder.append_value(r.name);
+        path_builder.append_value(r.path);
+        root_builder.append_value(r.root);
+        status_builder.append_value(r.status);
+        desc_builder.append_option(r.desc);
+        score_builder.append_option(r.score);
+
+        if !include_embedding {
+            continue;
+        }
+
+        if embedding_as_json {
+            let v = r.vec.map(|v| serde_json::to_string(&v)).transpose()?;
+            vec_json_builder.append_option(v);
+        } else if let Some(vec) = r.vec {
+            vec.into_iter().for_each(|f| {
+                vec_builder.values().append_value(f);
+            });
+            vec_builder.append(true);
+        } else {
+            for _ in 0..DIM {
+                vec_builder.values().append_null();
+            }
+            vec_builder.append(false);
+        }
+    }
+
+    let mut fields = vec![
+        Field::new("id", DataType::Utf8, false),
+        Field::new("name", DataType::Utf8, false),
+        Field::new("path", DataType::Utf8, false),
+        Field::new("root", DataType::Utf8, false),
+        Field::new("status", DataType::Utf8, false),
+        Field::new("desc", DataType::Utf8, true),
+        Field::new("score", DataType::Float32, true),
+    ];
+    let mut columns = vec![
+        Arc::new(id_builder.finish()) as ArrayRef,
+        Arc::new(name_builder.finish()) as ArrayRef,
+        Arc::new(path_builder.finish()) as ArrayRef,
+        Arc::new(root_builder.finish()) as ArrayRef,
+        Arc::new(status_builder.finish()) as ArrayRef,
+        Arc::new(desc_builder.finish()) as ArrayRef,
+        Arc::new(score_builder.finish()) as ArrayRef,
+    ];
+
+    if include_embedding && embedding_as_json {
+        fields.push(Field::new("embedding", DataType::Utf8, true));
+        columns.push(Arc::new(vec_json_builder.finish()) as ArrayRef);
+    } else if include_embedding {
+        fields.push(Field::new(
+            "embedding",
+            DataType::FixedSizeList(Arc::new(Field::new("item", DataType::Float32, true)), DIM),
+            true,
+        ));
+        columns.push(Arc::new(vec_builder.finish()) as ArrayRef);
+    }
+
+    Ok(RecordBatch::try_new(
+        Arc::new(Schema::new(fields)),
+        columns,
+    )?)
+}
@@ -3,27 +3,37 @@ use std::{
     sync::{Arc, OnceLock},
 };
 
-use crate::{error::AppError, uuid_utils};
+use crate::{
+    error::AppError,
+    image_command::{meta, vector_index},
+    uuid_utils,
+};
 use arrow_array::{
     builder::{BooleanBuilder, FixedSizeListBuilder, Float32Builder, StringBuilder},
     Array, ArrayRef, RecordBatch, RecordBatchIterator,
 };
 use futures::TryStreamExt;
+use itertools::Itertools;
 use lancedb::{
     arrow::{
         arrow_schema::{DataType, Field, Schema},
         IntoArrowStream,
     },
-    index::{scalar::BTreeIndexBuilder, vector::IvfPqIndexBuilder},
+    index::{
+        scalar::{BTreeIndexBuilder, BitmapIndexBuilder},
+        Index,
+    },
     query::{ExecutableQuery, HasQuery, QueryBase, QueryFilter, Select},
+    table::NewColumnTransform,
     Connection, Table,
 };
 use serde::{Deserialize, Serialize};
 
-#[derive(Deserialize, Serialize)]
+#[derive(Deserialize, Serialize, Clone)]
 pub struct ImgIdx {
     pub id: String,
     pub name: String,
+    // 绝对路径，保存时转为相对 root 的路径
     pub path: String,
     pub root: String,
     pub sign: String,
@@ -32,13 +42,39 @@ pub struct ImgIdx {
     pub idxed: bool,
     pub desc: Option<String>,
     pub vec: Option<Vec<f32>>,
+    // 处理失败的原因
+    pub error: Option<String>,
+    // pending / indexed / failed
+    pub status: String,
+    // desc 的来源，用户编辑过的描述重新索引时保留
+    pub desc_source: String,
+    // 服务端返回的图片向量，embedding 与描述向量混合后保存原值
+    pub img_vec: Option<Vec<f32>>,
+    // 待确认的重命名建议
+    pub suggested_name: Option<String>,
 }
 
+pub static STATUS_PENDING: &str = "pending";
+pub static STATUS_INDEXED: &str = "indexed";
+pub static STATUS_FAILED: &str = "failed";
+// 未达到 imgdir 的尺寸阈值，原因保存在 error
+pub static STATUS_SKIPPED: &str = "skipped";
+
+pub static DESC_SOURCE_SERVER: &str = "server";
+pub static DESC_SOURCE_USER: &str = "user";
+// 用户编辑，且 embedding 混合了描述的向量
+pub static DESC_SOURCE_BLENDED: &str = "user_blended";
+
 impl ImgIdx {
-    pub fn new_empty(path: &Path, root: String, sign: String, thumbnail: &Path) -> Self {
-        Self {
+    pub fn new_empty(
+        path: &Path,
+        root: String,
+        sign: String,
+        thumbnail: &Path,
+    ) -> Result<Self, AppError> {
+        Ok(Self {
             id: uuid_utils::get(),
-            name: path.file_name().unwrap().display().to_string(),
+            name: file_name(path)?,
             path: path.display().to_string(),
             root,
             sign,
@@ -46,15 +82,63 @@ impl ImgIdx {
             desc: None,
             idxed: false,
             vec: None,
-        }
+            error: None,
+            status: STATUS_PENDING.to_string(),
+            desc_source: DESC_SOURCE_SERVER.to_string(),
+            img_vec: None,
+            suggested_name: None,
+        })
+    }
+
+    /**
+     * 生成缩略图失败的图片，没有签名和缩略图
+     */
+    pub fn new_failed(path: &Path, root: String, error: String) -> Result<Self, AppError> {
+        Self::new_unindexed(path, root, error, STATUS_FAILED)
+    }
+
+    /**
+     * 被阈值过滤的图片，不生成缩略图也不上传
+     */
+    pub fn new_skipped(path: &Path, root: String, reason: String) -> Result<Self, AppError> {
+        Self::new_unindexed(path, root, reason, STATUS_SKIPPED)
+    }
+
+    fn new_unindexed(
+        path: &Path,
+        root: String,
+        error: String,
+        status: &str,
+    ) -> Result<Self, AppError> {
+        Ok(Self {
+            id: uuid_utils::get(),
+            name: file_name(path)?,
+            path: path.display().to_string(),
+            root,
+            sign: "".to_string(),
+            thumbnail: "".to_string(),
+            desc: None,
+            idxed: false,
+            vec: None,
+            error: Some(error),
+            status: status.to_string(),
+            desc_source: DESC_SOURCE_SERVER.to_string(),
+            img_vec: None,
+            suggested_name: None,
+        })
     }
 }
 
+fn file_name(path: &Path) -> Result<String, AppError> {
+    path.file_name()
+        .map(|n| n.display().to_string())
+        .ok_or_else(|| AppError::Internal(format!("invalid file path: {}", path.display())))
+}
+
 static SCHEMA: OnceLock<Arc<Schema>> = OnceLock::new();
-static DIM: i32 = 768;
+pub static DIM: i32 = 768;
 static IMG_IDX_TABLE_NAME: &str = "img_idx";
-static IMG_IDX_BUILD_DIVIDER: usize = 256;
-fn get_schema() -> &'static Arc<Schema> {
+pub(crate) fn get_schema() -> &'static Arc<Schema> {
     SCHEMA.get_or_init(|| {
         Arc::new(Schema::new(vec![
             Field::new("id", DataType::Utf8, false),
@@ -70,47 +154,511 @@ fn get_schema() -> &'static Arc<Schema> {
                 DataType::FixedSizeList(Arc::new(Field::new("item", DataType::Float32, true)), DIM),
                 true,
             ),
+            Field::new("error", DataType::Utf8, true),
+            Field::new("status", DataType::Utf8, false),
+            Field::new("desc_source", DataType::Utf8, false),
+            Field::new(
+                "img_embedding",
+                DataType::FixedSizeList(Arc::new(Field::new("item", DataType::Float32, true)), DIM),
+                true,
+            ),
+            Field::new("suggested_name", DataType::Utf8, true),
         ]))
     })
 }
 
-pub async fn get_table(db: &Connection) -> Result<Table, AppError> {
+/**
+ * 旧版本数据库缺少的列，按 schema 中的顺序追加，(列名, 默认值表达式)
+ * 表达式为 None 时按 schema 中的类型填充 null
+ */
+static COLUMN_MIGRATIONS: &[(&str, Option<&str>)] = &[
+    ("error", Some("CAST(NULL AS string)")),
+    (
+        "status",
+        Some(
+            "CASE WHEN idxed THEN 'indexed' WHEN error IS NOT NULL THEN 'failed' ELSE 'pending' END",
+        ),
+    ),
+    ("desc_source", Some("'server'")),
+    ("img_embedding", None),
+    ("suggested_name", Some("CAST(NULL AS string)")),
+];
+
+/**
+ * 标量索引，唯一值多的列用 BTree，取值少的列用 Bitmap
+ * 旧版本数据库打开时补建缺少的索引
+ */
+static SCALAR_INDEXES: &[(&str, ScalarIndexKind)] = &[
+    ("id", ScalarIndexKind::BTree),
+    ("path", ScalarIndexKind::BTree),
+    ("sign", ScalarIndexKind::BTree),
+    ("root", ScalarIndexKind::Bitmap),
+    ("idxed", ScalarIndexKind::Bitmap),
+    ("status", ScalarIndexKind::Bitmap),
+];
+
+#[derive(Clone, Copy)]
+enum ScalarIndexKind {
+    BTree,
+    Bitmap,
+}
+
+/**
+ * 取 schema 中的部分列，用于只更新部分列的 merge_insert
+ */
+fn get_sub_schema(names: &[&str]) -> Result<Arc<Schema>, AppError> {
+    let schema = get_schema();
+    let fields = names
+        .iter()
+        .map(|n| schema.field_with_name(n).cloned())
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(Arc::new(Schema::new(fields)))
+}
+
+/**
+ * 转为 sql 字符串字面量
+ */
+pub fn sql_str(s: &str) -> String {
+    format!("'{}'", s.replace('\'', "''"))
+}
+
+/**
+ * 记录中的 path 保存相对 root 的路径，root 整体迁移时只需修改 root 列
+ * 不在 root 下的路径原样保存
+ */
+pub fn stored_path(root: &str, path: &str) -> String {
+    Path::new(path)
+        .strip_prefix(root)
+        .map(|p| p.display().to_string())
+        .unwrap_or_else(|_| path.to_string())
+}
+
+/**
+ * 由 root 与保存的相对路径还原绝对路径，尚未迁移的绝对路径 join 后不变
+ */
+pub fn abs_path(root: &str, stored: &str) -> String {
+    Path::new(root).join(stored).display().to_string()
+}
+
+/**
+ * 绝对路径可能所属的 (root, 相对路径)，root 为 path 的各级上层目录
+ */
+fn split_path(path: &str) -> Vec<(String, String)> {
+    let p = Path::new(path);
+    p.ancestors()
+        .skip(1)
+        .filter_map(|a| {
+            p.strip_prefix(a)
+                .ok()
+                .map(|rel| (a.display().to_string(), rel.display().to_string()))
+        })
+        .collect()
+}
+
+/**
+ * 按绝对路径匹配记录的筛选条件
+ */
+fn path_filter(path: &str) -> String {
+    let filter = split_path(path)
+        .into_iter()
+        .map(|(root, rel)| format!("(root = {} and path = {})", sql_str(&root), sql_str(&rel)))
+        .join(" or ");
+    if filter.is_empty() {
+        "false".to_string()
+    } else {
+        filter
+    }
+}
+
+/**
+ * column 为 dir 下的路径，转义 dir 中的 like 通配符，避免 a_b 匹配到 axb
+ */
+fn under_dir(column: &str, dir: &str) -> String {
+    let prefix = format!("{dir}{}", std::path::MAIN_SEPARATOR)
+        .replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_");
+    format!(
+        "{column} like {} escape '\\'",
+        sql_str(&format!("{prefix}%"))
+    )
+}
+
+/**
+ * 匹配 path 本身及其下所有记录的筛选条件，path 为目录时包含其中的 root
+ */
+fn path_tree_filter(path: &str) -> String {
+    let mut conds = vec![format!("root = {}", sql_str(path)), under_dir("root", path)];
+    conds.extend(split_path(path).into_iter().map(|(root, rel)| {
+        format!(
+            "(root = {} and (path = {} or {}))",
+            sql_str(&root),
+            sql_str(&rel),
+            under_dir("path", &rel)
+        )
+    }));
+    conds.join(" or ")
+}
+
+async fn migrate(table: &Table) -> Result<(), AppError> {
+    let schema = table.schema().await?;
+
+    // 逐列追加，保证列的顺序与 schema 一致
+    for (name, expr) in COLUMN_MIGRATIONS.iter() {
+        if schema.field_with_name(name).is_ok() {
+            continue;
+        }
+
+        log::info!("migrate {IMG_IDX_TABLE_NAME}, add column: {name}");
+        let transform = match expr {
+            Some(expr) => {
+                NewColumnTransform::SqlExpressions(vec![(name.to_string(), expr.to_string())])
+            }
+            None => NewColumnTransform::AllNulls(get_sub_schema(&[name])?),
+        };
+        table.add_columns(transform, None).await?;
+    }
+
+    migrate_abs_paths(table).await?;
+
+    Ok(())
+}
+
+/**
+ * 旧版本数据库的 path 为绝对路径，改为相对 root 的路径
+ */
+async fn migrate_abs_paths(table: &Table) -> Result<(), AppError> {
+    let rows = get_id_root_path(table, "starts_with(path, root)".to_string())
+        .await?
+        .into_iter()
+        .filter(|(_, root, path)| {
+            Path::new(path).is_absolute() && Path::new(path).starts_with(root)
+        })
+        .map(|(id, root, path)| {
+            let path = stored_path(&root, &path);
+            (id, root, path)
+        })
+        .collect::<Vec<_>>();
+
+    if rows.is_empty() {
+        return Ok(());
+    }
+
+    log::info!(
+        "migrate {IMG_IDX_TABLE_NAME}, store {} paths relative to root",
+        rows.len()
+    );
+    merge_paths(table, rows).await
+}
+
+/**
+ * 查询 (id, root, path)，path 为保存的相对路径
+ */
+async fn get_id_root_path(
+    table: &Table,
+    filter: String,
+) -> Result<Vec<(String, String, String)>, AppError> {
+    let mut query = table.query();
+    let qr = query.mut_query();
+    qr.select = Select::Columns(vec![
+        "id".to_string(),
+        "root".to_string(),
+        "path".to_string(),
+    ]);
+    qr.filter = Some(QueryFilter::Sql(filter));
+
+    let stream = query.execute().await?;
+
+    let mut results = Vec::new();
+    // 消费 stream
+    futures::pin_mut!(stream);
+    while let Some(batch) = stream.try_next().await? {
+        let id_array = batch
+            .column_by_name("id")
+            .ok_or_else(|| AppError::Db("Missing column: id".to_string()))?
+            .as_any()
+            .downcast_ref::<arrow_array::StringArray>()
+            .ok_or_else(|| AppError::Db("Invalid type for column: id".to_string()))?;
+
+        let root_array = batch
+            .column_by_name("root")
+            .ok_or_else(|| AppError::Db("Missing column: root".to_string()))?
+            .as_any()
+            .downcast_ref::<arrow_array::StringArray>()
+            .ok_or_else(|| AppError::Db("Invalid type for column: root".to_string()))?;
+
+        let path_array = batch
+            .column_by_name("path")
+            .ok_or_else(|| AppError::Db("Missing column: path".to_string()))?
+            .as_any()
+            .downcast_ref::<arrow_array::StringArray>()
+            .ok_or_else(|| AppError::Db("Invalid type for column: path".to_string()))?;
+
+        for row in 0..batch.num_rows() {
+            results.push((
+                id_array.value(row).to_string(),
+                root_array.value(row).to_string(),
+                path_array.value(row).to_string(),
+            ));
+        }
+    }
+    Ok(results)
+}
+
+/**
+ * 读取 (id, sign)
+ */
+pub async fn get_id_signs(
+    table: &Table,
+    filter: String,
+) -> Result<Vec<(String, String)>, AppError> {
+    let mut query = table.query();
+    let qr = query.mut_query();
+    qr.select = Select::Columns(vec!["id".to_string(), "sign".to_string()]);
+    qr.filter = Some(QueryFilter::Sql(filter));
+
+    let stream = query.execute().await?;
+
+    let mut results = Vec::new();
+    // 消费 stream
+    futures::pin_mut!(stream);
+    while let Some(batch) = stream.try_next().await? {
+        let id_array = batch
+            .column_by_name("id")
+            .ok_or_else(|| AppError::Db("Missing column: id".to_string()))?
+            .as_any()
+            .downcast_ref::<arrow_array::StringArray>()
+            .ok_or_else(|| AppError::Db("Invalid type for column: id".to_string()))?;
+
+        let sign_array = batch
+            .column_by_name("sign")
+            .ok_or_else(|| AppError::Db("Missing column: sign".to_string()))?
+            .as_any()
+            .downcast_ref::<arrow_array::StringArray>()
+            .ok_or_else(|| AppError::Db("Invalid type for column: sign".to_string()))?;
+
+        for row in 0..batch.num_rows() {
+            results.push((
+                id_array.value(row).to_string(),
+                sign_array.value(row).to_string(),
+            ));
+        }
+    }
+    Ok(results)
+}
+
+/**
+ * 按 id 更新 root 与 path 列，rows 为 (id, root, path)
+ */
+async fn merge_paths(table: &Table, rows: Vec<(String, String, String)>) -> Result<(), AppError> {
+    if rows.is_empty() {
+        return Ok(());
+    }
+
+    let mut id_builder = StringBuilder::new();
+    let mut root_builder = StringBuilder::new();
+    let mut path_builder = StringBuilder::new();
+    for (id, root, path) in rows.into_iter() {
+        id_builder.append_value(id);
+        root_builder.append_value(root);
+        path_builder.append_value(path);
+    }
+
+    let schema = get_sub_schema(&["id", "path", "root"])?;
+    let new_data = RecordBatchIterator::new(
+        vec![RecordBatch::try_new(
+            schema.clone(),
+            vec![
+                Arc::new(id_builder.finish()) as ArrayRef,
+                Arc::new(path_builder.finish()) as ArrayRef,
+                Arc::new(root_builder.finish()) as ArrayRef,
+            ],
+        )?]
+        .into_iter()
+        .map(Ok),
+        schema.clone(),
+    );
+
+    let mut merge_insert = table.merge_insert(&["id"]);
+    merge_insert.when_matched_update_all(None);
+    merge_insert.execute(Box::new(new_data)).await?;
+
+    Ok(())
+}
+
+pub(crate) async fn ensure_scalar_indexes(table: &Table) -> Result<(), AppError> {
+    let indices = table.list_indices().await?;
+
+    for (column, kind) in SCALAR_INDEXES.iter() {
+        if indices
+            .iter()
+            .any(|i| i.columns.iter().any(|c| c == column))
+        {
+            continue;
+        }
+
+        log::info!("create scalar index on {IMG_IDX_TABLE_NAME}.{column}");
+        let index = match kind {
+            ScalarIndexKind::BTree => Index::BTree(BTreeIndexBuilder {}),
+            ScalarIndexKind::Bitmap => Index::Bitmap(BitmapIndexBuilder {}),
+        };
+        table.create_index(&[*column], index).execute().await?;
+    }
+
+    Ok(())
+}
+
+/**
+ * 打开或创建 img_idx，不做任何写入，完整性检查通过后再调用 prepare
+ */
+pub async fn open_table(db: &Connection) -> Result<Table, AppError> {
     let tbls = db.table_names().execute().await?;
     if tbls.contains(&IMG_IDX_TABLE_NAME.to_string()) {
         return Ok(db.open_table(IMG_IDX_TABLE_NAME).execute().await?);
     }
 
-    let r = db
+    Ok(db
         .create_empty_table(IMG_IDX_TABLE_NAME, get_schema().clone())
         .execute()
-        .await?;
+        .await?)
+}
 
-    r.create_index(&["id"], lancedb::index::Index::BTree(BTreeIndexBuilder {}))
-        .execute()
-        .await?;
+/**
+ * 只打开已有的表，不创建、不迁移，用于只读访问
+ */
+pub async fn open_existing_table(db: &Connection) -> Result<Table, AppError> {
+    let tbls = db.table_names().execute().await?;
+    if !tbls.contains(&IMG_IDX_TABLE_NAME.to_string()) {
+        return Err(AppError::Config(
+            "the image index is empty, add an imgdir first".to_string(),
+        ));
+    }
+    Ok(db.open_table(IMG_IDX_TABLE_NAME).execute().await?)
+}
+
+/**
+ * 是否已有迁移追加的全部列，未迁移的绝对路径与缺少的标量索引不影响读取
+ */
+pub async fn is_migrated(table: &Table) -> Result<bool, AppError> {
+    let schema = table.schema().await?;
+    Ok(COLUMN_MIGRATIONS
+        .iter()
+        .all(|(name, _)| schema.field_with_name(name).is_ok()))
+}
 
-    Ok(r)
+/**
+ * 迁移旧版本数据库的列与路径，补建缺少的标量索引
+ */
+pub async fn prepare(table: &Table) -> Result<(), AppError> {
+    migrate(table).await?;
+    ensure_scalar_indexes(table).await
 }
 
-async fn check_or_build_idx(table: Arc<Table>) -> Result<(), AppError> {
-    let count = table.count_rows(None).await?;
+/**
+ * 未索引的记录向量元素为 null
+ */
+fn append_vec(builder: &mut FixedSizeListBuilder<Float32Builder>, vec: Option<Vec<f32>>) {
+    if let Some(vec) = vec {
+        vec.into_iter().for_each(|f| {
+            builder.values().append_value(f);
+        });
+    } else {
+        for _ in 0..DIM {
+            builder.values().append_null();
+        }
+    }
+    builder.append(true);
+}
 
-    if count >= IMG_IDX_BUILD_DIVIDER && table.index_stats("embedding").await?.is_none() {
-        table
-            .create_index(
-                &["embedding"],
-                lancedb::index::Index::IvfPq(IvfPqIndexBuilder::default()),
-            )
-            .execute()
-            .await?;
+/**
+ * 重新索引会覆盖整条记录，保留原有的 id，以及用户编辑过的描述
+ * id 不变时标签等信息在修改图片内容后依然保留
+ */
+async fn keep_existing(table: Arc<Table>, records: Vec<ImgIdx>) -> Result<Vec<ImgIdx>, AppError> {
+    let paths = records
+        .iter()
+        .into_group_map_by(|r| r.root.as_str())
+        .into_iter()
+        .map(|(root, rs)| {
+            let paths = rs
+                .iter()
+                .map(|r| sql_str(&stored_path(root, &r.path)))
+                .join(",");
+            format!("(root = {} and path in ({paths}))", sql_str(root))
+        })
+        .join(" or ");
+    if paths.is_empty() {
+        return Ok(records);
     }
-    Ok(())
+
+    let mut ids = get_id_root_path(&table, paths.clone())
+        .await?
+        .into_iter()
+        .map(|(id, root, path)| (abs_path(&root, &path), id))
+        .collect::<std::collections::HashMap<_, _>>();
+
+    let filter = format!(
+        "({paths}) and desc_source <> {}",
+        sql_str(DESC_SOURCE_SERVER)
+    );
+    let mut user = get_records(table, Some(filter))
+        .await?
+        .into_iter()
+        .map(|r| (r.path, (r.desc, r.desc_source)))
+        .collect::<std::collections::HashMap<_, _>>();
+
+    Ok(records
+        .into_iter()
+        .map(|mut r| {
+            if let Some(id) = ids.remove(&r.path) {
+                r.id = id;
+            }
+            if r.desc_source == DESC_SOURCE_SERVER {
+                if let Some((desc, desc_source)) = user.remove(&r.path) {
+                    r.desc = desc;
+                    r.desc_source = desc_source;
+                }
+            }
+            r
+        })
+        .collect())
+}
+
+/**
+ * 根据 root 与 path，保存或插入，path 转为相对 root 的路径
+ * 返回按 records 顺序的 id，已有记录沿用原来的 id
+ */
+pub async fn save_batch(table: Arc<Table>, records: Vec<ImgIdx>) -> Result<Vec<String>, AppError> {
+    let records = keep_existing(table.clone(), records).await?;
+    let ids = records.iter().map(|r| r.id.clone()).collect();
+    let batch = record_batch(records)?;
+
+    let reader = Box::new(RecordBatchIterator::new(
+        vec![batch].into_iter().map(Ok),
+        get_schema().clone(),
+    ));
+
+    let mut merge_insert = table.merge_insert(&["root", "path"]);
+    merge_insert
+        .when_matched_update_all(None)
+        .when_not_matched_insert_all();
+
+    merge_insert.execute(reader).await?;
+
+    let table = table.clone();
+    tauri::async_runtime::spawn(async move {
+        if let Err(e) = vector_index::check_or_build(table).await {
+            log::error!("check_or_build vector index error: {e}");
+        }
+    });
+
+    Ok(ids)
 }
 
 /**
- * 根据path，保存或插入
+ * 记录转为完整 schema 的 RecordBatch，path 转为相对 root 的路径
  */
-pub async fn save_batch(table: Arc<Table>, records: Vec<ImgIdx>) -> Result<(), AppError> {
+pub(crate) fn record_batch(records: Vec<ImgIdx>) -> Result<RecordBatch, AppError> {
     let mut id_builder = StringBuilder::new();
     let mut name_builder = StringBuilder::new();
     let mut path_builder = StringBuilder::new();
@@ -123,6 +671,11 @@ pub async fn save_batch(table: Arc<Table>, records: Vec<ImgIdx>) -> Result<(), A
         Float32Builder::with_capacity(DIM as usize * records.len()),
         DIM,
     );
+    let mut error_builder = StringBuilder::new();
+    let mut status_builder = StringBuilder::new();
+    let mut desc_source_builder = StringBuilder::new();
+    let mut img_vec_builder = FixedSizeListBuilder::new(Float32Builder::new(), DIM);
+    let mut suggested_name_builder = StringBuilder::new();
 
     for ImgIdx {
         id,
@@ -134,27 +687,28 @@ pub async fn save_batch(table: Arc<Table>, records: Vec<ImgIdx>) -> Result<(), A
         idxed,
         desc,
         vec,
+        error,
+        status,
+        desc_source,
+        img_vec,
+        suggested_name,
     } in records.into_iter()
     {
         id_builder.append_value(id);
         name_builder.append_value(name);
-        path_builder.append_value(path);
+        path_builder.append_value(stored_path(&root, &path));
         root_builder.append_value(root);
         sign_builder.append_value(sign);
         thumbnail_builder.append_value(thumbnail);
         idxed_builder.append_value(idxed);
         desc_builder.append_option(desc);
 
-        if let Some(vec) = vec {
-            vec.into_iter().for_each(|f| {
-                vec_builder.values().append_value(f);
-            });
-        } else {
-            for _ in 0..DIM {
-                vec_builder.values().append_null();
-            }
-        }
-        vec_builder.append(true);
+        append_vec(&mut vec_builder, vec);
+        error_builder.append_option(error);
+        status_builder.append_value(status);
+        desc_source_builder.append_value(desc_source);
+        append_vec(&mut img_vec_builder, img_vec);
+        suggested_name_builder.append_option(suggested_name);
     }
 
     let schema = get_schema();
@@ -171,80 +725,88 @@ pub async fn save_batch(table: Arc<Table>, records: Vec<ImgIdx>) -> Result<(), A
             Arc::new(idxed_builder.finish()) as ArrayRef,
             Arc::new(desc_builder.finish()) as ArrayRef,
             Arc::new(vec_builder.finish()) as ArrayRef,
+            Arc::new(error_builder.finish()) as ArrayRef,
+            Arc::new(status_builder.finish()) as ArrayRef,
+            Arc::new(desc_source_builder.finish()) as ArrayRef,
+            Arc::new(img_vec_builder.finish()) as ArrayRef,
+            Arc::new(suggested_name_builder.finish()) as ArrayRef,
         ],
     )?;
 
-    let reader = Box::new(RecordBatchIterator::new(
-        vec![batch].into_iter().map(Ok),
-        schema.clone(),
-    ));
-
-    let mut merge_insert = table.merge_insert(&["path"]);
-    merge_insert
-        .when_matched_update_all(None)
-        .when_not_matched_insert_all();
-
-    merge_insert.execute(reader).await?;
-
-    let table = table.clone();
-    tauri::async_runtime::spawn(async move {
-        if let Err(e) = check_or_build_idx(table).await {
-            log::error!("check_or_build_idx error: {e}");
-        }
-    });
-
-    Ok(())
-}
+    Ok(batch)
+}
 
+/**
+ * 索引结果，重命名后的路径由 update_path 单独更新
+ */
 pub struct IndexModel {
     pub id: String,
-    pub name: String,
-    pub path: String,
     pub desc: String,
     pub vec: Vec<f32>,
+    pub desc_source: String,
+    // 服务端返回的图片向量，vec 未混合描述时与 vec 相同
+    pub img_vec: Vec<f32>,
+    pub suggested_name: Option<String>,
 }
 pub async fn save_indexes(table: Arc<Table>, indexes: Vec<IndexModel>) -> Result<(), AppError> {
     let mut id_builder = StringBuilder::new();
-    let mut name_builder = StringBuilder::new();
-    let mut path_builder = StringBuilder::new();
     let mut idxed_builder = BooleanBuilder::new();
     let mut desc_builder = StringBuilder::new();
     let mut vec_builder = FixedSizeListBuilder::new(
         Float32Builder::with_capacity(DIM as usize * indexes.len()),
         DIM,
     );
+    let mut error_builder = StringBuilder::new();
+    let mut status_builder = StringBuilder::new();
+    let mut desc_source_builder = StringBuilder::new();
+    let mut img_vec_builder = FixedSizeListBuilder::new(Float32Builder::new(), DIM);
+    let mut suggested_name_builder = StringBuilder::new();
 
     for IndexModel {
         id,
-        name,
-        path,
         desc,
         vec,
+        desc_source,
+        img_vec,
+        suggested_name,
     } in indexes.into_iter()
     {
         id_builder.append_value(id);
-        name_builder.append_value(name);
-        path_builder.append_value(path);
         idxed_builder.append_value(true);
         desc_builder.append_value(desc);
 
-        vec.into_iter().for_each(|f| {
-            vec_builder.values().append_value(f);
-        });
-        vec_builder.append(true);
+        append_vec(&mut vec_builder, Some(vec));
+        error_builder.append_null();
+        status_builder.append_value(STATUS_INDEXED);
+        desc_source_builder.append_value(desc_source);
+        append_vec(&mut img_vec_builder, Some(img_vec));
+        suggested_name_builder.append_option(suggested_name);
     }
 
-    let schema = get_schema();
+    let schema = get_sub_schema(&[
+        "id",
+        "idxed",
+        "desc",
+        "embedding",
+        "error",
+        "status",
+        "desc_source",
+        "img_embedding",
+        "suggested_name",
+    ])?;
 
     let batch = RecordBatch::try_new(
         schema.clone(),
         vec![
             Arc::new(id_builder.finish()) as ArrayRef,
-            Arc::new(name_builder.finish()) as ArrayRef,
-            Arc::new(path_builder.finish()) as ArrayRef,
             Arc::new(idxed_builder.finish()) as ArrayRef,
             Arc::new(desc_builder.finish()) as ArrayRef,
             Arc::new(vec_builder.finish()) as ArrayRef,
+            Arc::new(error_builder.finish()) as ArrayRef,
+            Arc::new(status_builder.finish()) as ArrayRef,
+            Arc::new(desc_source_builder.finish()) as ArrayRef,
+            Arc::new(img_vec_builder.finish()) as ArrayRef,
+            Arc::new(suggested_name_builder.finish()) as ArrayRef,
         ],
     )?;
 
@@ -272,69 +834,108 @@ pub struct ImgSearchResult {
     pub idxed: bool,
     pub desc: Option<String>,
     pub score: Option<f32>,
+    pub error: Option<String>,
+    pub status: String,
+    pub sign: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub suggested_name: Option<String>,
+    // 标签、相册与收藏，由命令按需附加
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub meta: Option<meta::ImgMeta>,
+    // 所在 root 的卷未连接，文件暂时无法打开
+    #[serde(default)]
+    pub offline: bool,
 }
 
 fn map_batch_to_searchresult(batch: &RecordBatch) -> Result<Vec<ImgSearchResult>, AppError> {
     let id_array = batch
         .column_by_name("id")
-        .ok_or_else(|| AppError::Internal("Missing column: id".to_string()))?
+        .ok_or_else(|| AppError::Db("Missing column: id".to_string()))?
         .as_any()
         .downcast_ref::<arrow_array::StringArray>()
-        .ok_or_else(|| AppError::Internal("Invalid type for column: id".to_string()))?;
+        .ok_or_else(|| AppError::Db("Invalid type for column: id".to_string()))?;
 
     let name_array = batch
         .column_by_name("name")
-        .ok_or_else(|| AppError::Internal("Missing column: name".to_string()))?
+        .ok_or_else(|| AppError::Db("Missing column: name".to_string()))?
         .as_any()
         .downcast_ref::<arrow_array::StringArray>()
-        .ok_or_else(|| AppError::Internal("Invalid type for column: name".to_string()))?;
+        .ok_or_else(|| AppError::Db("Invalid type for column: name".to_string()))?;
 
     let path_array = batch
         .column_by_name("path")
-        .ok_or_else(|| AppError::Internal("Missing column: path".to_string()))?
+        .ok_or_else(|| AppError::Db("Missing column: path".to_string()))?
         .as_any()
         .downcast_ref::<arrow_array::StringArray>()
-        .ok_or_else(|| AppError::Internal("Invalid type for column: path".to_string()))?;
+        .ok_or_else(|| AppError::Db("Invalid type for column: path".to_string()))?;
 
     let root_array = batch
         .column_by_name("root")
-        .ok_or_else(|| AppError::Internal("Missing column: root".to_string()))?
+        .ok_or_else(|| AppError::Db("Missing column: root".to_string()))?
         .as_any()
         .downcast_ref::<arrow_array::StringArray>()
-        .ok_or_else(|| AppError::Internal("Invalid type for column: root".to_string()))?;
+        .ok_or_else(|| AppError::Db("Invalid type for column: root".to_string()))?;
 
     let thumbnail_array = batch
         .column_by_name("thumbnail")
-        .ok_or_else(|| AppError::Internal("Missing column: thumbnail".to_string()))?
+        .ok_or_else(|| AppError::Db("Missing column: thumbnail".to_string()))?
         .as_any()
         .downcast_ref::<arrow_array::StringArray>()
-        .ok_or_else(|| AppError::Internal("Invalid type for column: thumbnail".to_string()))?;
+        .ok_or_else(|| AppError::Db("Invalid type for column: thumbnail".to_string()))?;
 
     let idxed_array = batch
         .column_by_name("idxed")
-        .ok_or_else(|| AppError::Internal("Missing column: idxed".to_string()))?
+        .ok_or_else(|| AppError::Db("Missing column: idxed".to_string()))?
         .as_any()
         .downcast_ref::<arrow_array::BooleanArray>()
-        .ok_or_else(|| AppError::Internal("Invalid type for column: idxed".to_string()))?;
+        .ok_or_else(|| AppError::Db("Invalid type for column: idxed".to_string()))?;
 
     let desc_array = batch
         .column_by_name("desc")
-        .ok_or_else(|| AppError::Internal("Missing column: desc".to_string()))?
+        .ok_or_else(|| AppError::Db("Missing column: desc".to_string()))?
+        .as_any()
+        .downcast_ref::<arrow_array::StringArray>()
+        .ok_or_else(|| AppError::Db("Invalid type for column: desc".to_string()))?;
+
+    let error_array = batch
+        .column_by_name("error")
+        .ok_or_else(|| AppError::Db("Missing column: error".to_string()))?
+        .as_any()
+        .downcast_ref::<arrow_array::StringArray>()
+        .ok_or_else(|| AppError::Db("Invalid type for column: error".to_string()))?;
+
+    let status_array = batch
+        .column_by_name("status")
+        .ok_or_else(|| AppError::Db("Missing column: status".to_string()))?
+        .as_any()
+        .downcast_ref::<arrow_array::StringArray>()
+        .ok_or_else(|| AppError::Db("Invalid type for column: status".to_string()))?;
+
+    let sign_array = batch
+        .column_by_name("sign")
+        .ok_or_else(|| AppError::Db("Missing column: sign".to_string()))?
+        .as_any()
+        .downcast_ref::<arrow_array::StringArray>()
+        .ok_or_else(|| AppError::Db("Invalid type for column: sign".to_string()))?;
+
+    let suggested_name_array = batch
+        .column_by_name("suggested_name")
+        .ok_or_else(|| AppError::Db("Missing column: suggested_name".to_string()))?
         .as_any()
         .downcast_ref::<arrow_array::StringArray>()
-        .ok_or_else(|| AppError::Internal("Invalid type for column: desc".to_string()))?;
+        .ok_or_else(|| AppError::Db("Invalid type for column: suggested_name".to_string()))?;
 
     let score_array_result = batch
         .column_by_name("_distance")
         .or_else(|| batch.column_by_name("score"))
-        .ok_or_else(|| AppError::Internal("Missing column: score or _distance".to_string()));
+        .ok_or_else(|| AppError::Db("Missing column: score or _distance".to_string()));
 
     let score_array = if let Ok(score_array) = score_array_result {
         Some(
             score_array
                 .as_any()
                 .downcast_ref::<arrow_array::Float32Array>()
-                .ok_or_else(|| AppError::Internal("Invalid type for column: score".to_string()))?,
+                .ok_or_else(|| AppError::Db("Invalid type for column: score".to_string()))?,
         )
     } else {
         None
@@ -345,8 +946,8 @@ fn map_batch_to_searchresult(batch: &RecordBatch) -> Result<Vec<ImgSearchResult>
     for row in 0..batch.num_rows() {
         let id = id_array.value(row).to_string();
         let name = name_array.value(row).to_string();
-        let path = path_array.value(row).to_string();
         let root = root_array.value(row).to_string();
+        let path = abs_path(&root, path_array.value(row));
         let thumbnail = thumbnail_array.value(row).to_string();
         let idxed = idxed_array.value(row);
         let desc = if desc_array.is_null(row) {
@@ -356,6 +957,18 @@ fn map_batch_to_searchresult(batch: &RecordBatch) -> Result<Vec<ImgSearchResult>
         };
 
         let score = score_array.map(|score_array| score_array.value(row));
+        let error = if error_array.is_null(row) {
+            None
+        } else {
+            Some(error_array.value(row).to_string())
+        };
+        let status = status_array.value(row).to_string();
+        let sign = sign_array.value(row).to_string();
+        let suggested_name = if suggested_name_array.is_null(row) {
+            None
+        } else {
+            Some(suggested_name_array.value(row).to_string())
+        };
 
         res.push(ImgSearchResult {
             id,
@@ -366,25 +979,180 @@ fn map_batch_to_searchresult(batch: &RecordBatch) -> Result<Vec<ImgSearchResult>
             idxed,
             desc,
             score,
+            error,
+            status,
+            sign,
+            suggested_name,
+            meta: None,
+            offline: false,
         });
     }
 
     Ok(res)
 }
 
+/**
+ * 完整的索引记录，包含签名与向量
+ */
+fn map_batch_to_imgidx(batch: &RecordBatch) -> Result<Vec<ImgIdx>, AppError> {
+    let results = map_batch_to_searchresult(batch)?;
+
+    let sign_array = batch
+        .column_by_name("sign")
+        .ok_or_else(|| AppError::Db("Missing column: sign".to_string()))?
+        .as_any()
+        .downcast_ref::<arrow_array::StringArray>()
+        .ok_or_else(|| AppError::Db("Invalid type for column: sign".to_string()))?;
+
+    let desc_source_array = batch
+        .column_by_name("desc_source")
+        .ok_or_else(|| AppError::Db("Missing column: desc_source".to_string()))?
+        .as_any()
+        .downcast_ref::<arrow_array::StringArray>()
+        .ok_or_else(|| AppError::Db("Invalid type for column: desc_source".to_string()))?;
+
+    let mut res = Vec::with_capacity(results.len());
+    for (row, r) in results.into_iter().enumerate() {
+        let vec = vector_at(batch, "embedding", row)?;
+        let img_vec = vector_at(batch, "img_embedding", row)?;
+
+        res.push(ImgIdx {
+            id: r.id,
+            name: r.name,
+            path: r.path,
+            root: r.root,
+            sign: sign_array.value(row).to_string(),
+            thumbnail: r.thumbnail,
+            idxed: r.idxed,
+            desc: r.desc,
+            vec,
+            error: r.error,
+            status: r.status,
+            desc_source: desc_source_array.value(row).to_string(),
+            img_vec,
+            suggested_name: r.suggested_name,
+        });
+    }
+
+    Ok(res)
+}
+
+fn vector_at(batch: &RecordBatch, name: &str, row: usize) -> Result<Option<Vec<f32>>, AppError> {
+    let array = batch
+        .column_by_name(name)
+        .ok_or_else(|| AppError::Db(format!("Missing column: {name}")))?
+        .as_any()
+        .downcast_ref::<arrow_array::FixedSizeListArray>()
+        .ok_or_else(|| AppError::Db(format!("Invalid type for column: {name}")))?;
+
+    if array.is_null(row) {
+        return Ok(None);
+    }
+
+    let values = array.value(row);
+    let values = values
+        .as_any()
+        .downcast_ref::<arrow_array::Float32Array>()
+        .ok_or_else(|| AppError::Db(format!("Invalid type for column: {name}")))?;
+    // 未索引的记录向量元素为 null
+    if values.null_count() > 0 {
+        Ok(None)
+    } else {
+        Ok(Some(values.values().to_vec()))
+    }
+}
+
+/**
+ * 更新描述与向量，vecs 为 (embedding, img_embedding)，为 None 时不修改向量
+ */
+pub async fn update_desc(
+    table: Arc<Table>,
+    id: &str,
+    desc: &str,
+    desc_source: &str,
+    vecs: Option<(Vec<f32>, Vec<f32>)>,
+) -> Result<(), AppError> {
+    let mut id_builder = StringBuilder::new();
+    let mut desc_builder = StringBuilder::new();
+    let mut desc_source_builder = StringBuilder::new();
+    id_builder.append_value(id);
+    desc_builder.append_value(desc);
+    desc_source_builder.append_value(desc_source);
+
+    let mut names = vec!["id", "desc", "desc_source"];
+    let mut columns = vec![
+        Arc::new(id_builder.finish()) as ArrayRef,
+        Arc::new(desc_builder.finish()) as ArrayRef,
+        Arc::new(desc_source_builder.finish()) as ArrayRef,
+    ];
+
+    if let Some((vec, img_vec)) = vecs {
+        let mut vec_builder = FixedSizeListBuilder::new(Float32Builder::new(), DIM);
+        let mut img_vec_builder = FixedSizeListBuilder::new(Float32Builder::new(), DIM);
+        append_vec(&mut vec_builder, Some(vec));
+        append_vec(&mut img_vec_builder, Some(img_vec));
+
+        names.extend(["embedding", "img_embedding"]);
+        columns.push(Arc::new(vec_builder.finish()) as ArrayRef);
+        columns.push(Arc::new(img_vec_builder.finish()) as ArrayRef);
+    }
+
+    let schema = get_sub_schema(&names)?;
+    let batch = RecordBatch::try_new(schema.clone(), columns)?;
+
+    let reader = Box::new(RecordBatchIterator::new(
+        vec![batch].into_iter().map(Ok),
+        schema.clone(),
+    ));
+
+    let mut merge_insert = table.merge_insert(&["id"]);
+    merge_insert.when_matched_update_all(None);
+
+    merge_insert.execute(reader).await?;
+
+    Ok(())
+}
+
+pub async fn get_records(
+    table: Arc<Table>,
+    filter: Option<String>,
+) -> Result<Vec<ImgIdx>, AppError> {
+    let stream = match filter {
+        Some(filter) => table.query().only_if(filter).execute().await?,
+        None => table.query().execute().await?,
+    }
+    .into_arrow()?;
+    let mut results = Vec::new();
+
+    // 消费 stream
+    futures::pin_mut!(stream);
+    while let Some(batch) = stream.try_next().await? {
+        let mut items = map_batch_to_imgidx(&batch)?;
+        results.append(&mut items);
+    }
+    Ok(results)
+}
+
 pub async fn get_all(
     table: Arc<Table>,
     idxed: Option<bool>,
+    filter: Option<String>,
 ) -> Result<Vec<ImgSearchResult>, AppError> {
-    let stream = if let Some(idxed) = idxed {
+    let filter = [idxed.map(|idxed| format!("idxed = {idxed}")), filter]
+        .into_iter()
+        .flatten()
+        .map(|f| format!("({f})"))
+        .join(" and ");
+
+    let stream = if filter.is_empty() {
+        table.query().execute().await?.into_arrow()?
+    } else {
         table
             .query()
-            .only_if(format!("idxed = {}", idxed))
+            .only_if(filter)
             .execute()
             .await?
             .into_arrow()?
-    } else {
-        table.query().execute().await?.into_arrow()?
     };
     let mut results = Vec::new();
 
@@ -396,17 +1164,97 @@ pub async fn get_all(
     }
     Ok(results)
 }
-pub async fn search(
+pub async fn get_by_status(
     table: Arc<Table>,
-    v: &[f32],
-    top: usize,
+    status: &str,
+) -> Result<Vec<ImgSearchResult>, AppError> {
+    let stream = table
+        .query()
+        .only_if(format!("status = {}", sql_str(status)))
+        .execute()
+        .await?
+        .into_arrow()?;
+    let mut results = Vec::new();
+
+    // 消费 stream
+    futures::pin_mut!(stream);
+    while let Some(batch) = stream.try_next().await? {
+        let mut items = map_batch_to_searchresult(&batch)?;
+        results.append(&mut items);
+    }
+    Ok(results)
+}
+
+pub async fn get_by_path(table: Arc<Table>, path: &str) -> Result<Vec<ImgSearchResult>, AppError> {
+    let stream = table
+        .query()
+        .only_if(path_filter(path))
+        .execute()
+        .await?
+        .into_arrow()?;
+    let mut results = Vec::new();
+
+    // 消费 stream
+    futures::pin_mut!(stream);
+    while let Some(batch) = stream.try_next().await? {
+        let mut items = map_batch_to_searchresult(&batch)?;
+        results.append(&mut items);
+    }
+    Ok(results)
+}
+
+pub async fn get_by_ids(
+    table: Arc<Table>,
+    ids: &[String],
 ) -> Result<Vec<ImgSearchResult>, AppError> {
+    if ids.is_empty() {
+        return Ok(vec![]);
+    }
+
+    let ids = ids.iter().map(|id| sql_str(id)).join(",");
     let stream = table
-        .vector_search(v)?
-        .limit(top)
+        .query()
+        .only_if(format!("id in ({ids})"))
         .execute()
         .await?
         .into_arrow()?;
+    let mut results = Vec::new();
+
+    // 消费 stream
+    futures::pin_mut!(stream);
+    while let Some(batch) = stream.try_next().await? {
+        let mut items = map_batch_to_searchresult(&batch)?;
+        results.append(&mut items);
+    }
+    Ok(results)
+}
+
+/**
+ * nprobes: 搜索的分区数，越大越准越慢
+ * refine_factor: 先取 top * refine_factor 条，再用原始向量重新排序
+ * filter: 在 img_idx 上的筛选条件
+ */
+pub async fn search(
+    table: Arc<Table>,
+    v: &[f32],
+    top: usize,
+    nprobes: Option<usize>,
+    refine_factor: Option<u32>,
+    filter: Option<String>,
+) -> Result<Vec<ImgSearchResult>, AppError> {
+    let mut query = table.vector_search(v)?.limit(top);
+    // 先筛选再搜索，保证返回 top 条
+    if let Some(filter) = filter {
+        query = query.only_if(filter).prefilter(true);
+    }
+    if let Some(n) = nprobes {
+        query = query.nprobes(n);
+    }
+    if let Some(f) = refine_factor {
+        query = query.refine_factor(f);
+    }
+
+    let stream = query.execute().await?.into_arrow()?;
 
     let mut results = Vec::new();
 
@@ -419,96 +1267,211 @@ pub async fn search(
     Ok(results)
 }
 
+/**
+ * 目录重命名，修改其中图片的相对路径
+ * 目录中的 root 本身被移动时由 relocate_root 处理
+ */
 pub async fn update_path_prefix(table: Arc<Table>, old: &str, new: &str) -> Result<(), AppError> {
+    let filter = split_path(old)
+        .into_iter()
+        .map(|(root, rel)| {
+            format!(
+                "(root = {} and {})",
+                sql_str(&root),
+                under_dir("path", &rel)
+            )
+        })
+        .join(" or ");
+    if filter.is_empty() {
+        return Ok(());
+    }
+
+    let rows = get_id_root_path(&table, filter)
+        .await?
+        .into_iter()
+        .filter_map(|(id, root, path)| {
+            let abs = abs_path(&root, &path);
+            let rest = Path::new(&abs).strip_prefix(old).ok()?;
+            let moved = Path::new(new).join(rest).display().to_string();
+            let path = stored_path(&root, &moved);
+            Some((id, root, path))
+        })
+        .collect::<Vec<_>>();
+
+    merge_paths(&table, rows).await
+}
+
+pub async fn update_path(table: Arc<Table>, old: &str, new: &str) -> Result<(), AppError> {
+    let newname = file_name(Path::new(new))?;
+    let roots = get_by_path(table.clone(), old)
+        .await?
+        .into_iter()
+        .map(|r| r.root)
+        .unique()
+        .collect::<Vec<_>>();
+
+    for root in roots {
+        table
+            .update()
+            .column("path", sql_str(&stored_path(&root, new)))
+            .column("name", sql_str(&newname))
+            .only_if(format!(
+                "root = {} and path = {}",
+                sql_str(&root),
+                sql_str(&stored_path(&root, old))
+            ))
+            .execute()
+            .await?;
+    }
+    Ok(())
+}
+
+pub async fn remove_by_root(table: Arc<Table>, root: &str) -> Result<(), AppError> {
+    table.delete(&format!("root = {}", sql_str(root))).await?;
+    Ok(())
+}
+
+/**
+ * root 所在目录被移动后，记录改为属于新的 root，相对路径不变
+ * thumbnail_dirs 为缩略图目录的 (原目录, 新目录)，位于原目录的缩略图路径随之修改
+ */
+pub async fn relocate_root(
+    table: Arc<Table>,
+    old: &str,
+    new: &str,
+    thumbnail_dirs: Option<(&Path, &Path)>,
+) -> Result<(), AppError> {
+    table
+        .update()
+        .only_if(format!("root = {}", sql_str(old)))
+        .column("root", sql_str(new))
+        .execute()
+        .await?;
+
+    let Some((from, to)) = thumbnail_dirs else {
+        return Ok(());
+    };
+
     let mut query = table.query();
     let qr = query.mut_query();
-    qr.select = Select::Columns(vec!["id".to_string(), "path".to_string()]);
-    qr.filter = Some(QueryFilter::Sql(format!("path like '{old}%'")));
+    qr.select = Select::Columns(vec!["id".to_string(), "thumbnail".to_string()]);
+    qr.filter = Some(QueryFilter::Sql(format!("root = {}", sql_str(new))));
 
     let stream = query.execute().await?;
 
-    let mut results = Vec::new();
-    // 消费 stream
+    let mut id_builder = StringBuilder::new();
+    let mut thumbnail_builder = StringBuilder::new();
     futures::pin_mut!(stream);
     while let Some(batch) = stream.try_next().await? {
         let id_array = batch
             .column_by_name("id")
-            .ok_or_else(|| AppError::Internal("Missing column: id".to_string()))?
+            .ok_or_else(|| AppError::Db("Missing column: id".to_string()))?
             .as_any()
             .downcast_ref::<arrow_array::StringArray>()
-            .ok_or_else(|| AppError::Internal("Invalid type for column: id".to_string()))?;
+            .ok_or_else(|| AppError::Db("Invalid type for column: id".to_string()))?;
 
-        let path_array = batch
-            .column_by_name("path")
-            .ok_or_else(|| AppError::Internal("Missing column: path".to_string()))?
+        let thumbnail_array = batch
+            .column_by_name("thumbnail")
+            .ok_or_else(|| AppError::Db("Missing column: thumbnail".to_string()))?
             .as_any()
             .downcast_ref::<arrow_array::StringArray>()
-            .ok_or_else(|| AppError::Internal("Invalid type for column: path".to_string()))?;
+            .ok_or_else(|| AppError::Db("Invalid type for column: thumbnail".to_string()))?;
 
         for row in 0..batch.num_rows() {
-            let id = id_array.value(row).to_string();
-            let path = path_array.value(row).to_string();
-
-            results.push((id, path));
+            if thumbnail_array.is_null(row) {
+                continue;
+            }
+            if let Some(thumbnail) = moved_path(thumbnail_array.value(row), from, to) {
+                id_builder.append_value(id_array.value(row));
+                thumbnail_builder.append_value(thumbnail);
+            }
         }
     }
-    let mut id_builder = StringBuilder::new();
-    let mut path_builder = StringBuilder::new();
-    results.into_iter().for_each(|(id, path)| {
-        let p = path.replace(old, new);
 
-        id_builder.append_value(id);
-        path_builder.append_value(p);
-    });
+    let ids = id_builder.finish();
+    if ids.is_empty() {
+        return Ok(());
+    }
 
-    let schema = Arc::new(Schema::new(vec![
-        Field::new("id", DataType::FixedSizeBinary(16), false),
-        Field::new("path", DataType::Utf8, false),
-    ]));
+    let schema = get_sub_schema(&["id", "thumbnail"])?;
     let new_data = RecordBatchIterator::new(
         vec![RecordBatch::try_new(
             schema.clone(),
             vec![
-                Arc::new(id_builder.finish()),
-                Arc::new(path_builder.finish()),
+                Arc::new(ids) as ArrayRef,
+                Arc::new(thumbnail_builder.finish()) as ArrayRef,
             ],
-        )
-        .unwrap()]
+        )?]
         .into_iter()
         .map(Ok),
         schema.clone(),
     );
-    // Perform an upsert operation
-    table
-        .merge_insert(&["id"])
-        .execute(Box::new(new_data))
-        .await?;
+
+    let mut merge_insert = table.merge_insert(&["id"]);
+    merge_insert.when_matched_update_all(None);
+    merge_insert.execute(Box::new(new_data)).await?;
 
     Ok(())
 }
 
-pub async fn update_path(table: Arc<Table>, old: &str, new: &str) -> Result<(), AppError> {
-    let newname = Path::new(new).file_name().unwrap().to_str().unwrap();
-    table
-        .update()
-        .column("path", format!("'{new}'"))
-        .column("name", format!("'{newname}'"))
-        .only_if(format!("path = '{old}'"))
-        .execute()
-        .await?;
-    Ok(())
+/**
+ * path 位于 from 目录下时，返回移动到 to 目录后的路径
+ */
+fn moved_path(path: &str, from: &Path, to: &Path) -> Option<String> {
+    Path::new(path)
+        .strip_prefix(from)
+        .ok()
+        .map(|rest| to.join(rest).display().to_string())
 }
 
-pub async fn remove_by_root(table: Arc<Table>, root: &str) -> Result<(), AppError> {
-    table.delete(&format!("root = '{root}'")).await?;
-    Ok(())
+/**
+ * 将 prefix 目录下、root 属于 from 的记录改为属于 to，相对路径随之修改
+ */
+pub async fn reassign_root(
+    table: Arc<Table>,
+    prefix: &str,
+    from: &[&str],
+    to: &str,
+) -> Result<(), AppError> {
+    let filter = from
+        .iter()
+        .filter_map(|r| {
+            let rel = Path::new(prefix)
+                .strip_prefix(r)
+                .ok()?
+                .display()
+                .to_string();
+            Some(if rel.is_empty() {
+                format!("root = {}", sql_str(r))
+            } else {
+                format!("(root = {} and {})", sql_str(r), under_dir("path", &rel))
+            })
+        })
+        .join(" or ");
+    if filter.is_empty() {
+        return Ok(());
+    }
+
+    let rows = get_id_root_path(&table, filter)
+        .await?
+        .into_iter()
+        .map(|(id, root, path)| {
+            let path = stored_path(to, &abs_path(&root, &path));
+            (id, to.to_string(), path)
+        })
+        .collect::<Vec<_>>();
+
+    merge_paths(&table, rows).await
 }
 
+/**
+ * 删除 path 及其下的记录，返回缩略图
+ */
 pub async fn remove_path_like(table: Arc<Table>, path: &str) -> Result<Vec<String>, AppError> {
     let mut query = table.query();
     let qr = query.mut_query();
     qr.select = Select::Columns(vec!["thumbnail".to_string()]);
-    let sql = format!("path = '{path}' or path like '{path}%'");
+    let sql = path_tree_filter(path);
     qr.filter = Some(QueryFilter::Sql(sql.clone()));
 
     let stream = query.execute().await?;
@@ -519,10 +1482,10 @@ pub async fn remove_path_like(table: Arc<Table>, path: &str) -> Result<Vec<Strin
     while let Some(batch) = stream.try_next().await? {
         let thumbnail_array = batch
             .column_by_name("thumbnail")
-            .ok_or_else(|| AppError::Internal("Missing column: thumbnail".to_string()))?
+            .ok_or_else(|| AppError::Db("Missing column: thumbnail".to_string()))?
             .as_any()
             .downcast_ref::<arrow_array::StringArray>()
-            .ok_or_else(|| AppError::Internal("Invalid type for column: thumbnail".to_string()))?;
+            .ok_or_else(|| AppError::Db("Invalid type for column: thumbnail".to_string()))?;
 
         for row in 0..batch.num_rows() {
             let thumbnail = thumbnail_array.value(row).to_string();
@@ -534,3 +1497,159 @@ pub async fn remove_path_like(table: Arc<Table>, path: &str) -> Result<Vec<Strin
 
     Ok(results)
 }
+
+pub async fn mark_failed(table: Arc<Table>, ids: &[String], error: &str) -> Result<(), AppError> {
+    if ids.is_empty() {
+        return Ok(());
+    }
+
+    let ids = ids.iter().map(|id| sql_str(id)).join(",");
+    table
+        .update()
+        .column("status", sql_str(STATUS_FAILED))
+        .column("error", sql_str(error))
+        .only_if(format!("id in ({ids})"))
+        .execute()
+        .await?;
+    Ok(())
+}
+
+pub async fn get_embedding(table: Arc<Table>, path: &str) -> Result<Option<Vec<f32>>, AppError> {
+    let mut query = table.query();
+    let qr = query.mut_query();
+    qr.select = Select::Columns(vec!["embedding".to_string()]);
+    qr.filter = Some(QueryFilter::Sql(format!(
+        "({}) and status = {}",
+        path_filter(path),
+        sql_str(STATUS_INDEXED)
+    )));
+
+    let stream = query.execute().await?;
+
+    // 消费 stream
+    futures::pin_mut!(stream);
+    while let Some(batch) = stream.try_next().await? {
+        let vec_array = batch
+            .column_by_name("embedding")
+            .ok_or_else(|| AppError::Db("Missing column: embedding".to_string()))?
+            .as_any()
+            .downcast_ref::<arrow_array::FixedSizeListArray>()
+            .ok_or_else(|| AppError::Db("Invalid type for column: embedding".to_string()))?;
+
+        for row in 0..batch.num_rows() {
+            if vec_array.is_null(row) {
+                continue;
+            }
+            let values = vec_array.value(row);
+            let values = values
+                .as_any()
+                .downcast_ref::<arrow_array::Float32Array>()
+                .ok_or_else(|| AppError::Db("Invalid type for column: embedding".to_string()))?;
+            return Ok(Some(values.values().to_vec()));
+        }
+    }
+    Ok(None)
+}
+
+pub async fn count(table: Arc<Table>, filter: Option<String>) -> Result<usize, AppError> {
+    Ok(table.count_rows(filter).await?)
+}
+
+pub async fn clear_suggested_name(table: Arc<Table>, ids: &[String]) -> Result<(), AppError> {
+    if ids.is_empty() {
+        return Ok(());
+    }
+
+    let ids = ids.iter().map(|id| sql_str(id)).join(",");
+    table
+        .update()
+        .only_if(format!("id in ({ids})"))
+        .column("suggested_name", "CAST(NULL AS string)")
+        .execute()
+        .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(unix)]
+    #[test]
+    fn stored_path_round_trips() {
+        for (root, path, stored) in [
+            ("/imgs", "/imgs/a.jpg", "a.jpg"),
+            ("/imgs", "/imgs/sub/b.png", "sub/b.png"),
+            ("/imgs/", "/imgs/c.webp", "c.webp"),
+        ] {
+            assert_eq!(stored_path(root, path), stored);
+            assert_eq!(abs_path(root, stored), path);
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn stored_path_keeps_foreign_paths() {
+        // /imgs2 不在 /imgs 下，按组件比较而非字符串前缀
+        assert_eq!(stored_path("/imgs", "/imgs2/a.jpg"), "/imgs2/a.jpg");
+        // 尚未迁移的绝对路径还原后不变
+        assert_eq!(abs_path("/imgs", "/other/a.jpg"), "/other/a.jpg");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn split_path_lists_ancestors() {
+        assert_eq!(
+            split_path("/a/b/c.jpg"),
+            vec![
+                ("/a/b".to_string(), "c.jpg".to_string()),
+                ("/a".to_string(), "b/c.jpg".to_string()),
+                ("/".to_string(), "a/b/c.jpg".to_string()),
+            ]
+        );
+        assert!(split_path("/").is_empty());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn path_filter_matches_each_root() {
+        assert_eq!(
+            path_filter("/a/it's.jpg"),
+            "(root = '/a' and path = 'it''s.jpg') or (root = '/' and path = 'a/it''s.jpg')"
+        );
+        assert_eq!(path_filter("/"), "false");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn moved_path_only_rewrites_paths_under_dir() {
+        let (from, to) = (Path::new("/t/old"), Path::new("/t/new"));
+        assert_eq!(
+            moved_path("/t/old/x.jpg", from, to),
+            Some("/t/new/x.jpg".to_string())
+        );
+        assert_eq!(moved_path("/t/old2/x.jpg", from, to), None);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn under_dir_escapes_wildcards() {
+        assert_eq!(
+            under_dir("path", "a_b/100%"),
+            "path like 'a\\_b/100\\%/%' escape '\\'"
+        );
+        assert_eq!(
+            under_dir("root", "/it's"),
+            "root like '/it''s/%' escape '\\'"
+        );
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn under_dir_escapes_separator() {
+        assert_eq!(
+            under_dir("root", "C:\\a_b"),
+            "root like 'C:\\\\a\\_b\\\\%' escape '\\'"
+        );
+    }
+}
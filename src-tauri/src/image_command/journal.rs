@@ -0,0 +1,190 @@
+/**
+ * 应用执行过的重命名记录，每行一条 json，用于撤销
+ */
+use std::{
+    collections::HashSet,
+    fs::OpenOptions,
+    io::{BufRead, BufReader, Write},
+    path::Path,
+    sync::{Arc, Mutex},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use lancedb::Table;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    error::AppError,
+    image_command::{api::AppliedRename, idx},
+    path_utils,
+};
+
+pub static SOURCE_INDEX: &str = "index";
+pub static SOURCE_PROPOSAL: &str = "proposal";
+pub static SOURCE_UNDO: &str = "undo";
+
+// 并发上传时多个批次同时追加
+static WRITE_LOCK: Mutex<()> = Mutex::new(());
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct JournalEntry {
+    pub id: String,
+    // 同一次操作中的重命名共用一个 batch
+    pub batch: String,
+    pub old: String,
+    pub new: String,
+    // unix 秒
+    pub time: u64,
+    // index / proposal / undo
+    pub source: String,
+    // 撤销记录对应的原记录 id
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub undoes: Option<String>,
+    // 是否已被撤销，读取时计算
+    #[serde(default, skip_deserializing)]
+    pub undone: bool,
+}
+
+pub fn new_batch() -> String {
+    crate::uuid_utils::get()
+}
+
+fn append(entry: &JournalEntry) -> Result<(), AppError> {
+    let p = path_utils::rename_journal_file()?;
+    let line = serde_json::to_string(entry)?;
+
+    let _guard = WRITE_LOCK.lock()?;
+    let mut f = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&p)
+        .map_err(|e| AppError::io(e, &p))?;
+    writeln!(f, "{line}").map_err(|e| AppError::io(e, &p))?;
+    Ok(())
+}
+
+/**
+ * 记录一次重命名，文件已经改名，记录失败只打日志
+ */
+pub fn record(batch: &str, old: &str, new: &str, source: &str) {
+    let entry = JournalEntry {
+        id: crate::uuid_utils::get(),
+        batch: batch.to_string(),
+        old: old.to_string(),
+        new: new.to_string(),
+        time: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default(),
+        source: source.to_string(),
+        undoes: None,
+    };
+    log::info!("rename {old} -> {new}, source: {source}");
+
+    if let Err(e) = append(&entry) {
+        log::error!("write rename journal error, {e}");
+    }
+}
+
+/**
+ * 全部记录，最新的在前
+ */
+pub fn entries() -> Result<Vec<JournalEntry>, AppError> {
+    let p = path_utils::rename_journal_file()?;
+    if !p.exists() {
+        return Ok(vec![]);
+    }
+
+    let f = std::fs::File::open(&p).map_err(|e| AppError::io(e, &p))?;
+    let mut r = BufReader::new(f)
+        .lines()
+        .map_while(Result::ok)
+        .filter_map(|l| match serde_json::from_str::<JournalEntry>(&l) {
+            Ok(e) => Some(e),
+            Err(e) => {
+                log::warn!("invalid rename journal line, {e}");
+                None
+            }
+        })
+        .collect::<Vec<_>>();
+
+    let undone = r
+        .iter()
+        .filter_map(|e| e.undoes.clone())
+        .collect::<HashSet<_>>();
+    for e in r.iter_mut() {
+        e.undone = undone.contains(&e.id);
+    }
+
+    r.reverse();
+    Ok(r)
+}
+
+async fn undo_entry(
+    entry: &JournalEntry,
+    batch: &str,
+    img_idx_tbl: Arc<Table>,
+) -> Result<(), AppError> {
+    if entry.undone {
+        return Err(AppError::Config(format!(
+            "rename already undone: {}",
+            entry.id
+        )));
+    }
+
+    let (old, new) = (Path::new(&entry.old), Path::new(&entry.new));
+    if !new.exists() {
+        return Err(AppError::Config(format!("file not found: {}", entry.new)));
+    }
+    // 不覆盖之后出现的同名文件
+    if old.exists() {
+        return Err(AppError::Config(format!(
+            "file already exists: {}",
+            entry.old
+        )));
+    }
+
+    std::fs::rename(new, old).map_err(|e| AppError::io(e, new))?;
+    idx::update_path(img_idx_tbl, &entry.new, &entry.old).await?;
+
+    let undo = JournalEntry {
+        id: crate::uuid_utils::get(),
+        batch: batch.to_string(),
+        old: entry.new.clone(),
+        new: entry.old.clone(),
+        time: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default(),
+        source: SOURCE_UNDO.to_string(),
+        undoes: Some(entry.id.clone()),
+    };
+    append(&undo)
+}
+
+/**
+ * 撤销指定的重命名，按时间倒序执行，失败的记录在结果中
+ */
+pub async fn undo<F>(filter: F, img_idx_tbl: Arc<Table>) -> Result<Vec<AppliedRename>, AppError>
+where
+    F: Fn(&JournalEntry) -> bool,
+{
+    let batch = new_batch();
+    let mut r = vec![];
+
+    for entry in entries()?.into_iter().filter(|e| !e.undone && filter(e)) {
+        let result = undo_entry(&entry, &batch, img_idx_tbl.clone()).await;
+        if let Err(e) = &result {
+            log::warn!("undo rename {} error, {e}", entry.id);
+        }
+        r.push(AppliedRename {
+            id: entry.id,
+            from: entry.new,
+            to: result.is_ok().then_some(entry.old),
+            error: result.err().map(|e| e.to_string()),
+        });
+    }
+
+    Ok(r)
+}
@@ -0,0 +1,568 @@
+/**
+ * 用户整理图片用的标签、相册与收藏，保存在 img_meta 表中
+ * 以图片 id 为键，重命名（update_path）与重新索引后 id 不变；
+ * 移动后旧记录被删除、新位置生成新的 id，由 relink 按签名重新关联
+ */
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    sync::{Arc, OnceLock},
+};
+
+use arrow_array::{
+    builder::{BooleanBuilder, ListBuilder, StringBuilder},
+    Array, ArrayRef, RecordBatch, RecordBatchIterator,
+};
+use futures::TryStreamExt;
+use itertools::Itertools;
+use lancedb::{
+    arrow::{
+        arrow_schema::{DataType, Field, Schema},
+        IntoArrowStream,
+    },
+    index::{scalar::BTreeIndexBuilder, Index},
+    query::{ExecutableQuery, QueryBase, Select},
+    table::NewColumnTransform,
+    Connection, Table,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    error::AppError,
+    image_command::idx::{self, sql_str, ImgSearchResult},
+};
+
+// 每张图片一条记录
+static ID_COLUMN: &str = "id";
+
+static IMG_META_TABLE_NAME: &str = "img_meta";
+static SCHEMA: OnceLock<Arc<Schema>> = OnceLock::new();
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct ImgMeta {
+    #[serde(skip)]
+    pub id: String,
+    // 保存时图片的签名，用于移动后重新关联
+    #[serde(skip)]
+    pub sign: String,
+    pub favorite: bool,
+    pub tags: Vec<String>,
+    pub albums: Vec<String>,
+}
+
+impl ImgMeta {
+    fn is_empty(&self) -> bool {
+        !self.favorite && self.tags.is_empty() && self.albums.is_empty()
+    }
+}
+
+/**
+ * 搜索与 show_all 的筛选条件，多个条件同时满足
+ */
+#[derive(Deserialize, Clone, Debug, Default)]
+#[serde(default, rename_all = "camelCase")]
+pub struct MetaFilter {
+    // 只看收藏
+    pub favorite: bool,
+    // 包含全部标签
+    pub tags: Vec<String>,
+    pub album: Option<String>,
+}
+
+impl MetaFilter {
+    fn is_empty(&self) -> bool {
+        !self.favorite && self.tags.is_empty() && self.album.is_none()
+    }
+
+    /**
+     * img_meta 上的筛选条件
+     */
+    fn to_meta_sql(&self) -> String {
+        let mut conds = vec![];
+        if self.favorite {
+            conds.push("favorite = true".to_string());
+        }
+        if !self.tags.is_empty() {
+            let tags = self.tags.iter().map(|t| sql_str(t)).join(",");
+            conds.push(format!("array_has_all(tags, [{tags}])"));
+        }
+        if let Some(album) = self.album.as_ref() {
+            conds.push(format!("array_has_any(albums, [{}])", sql_str(album)));
+        }
+        conds.join(" and ")
+    }
+}
+
+/**
+ * 对一组图片的修改
+ */
+#[derive(Deserialize, Clone, Debug)]
+#[serde(tag = "op", rename_all = "camelCase")]
+pub enum MetaChange {
+    SetFavorite { favorite: bool },
+    AddTags { tags: Vec<String> },
+    RemoveTags { tags: Vec<String> },
+    AddToAlbum { album: String },
+    RemoveFromAlbum { album: String },
+}
+
+impl MetaChange {
+    fn apply(&self, m: &mut ImgMeta) {
+        match self {
+            MetaChange::SetFavorite { favorite } => m.favorite = *favorite,
+            MetaChange::AddTags { tags } => {
+                for t in tags.iter().map(|t| t.trim()).filter(|t| !t.is_empty()) {
+                    if !m.tags.iter().any(|e| e == t) {
+                        m.tags.push(t.to_string());
+                    }
+                }
+            }
+            MetaChange::RemoveTags { tags } => m.tags.retain(|t| !tags.contains(t)),
+            MetaChange::AddToAlbum { album } => {
+                if !m.albums.contains(album) {
+                    m.albums.push(album.clone());
+                }
+            }
+            MetaChange::RemoveFromAlbum { album } => m.albums.retain(|a| a != album),
+        }
+    }
+}
+
+fn get_schema() -> &'static Arc<Schema> {
+    SCHEMA.get_or_init(|| {
+        let list = || DataType::List(Arc::new(Field::new("item", DataType::Utf8, true)));
+        Arc::new(Schema::new(vec![
+            Field::new("sign", DataType::Utf8, false),
+            Field::new("favorite", DataType::Boolean, false),
+            Field::new("tags", list(), false),
+            Field::new("albums", list(), false),
+            // 旧版本以签名为键，id 为后加的列，放在最后
+            Field::new(ID_COLUMN, DataType::Utf8, true),
+        ]))
+    })
+}
+
+pub async fn get_table(db: &Connection) -> Result<Table, AppError> {
+    let tbls = db.table_names().execute().await?;
+    if tbls.contains(&IMG_META_TABLE_NAME.to_string()) {
+        let r = db.open_table(IMG_META_TABLE_NAME).execute().await?;
+        migrate(&r).await?;
+        return Ok(r);
+    }
+
+    let r = db
+        .create_empty_table(IMG_META_TABLE_NAME, get_schema().clone())
+        .execute()
+        .await?;
+
+    for column in [ID_COLUMN, "sign"] {
+        r.create_index(&[column], Index::BTree(BTreeIndexBuilder {}))
+            .execute()
+            .await?;
+    }
+
+    Ok(r)
+}
+
+/**
+ * 旧版本以签名为键，追加 id 列并暂时填入签名
+ * 这类记录由 relink 展开到签名相同的每张图片
+ */
+async fn migrate(table: &Table) -> Result<(), AppError> {
+    let schema = table.schema().await?;
+    if schema.field_with_name(ID_COLUMN).is_ok() {
+        return Ok(());
+    }
+
+    log::info!("migrate {IMG_META_TABLE_NAME}, key by image id");
+    table
+        .add_columns(
+            NewColumnTransform::SqlExpressions(vec![(ID_COLUMN.to_string(), "sign".to_string())]),
+            None,
+        )
+        .await?;
+    table
+        .create_index(&[ID_COLUMN], Index::BTree(BTreeIndexBuilder {}))
+        .execute()
+        .await?;
+    Ok(())
+}
+
+fn string_list(batch: &RecordBatch, name: &str, row: usize) -> Result<Vec<String>, AppError> {
+    let list = batch
+        .column_by_name(name)
+        .ok_or_else(|| AppError::Db(format!("Missing column: {name}")))?
+        .as_any()
+        .downcast_ref::<arrow_array::ListArray>()
+        .ok_or_else(|| AppError::Db(format!("Invalid type for column: {name}")))?;
+
+    let values = list.value(row);
+    let values = values
+        .as_any()
+        .downcast_ref::<arrow_array::StringArray>()
+        .ok_or_else(|| AppError::Db(format!("Invalid type for column: {name}")))?;
+
+    Ok(values.iter().flatten().map(|s| s.to_string()).collect())
+}
+
+fn map_batch_to_meta(batch: &RecordBatch) -> Result<Vec<ImgMeta>, AppError> {
+    let id_array = batch
+        .column_by_name(ID_COLUMN)
+        .ok_or_else(|| AppError::Db("Missing column: id".to_string()))?
+        .as_any()
+        .downcast_ref::<arrow_array::StringArray>()
+        .ok_or_else(|| AppError::Db("Invalid type for column: id".to_string()))?;
+
+    let sign_array = batch
+        .column_by_name("sign")
+        .ok_or_else(|| AppError::Db("Missing column: sign".to_string()))?
+        .as_any()
+        .downcast_ref::<arrow_array::StringArray>()
+        .ok_or_else(|| AppError::Db("Invalid type for column: sign".to_string()))?;
+
+    let favorite_array = batch
+        .column_by_name("favorite")
+        .ok_or_else(|| AppError::Db("Missing column: favorite".to_string()))?
+        .as_any()
+        .downcast_ref::<arrow_array::BooleanArray>()
+        .ok_or_else(|| AppError::Db("Invalid type for column: favorite".to_string()))?;
+
+    (0..batch.num_rows())
+        .map(|row| {
+            Ok(ImgMeta {
+                id: id_array.value(row).to_string(),
+                sign: sign_array.value(row).to_string(),
+                favorite: favorite_array.value(row),
+                tags: string_list(batch, "tags", row)?,
+                albums: string_list(batch, "albums", row)?,
+            })
+        })
+        .collect()
+}
+
+async fn load(table: Arc<Table>, filter: Option<String>) -> Result<Vec<ImgMeta>, AppError> {
+    let stream = match filter {
+        Some(filter) => table.query().only_if(filter).execute().await?,
+        None => table.query().execute().await?,
+    }
+    .into_arrow()?;
+    let mut results = Vec::new();
+
+    // 消费 stream
+    futures::pin_mut!(stream);
+    while let Some(batch) = stream.try_next().await? {
+        let mut items = map_batch_to_meta(&batch)?;
+        results.append(&mut items);
+    }
+    Ok(results)
+}
+
+/**
+ * 只读取 id 列
+ */
+async fn load_ids(table: Arc<Table>, filter: String) -> Result<Vec<String>, AppError> {
+    let stream = table
+        .query()
+        .select(Select::columns(&[ID_COLUMN]))
+        .only_if(filter)
+        .execute()
+        .await?
+        .into_arrow()?;
+    let mut results = Vec::new();
+
+    // 消费 stream
+    futures::pin_mut!(stream);
+    while let Some(batch) = stream.try_next().await? {
+        let id_array = batch
+            .column_by_name(ID_COLUMN)
+            .ok_or_else(|| AppError::Db("Missing column: id".to_string()))?
+            .as_any()
+            .downcast_ref::<arrow_array::StringArray>()
+            .ok_or_else(|| AppError::Db("Invalid type for column: id".to_string()))?;
+
+        results.extend((0..batch.num_rows()).map(|row| id_array.value(row).to_string()));
+    }
+    Ok(results)
+}
+
+pub async fn get_by_ids(
+    table: Arc<Table>,
+    ids: &[String],
+) -> Result<HashMap<String, ImgMeta>, AppError> {
+    if ids.is_empty() {
+        return Ok(HashMap::new());
+    }
+
+    let ids = ids.iter().map(|id| sql_str(id)).join(",");
+    let r = load(table, Some(format!("id in ({ids})"))).await?;
+    Ok(r.into_iter().map(|m| (m.id.clone(), m)).collect())
+}
+
+async fn delete_ids(table: &Table, ids: &[String]) -> Result<(), AppError> {
+    if ids.is_empty() {
+        return Ok(());
+    }
+    let ids = ids.iter().map(|id| sql_str(id)).join(",");
+    table.delete(&format!("id in ({ids})")).await?;
+    Ok(())
+}
+
+async fn save(table: Arc<Table>, records: Vec<ImgMeta>) -> Result<(), AppError> {
+    let (empty, records): (Vec<_>, Vec<_>) = records.into_iter().partition(|m| m.is_empty());
+
+    // 没有任何信息的记录直接删除
+    let empty = empty.into_iter().map(|m| m.id).collect::<Vec<_>>();
+    delete_ids(&table, &empty).await?;
+    if records.is_empty() {
+        return Ok(());
+    }
+
+    let mut sign_builder = StringBuilder::new();
+    let mut favorite_builder = BooleanBuilder::new();
+    let mut tags_builder = ListBuilder::new(StringBuilder::new());
+    let mut albums_builder = ListBuilder::new(StringBuilder::new());
+    let mut id_builder = StringBuilder::new();
+
+    for m in records.into_iter() {
+        sign_builder.append_value(m.sign);
+        favorite_builder.append_value(m.favorite);
+        for t in m.tags {
+            tags_builder.values().append_value(t);
+        }
+        tags_builder.append(true);
+        for a in m.albums {
+            albums_builder.values().append_value(a);
+        }
+        albums_builder.append(true);
+        id_builder.append_value(m.id);
+    }
+
+    let schema = get_schema().clone();
+    let batch = RecordBatch::try_new(
+        schema.clone(),
+        vec![
+            Arc::new(sign_builder.finish()) as ArrayRef,
+            Arc::new(favorite_builder.finish()) as ArrayRef,
+            Arc::new(tags_builder.finish()) as ArrayRef,
+            Arc::new(albums_builder.finish()) as ArrayRef,
+            Arc::new(id_builder.finish()) as ArrayRef,
+        ],
+    )?;
+
+    let reader = Box::new(RecordBatchIterator::new(
+        vec![batch].into_iter().map(Ok),
+        schema,
+    ));
+
+    let mut merge_insert = table.merge_insert(&[ID_COLUMN]);
+    merge_insert
+        .when_matched_update_all(None)
+        .when_not_matched_insert_all();
+
+    merge_insert.execute(reader).await?;
+
+    Ok(())
+}
+
+/**
+ * 修改一组图片的标签、相册或收藏
+ */
+pub async fn apply(
+    img_idx_tbl: Arc<Table>,
+    img_meta_tbl: Arc<Table>,
+    ids: &[String],
+    change: &MetaChange,
+) -> Result<(), AppError> {
+    if ids.is_empty() {
+        return Ok(());
+    }
+
+    let filter = format!("id in ({})", ids.iter().map(|id| sql_str(id)).join(","));
+    let signs = idx::get_id_signs(&img_idx_tbl, filter).await?;
+    let ids = signs.iter().map(|(id, _)| id.clone()).collect::<Vec<_>>();
+
+    let mut existing = get_by_ids(img_meta_tbl.clone(), &ids).await?;
+    let records = signs
+        .into_iter()
+        .map(|(id, sign)| {
+            let mut m = existing.remove(&id).unwrap_or_else(|| ImgMeta {
+                id,
+                ..Default::default()
+            });
+            // 内容修改后签名会变化，保存最新的签名
+            m.sign = sign;
+            change.apply(&mut m);
+            m
+        })
+        .collect();
+
+    save(img_meta_tbl, records).await
+}
+
+/**
+ * 图片移动后在新位置重新索引，得到新的 id，按签名把旧 id 上的信息关联到新 id
+ * 旧版本以签名为键的记录（id 等于签名）展开到签名相同的每张图片
+ * 同时更新内容被修改的图片的签名
+ */
+pub async fn relink(img_idx_tbl: Arc<Table>, img_meta_tbl: Arc<Table>) -> Result<(), AppError> {
+    let metas = load(img_meta_tbl.clone(), None).await?;
+    if metas.is_empty() {
+        return Ok(());
+    }
+
+    let ids = metas.iter().map(|m| sql_str(&m.id)).join(",");
+    let live = idx::get_id_signs(&img_idx_tbl, format!("id in ({ids})"))
+        .await?
+        .into_iter()
+        .collect::<HashMap<_, _>>();
+
+    let mut changed = vec![];
+    let mut orphans: HashMap<String, Vec<ImgMeta>> = HashMap::new();
+    for mut m in metas.into_iter() {
+        match live.get(&m.id) {
+            Some(sign) if *sign != m.sign => {
+                m.sign = sign.clone();
+                changed.push(m);
+            }
+            Some(_) => {}
+            // 失败的图片没有签名，无法重新关联
+            None if !m.sign.is_empty() => orphans.entry(m.sign.clone()).or_default().push(m),
+            None => {}
+        }
+    }
+
+    let mut removed = vec![];
+    if !orphans.is_empty() {
+        let signs = orphans.keys().map(|s| sql_str(s)).join(",");
+        let linked = live.keys().cloned().collect::<HashSet<_>>();
+        let candidates = idx::get_id_signs(&img_idx_tbl, format!("sign in ({signs})"))
+            .await?
+            .into_iter()
+            .filter(|(id, _)| !linked.contains(id))
+            .into_group_map_by(|(_, sign)| sign.clone());
+
+        for (sign, olds) in orphans.into_iter() {
+            let Some(ids) = candidates.get(&sign) else {
+                continue;
+            };
+            let mut ids = ids.iter().map(|(id, _)| id.clone());
+            let (legacy, olds): (Vec<_>, Vec<_>) = olds.into_iter().partition(|m| m.id == m.sign);
+
+            // 移动后的图片与旧记录一一对应
+            for (old, id) in olds.into_iter().zip(ids.by_ref()) {
+                log::info!("relink img meta {} -> {id}", old.id);
+                removed.push(old.id.clone());
+                changed.push(ImgMeta { id, ..old });
+            }
+
+            // 旧版本的记录以签名为键，每个签名只有一条，展开到其余的全部副本
+            let rest = ids.collect::<Vec<_>>();
+            if let Some(old) = legacy.into_iter().next().filter(|_| !rest.is_empty()) {
+                log::info!("relink img meta {} -> {} images", old.id, rest.len());
+                removed.push(old.id.clone());
+                changed.extend(rest.into_iter().map(|id| ImgMeta { id, ..old.clone() }));
+            }
+        }
+    }
+
+    // 先写入新记录再删除旧记录，中断后重新执行不会丢失信息
+    save(img_meta_tbl.clone(), changed).await?;
+    delete_ids(&img_meta_tbl, &removed).await
+}
+
+/**
+ * 把筛选条件转为 img_idx 上的 sql，条件为空时返回 None
+ */
+pub async fn to_sql(
+    img_meta_tbl: Arc<Table>,
+    filter: &MetaFilter,
+) -> Result<Option<String>, AppError> {
+    if filter.is_empty() {
+        return Ok(None);
+    }
+
+    let ids = load_ids(img_meta_tbl, filter.to_meta_sql())
+        .await?
+        .iter()
+        .map(|id| sql_str(id))
+        .join(",");
+
+    if ids.is_empty() {
+        return Ok(Some("false".to_string()));
+    }
+    Ok(Some(format!("id in ({ids})")))
+}
+
+/**
+ * 为结果附加标签等信息
+ */
+pub async fn attach(
+    img_meta_tbl: Arc<Table>,
+    results: &mut [ImgSearchResult],
+) -> Result<(), AppError> {
+    let ids = results.iter().map(|r| r.id.clone()).collect::<Vec<_>>();
+    let metas = get_by_ids(img_meta_tbl, &ids).await?;
+
+    for r in results.iter_mut() {
+        r.meta = metas.get(&r.id).cloned();
+    }
+    Ok(())
+}
+
+/**
+ * 所有标签或相册及其图片数
+ */
+pub async fn list_names(
+    img_meta_tbl: Arc<Table>,
+    albums: bool,
+) -> Result<BTreeMap<String, usize>, AppError> {
+    let mut r = BTreeMap::new();
+    for m in load(img_meta_tbl, None).await? {
+        let names = if albums { m.albums } else { m.tags };
+        for n in names {
+            *r.entry(n).or_insert(0) += 1;
+        }
+    }
+    Ok(r)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn meta_filter_sql() {
+        let filter = MetaFilter {
+            favorite: true,
+            tags: vec!["cat".to_string(), "it's".to_string()],
+            album: Some("trip".to_string()),
+        };
+        assert_eq!(
+            filter.to_meta_sql(),
+            "favorite = true and array_has_all(tags, ['cat','it''s']) and array_has_any(albums, ['trip'])"
+        );
+        assert!(MetaFilter::default().is_empty());
+    }
+
+    #[test]
+    fn changes_keep_tags_unique() {
+        let mut m = ImgMeta::default();
+        MetaChange::AddTags {
+            tags: vec!["cat".to_string(), " cat ".to_string(), "".to_string()],
+        }
+        .apply(&mut m);
+        assert_eq!(m.tags, vec!["cat"]);
+
+        MetaChange::RemoveTags {
+            tags: vec!["cat".to_string()],
+        }
+        .apply(&mut m);
+        MetaChange::AddToAlbum {
+            album: "trip".to_string(),
+        }
+        .apply(&mut m);
+        assert!(m.tags.is_empty());
+        assert_eq!(m.albums, vec!["trip"]);
+        assert!(!m.is_empty());
+    }
+}
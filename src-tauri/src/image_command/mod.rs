@@ -1,71 +1,250 @@
-mod api;
-mod idx;
+pub mod api;
+mod archive;
+pub mod bench;
+mod export;
+pub mod idx;
+mod journal;
+pub mod meta;
+pub mod naming;
+pub mod roots;
 mod utils;
+pub mod vector_index;
+pub mod volume;
+mod xmp;
 
-use std::sync::Arc;
+use std::{collections::HashMap, path::Path, sync::Arc};
 
 use lancedb::Table;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use tauri::{async_runtime::RwLock, State, Wry};
 use tauri_plugin_store::Store;
 
-use crate::{error::AppError, server::imgsearch_server::ImgseachServer, GlobalState};
+use crate::{
+    error::AppError, path_utils::ScanOptions, server::imgsearch_server::ImgseachServer,
+    settings_command::index_settings, GlobalState,
+};
 
-pub use idx::get_table;
 use idx::ImgSearchResult;
+use naming::RenameRule;
+pub use utils::SizeThresholds;
 
-#[warn(dead_code)]
-#[derive(Deserialize)]
-struct ImgDir {
-    name: String,
-    root: String,
-    #[serde(alias = "enableRename")]
-    rename: bool,
+/**
+ * ImgDirStore.json 中保存的 imgdir，由前端写入
+ */
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct ImgDir {
+    pub name: String,
+    pub root: String,
+    #[serde(rename = "enableRename", alias = "rename")]
+    pub rename: bool,
+    #[serde(
+        rename = "createTime",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub create_time: Option<serde_json::Value>,
+    // 是否读写 .xmp 附属文件，默认关闭
+    #[serde(rename = "writeXmp", default)]
+    pub write_xmp: bool,
+    // 自动重命名的文件名规则
+    #[serde(rename = "renameRule", default)]
+    pub rename_rule: RenameRule,
+    // 忽略规则与扫描选项
+    #[serde(default)]
+    pub scan: ScanOptions,
+    // 跳过小图的阈值
+    #[serde(default)]
+    pub thresholds: SizeThresholds,
+}
+
+/**
+ * 修改 imgdir 的可选设置，未传的字段保持不变
+ */
+#[derive(Deserialize, Default, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ImgDirOptions {
+    pub write_xmp: Option<bool>,
+    pub rename_rule: Option<RenameRule>,
+    pub scan: Option<ScanOptions>,
+    pub thresholds: Option<SizeThresholds>,
+}
+
+impl ImgDir {
+    pub fn apply(&mut self, options: ImgDirOptions) {
+        if let Some(write_xmp) = options.write_xmp {
+            self.write_xmp = write_xmp;
+        }
+        if let Some(rename_rule) = options.rename_rule {
+            self.rename_rule = rename_rule;
+        }
+        if let Some(scan) = options.scan {
+            self.scan = scan;
+        }
+        if let Some(thresholds) = options.thresholds {
+            self.thresholds = thresholds;
+        }
+    }
+}
+
+/**
+ * key = root
+ */
+pub type ImgDirs = HashMap<String, ImgDir>;
+
+pub fn load_imgdirs(entries: Vec<(String, serde_json::Value)>) -> ImgDirs {
+    entries
+        .into_iter()
+        .filter_map(|(root, v)| match serde_json::from_value::<ImgDir>(v) {
+            Ok(imgdir) => Some((root, imgdir)),
+            Err(e) => {
+                log::warn!("invalid imgdir, root = {root}, {e}");
+                None
+            }
+        })
+        .collect()
 }
 
 pub fn after_start_up(
     table: Arc<Table>,
+    img_meta_tbl: Arc<Table>,
     imgdir_store: Arc<Store<Wry>>,
+    settings_store: Arc<Store<Wry>>,
     server: Arc<RwLock<Option<ImgseachServer>>>,
 ) {
     tauri::async_runtime::spawn(async move {
-        if let Err(e) = api::on_start_up(table, imgdir_store).await {
+        let settings = index_settings(&settings_store);
+        let imgdirs = load_imgdirs(imgdir_store.entries());
+        // 上传可能持续很久，不持有读锁，避免设置 apikey 时阻塞
+        let server = server.read().await.clone();
+        if let Err(e) = api::on_start_up(table.clone(), &imgdirs, server.as_ref(), &settings).await
+        {
             log::error!("on_startup process error, {e:?}");
         }
+        // 命令行索引的图片、旧版本以签名为键的信息在启动时关联
+        if let Err(e) = meta::relink(table, img_meta_tbl).await {
+            log::error!("relink img meta error, {e}");
+        }
     });
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
 pub struct SearchModel {
-    keyword: String,
-    top: usize,
+    pub keyword: String,
+    pub top: usize,
+    // 向量索引的搜索参数，不传时使用默认值
+    #[serde(default)]
+    pub nprobes: Option<usize>,
+    #[serde(default)]
+    pub refine_factor: Option<u32>,
 }
 #[tauri::command]
 pub async fn search(
     model: SearchModel,
+    filter: Option<meta::MetaFilter>,
     state: State<'_, GlobalState>,
 ) -> Result<Vec<idx::ImgSearchResult>, AppError> {
     let server = state.server.read().await;
     if server.is_none() {
-        return Err(AppError::Auth("server not ready".to_string()));
+        return Err(AppError::ServerNotReady);
     }
 
     let server = server.as_ref().unwrap();
-    Ok(api::search(&model, server, state.img_idx_tbl.clone()).await?)
+    let filter = meta::to_sql(state.img_meta_tbl.clone(), &filter.unwrap_or_default()).await?;
+    let mut r = api::search_filtered(&model, server, state.img_idx_tbl.clone(), filter).await?;
+    meta::attach(state.img_meta_tbl.clone(), &mut r).await?;
+    volume::attach(state.img_root_tbl.clone(), &mut r).await?;
+    Ok(r)
+}
+
+#[tauri::command]
+pub async fn show_all(
+    filter: Option<meta::MetaFilter>,
+    state: State<'_, GlobalState>,
+) -> Result<Vec<ImgSearchResult>, AppError> {
+    let filter = meta::to_sql(state.img_meta_tbl.clone(), &filter.unwrap_or_default()).await?;
+    let mut r = idx::get_all(state.img_idx_tbl.clone(), Some(true), filter).await?;
+    meta::attach(state.img_meta_tbl.clone(), &mut r).await?;
+    volume::attach(state.img_root_tbl.clone(), &mut r).await?;
+    Ok(r)
+}
+
+/**
+ * 修改图片的标签、相册或收藏
+ */
+#[tauri::command]
+pub async fn update_meta(
+    ids: Vec<String>,
+    change: meta::MetaChange,
+    state: State<'_, GlobalState>,
+) -> Result<(), AppError> {
+    log::info!("update meta of {} images: {change:?}", ids.len());
+    meta::apply(
+        state.img_idx_tbl.clone(),
+        state.img_meta_tbl.clone(),
+        &ids,
+        &change,
+    )
+    .await
+}
+
+#[tauri::command]
+pub async fn list_tags(
+    state: State<'_, GlobalState>,
+) -> Result<std::collections::BTreeMap<String, usize>, AppError> {
+    meta::list_names(state.img_meta_tbl.clone(), false).await
 }
 
 #[tauri::command]
-pub async fn show_all(state: State<'_, GlobalState>) -> Result<Vec<ImgSearchResult>, AppError> {
-    let r = idx::get_all(state.img_idx_tbl.clone(), Some(true)).await?;
+pub async fn list_albums(
+    state: State<'_, GlobalState>,
+) -> Result<std::collections::BTreeMap<String, usize>, AppError> {
+    meta::list_names(state.img_meta_tbl.clone(), true).await
+}
+
+#[tauri::command]
+pub async fn list_failed(state: State<'_, GlobalState>) -> Result<Vec<ImgSearchResult>, AppError> {
+    let r = api::list_failed(state.img_idx_tbl.clone()).await?;
     Ok(r)
 }
 
+/**
+ * 因尺寸阈值被跳过的图片，error 为跳过的原因
+ */
+#[tauri::command]
+pub async fn list_skipped(state: State<'_, GlobalState>) -> Result<Vec<ImgSearchResult>, AppError> {
+    api::list_skipped(state.img_idx_tbl.clone()).await
+}
+
+/**
+ * 重试失败的图片，ids 为空时重试全部
+ */
+#[tauri::command]
+pub async fn retry_failed(
+    ids: Option<Vec<String>>,
+    state: State<'_, GlobalState>,
+) -> Result<(), AppError> {
+    let server = state.server.read().await;
+    let Some(server) = server.as_ref() else {
+        return Err(AppError::ServerNotReady);
+    };
+
+    let settings = index_settings(&state.settings_store);
+    let imgdirs = load_imgdirs(state.imgdir_store.entries());
+    api::retry_failed(ids, &imgdirs, server, &settings, state.img_idx_tbl.clone()).await?;
+
+    Ok(())
+}
+
+/**
+ * 返回与其他 imgdir 的嵌套关系，图片归属于包含它的最深的 imgdir
+ */
 #[tauri::command]
 pub async fn after_add_imgdir(
     root: String,
     rename: bool,
     state: State<'_, GlobalState>,
-) -> Result<(), AppError> {
+) -> Result<roots::RootOverlap, AppError> {
     let server = state.server.read().await;
     let server = if let Some(server) = server.as_ref() {
         Some(server)
@@ -73,9 +252,36 @@ pub async fn after_add_imgdir(
         None
     };
 
-    api::index_imgdir(root, rename, server, state.img_idx_tbl.clone()).await?;
+    let settings = index_settings(&state.settings_store);
+    let imgdirs = load_imgdirs(state.imgdir_store.entries());
+    let overlap = roots::overlap(&imgdirs, &root);
+    if let Some(parent) = overlap.parent.as_ref() {
+        log::info!("img dir {root} is nested in {parent}");
+    }
+    if !overlap.children.is_empty() {
+        log::info!("img dir {root} contains {:?}", overlap.children);
+    }
+
+    api::index_imgdir(
+        root.clone(),
+        rename,
+        &imgdirs,
+        server,
+        &settings,
+        state.img_idx_tbl.clone(),
+    )
+    .await?;
 
-    Ok(())
+    if let Err(e) = meta::relink(state.img_idx_tbl.clone(), state.img_meta_tbl.clone()).await {
+        log::error!("relink img meta error, {e}");
+    }
+
+    if imgdirs.get(&root).is_some_and(|d| d.write_xmp) {
+        xmp::import(&root, state.img_idx_tbl.clone(), state.img_meta_tbl.clone()).await?;
+        xmp::export(&root, state.img_idx_tbl.clone(), state.img_meta_tbl.clone()).await?;
+    }
+
+    Ok(overlap)
 }
 
 #[tauri::command]
@@ -85,13 +291,87 @@ pub async fn after_remove_imgdir(
 ) -> Result<(), AppError> {
     log::info!("remove img dir: {root}");
 
-    api::remove_root(&root, state.img_idx_tbl.clone()).await?;
+    let mut imgdirs = load_imgdirs(state.imgdir_store.entries());
+    imgdirs.remove(&root);
+    api::remove_root(&root, &imgdirs, state.img_idx_tbl.clone()).await?;
+
+    Ok(())
+}
+
+/**
+ * imgdir 移动到新位置，如换了磁盘或盘符，保留索引与标签
+ */
+/**
+ * 修改 imgdir 的设置，返回修改后的 imgdir，新的设置在下次索引时生效
+ */
+#[tauri::command]
+pub async fn update_imgdir_options(
+    root: String,
+    options: ImgDirOptions,
+    state: State<'_, GlobalState>,
+) -> Result<ImgDir, AppError> {
+    log::info!("update img dir options: {root}, {options:?}");
+
+    let mut imgdirs = load_imgdirs(state.imgdir_store.entries());
+    let Some(imgdir) = imgdirs.get_mut(&root) else {
+        return Err(AppError::Config(format!("img dir not found: {root}")));
+    };
+    imgdir.apply(options);
+    imgdir.scan.check(Path::new(&root))?;
+
+    state.imgdir_store.set(root, serde_json::to_value(&imgdir)?);
+    state.imgdir_store.save()?;
+
+    Ok(imgdir.clone())
+}
+
+#[tauri::command]
+pub async fn relocate_root(
+    old: String,
+    new: String,
+    state: State<'_, GlobalState>,
+) -> Result<(), AppError> {
+    relocate_imgdir(&old, &new, &state).await
+}
+
+async fn relocate_imgdir(old: &str, new: &str, state: &GlobalState) -> Result<(), AppError> {
+    log::info!("relocate img dir: {old} -> {new}");
+
+    let mut imgdirs = load_imgdirs(state.imgdir_store.entries());
+    let Some(mut imgdir) = imgdirs.remove(old) else {
+        return Err(AppError::Config(format!("img dir not found: {old}")));
+    };
+    if imgdirs.contains_key(new) {
+        return Err(AppError::Config(format!("img dir already exists: {new}")));
+    }
+    if !Path::new(new).is_dir() {
+        return Err(AppError::Config(format!("not a directory: {new}")));
+    }
+
+    imgdir.root = new.to_string();
+    imgdirs.insert(new.to_string(), imgdir.clone());
+    api::relocate_root(old, new, &imgdirs, state.img_idx_tbl.clone()).await?;
+
+    state.imgdir_store.delete(old);
+    state
+        .imgdir_store
+        .set(new.to_string(), serde_json::to_value(&imgdir)?);
+    state.imgdir_store.save()?;
+
+    volume::refresh(state.img_root_tbl.clone(), &imgdirs).await?;
 
     Ok(())
 }
 
 #[tauri::command]
 pub async fn delete(path: String, state: State<'_, GlobalState>) -> Result<(), AppError> {
+    // 移动硬盘拔出时监听会报告删除，保留记录
+    let imgdirs = load_imgdirs(state.imgdir_store.entries());
+    if volume::is_offline_path(&imgdirs, &path) {
+        log::warn!("root of {path} is offline, ignore delete");
+        return Ok(());
+    }
+
     api::delete_path(path, state.img_idx_tbl.clone()).await?;
     Ok(())
 }
@@ -104,10 +384,41 @@ pub struct RenameModel {
     old: String,
     new: String,
 }
+/**
+ * 返回随文件夹移动的 imgdir 新位置，需要重新监听
+ */
 #[tauri::command]
-pub async fn rename(model: RenameModel, state: State<'_, GlobalState>) -> Result<(), AppError> {
+pub async fn rename(
+    model: RenameModel,
+    state: State<'_, GlobalState>,
+) -> Result<Vec<String>, AppError> {
+    // 文件夹本身或其子目录是 imgdir 时，按 root 迁移处理，同时更新 img_root 与缩略图目录
+    let mut relocated = vec![];
+    if Path::new(&model.new).is_dir() {
+        let imgdirs = load_imgdirs(state.imgdir_store.entries());
+        let mut roots = imgdirs
+            .keys()
+            .filter_map(|root| {
+                let rest = Path::new(root).strip_prefix(&model.old).ok()?;
+                let new_root = if rest.as_os_str().is_empty() {
+                    model.new.clone()
+                } else {
+                    Path::new(&model.new).join(rest).display().to_string()
+                };
+                Some((root.clone(), new_root))
+            })
+            .collect::<Vec<_>>();
+        // 先迁移上层 root
+        roots.sort();
+        for (root, new_root) in roots {
+            relocate_imgdir(&root, &new_root, &state).await?;
+            relocated.push(new_root);
+        }
+    }
+
+    // 其余属于上层 root 的记录修改路径前缀
     api::rename(model, state.img_idx_tbl.clone()).await?;
-    Ok(())
+    Ok(relocated)
 }
 
 #[tauri::command]
@@ -123,7 +434,226 @@ pub async fn modify_content(
     } else {
         None
     };
-    api::modify_content(root, paths, rename, server, state.img_idx_tbl.clone()).await?;
+    let settings = index_settings(&state.settings_store);
+    let imgdirs = load_imgdirs(state.imgdir_store.entries());
+    api::modify_content(
+        root,
+        paths,
+        rename,
+        &imgdirs,
+        server,
+        &settings,
+        state.img_idx_tbl.clone(),
+    )
+    .await?;
+
+    // 移动进来的图片按签名关联原来的标签等信息
+    if let Err(e) = meta::relink(state.img_idx_tbl.clone(), state.img_meta_tbl.clone()).await {
+        log::error!("relink img meta error, {e}");
+    }
 
     Ok(())
 }
+
+/**
+ * 导出已索引的记录与缩略图到归档文件
+ */
+#[tauri::command]
+pub async fn export_index(
+    archive: String,
+    state: State<'_, GlobalState>,
+) -> Result<usize, AppError> {
+    archive::export(Path::new(&archive), state.img_idx_tbl.clone()).await
+}
+
+/**
+ * 导入归档，root_map 为 导出时的 root -> 本机 root
+ * 导入的 root 会加入 imgdir
+ */
+#[tauri::command]
+pub async fn import_index(
+    archive: String,
+    root_map: HashMap<String, String>,
+    state: State<'_, GlobalState>,
+) -> Result<archive::ImportReport, AppError> {
+    let report = archive::import(Path::new(&archive), root_map, state.img_idx_tbl.clone()).await?;
+
+    for root in report.roots.iter() {
+        if state.imgdir_store.has(root) {
+            continue;
+        }
+
+        let imgdir = ImgDir {
+            name: Path::new(root)
+                .file_name()
+                .map(|n| n.display().to_string())
+                .unwrap_or_else(|| root.clone()),
+            root: root.clone(),
+            rename: false,
+            create_time: None,
+            write_xmp: false,
+            rename_rule: RenameRule::default(),
+            scan: ScanOptions::default(),
+            thresholds: SizeThresholds::default(),
+        };
+        state
+            .imgdir_store
+            .set(root.clone(), serde_json::to_value(&imgdir)?);
+    }
+    state.imgdir_store.save()?;
+
+    Ok(report)
+}
+
+/**
+ * 导出搜索结果或筛选后的索引，支持 csv / jsonl / parquet
+ */
+#[tauri::command]
+pub async fn export_results(
+    model: export::ExportModel,
+    state: State<'_, GlobalState>,
+) -> Result<usize, AppError> {
+    let server = state.server.read().await;
+    export::export(&model, server.as_ref(), state.img_idx_tbl.clone()).await
+}
+
+/**
+ * 向量索引与标量索引的统计
+ */
+#[tauri::command]
+pub async fn index_stats(
+    state: State<'_, GlobalState>,
+) -> Result<vector_index::IndexReport, AppError> {
+    vector_index::report(state.img_idx_tbl.clone()).await
+}
+
+/**
+ * 修改图片描述，reembed 为 true 时用描述重新计算向量
+ */
+#[tauri::command]
+pub async fn edit_description(
+    id: String,
+    desc: String,
+    reembed: bool,
+    state: State<'_, GlobalState>,
+) -> Result<(), AppError> {
+    let server = state.server.read().await;
+    api::edit_description(
+        &id,
+        desc.trim(),
+        reembed,
+        server.as_ref(),
+        state.img_idx_tbl.clone(),
+    )
+    .await
+}
+
+/**
+ * 为开启了 writeXmp 的 imgdir 写入 .xmp 附属文件，root 为空时处理全部
+ */
+#[tauri::command]
+pub async fn write_xmp(
+    root: Option<String>,
+    state: State<'_, GlobalState>,
+) -> Result<xmp::XmpReport, AppError> {
+    let imgdirs = load_imgdirs(state.imgdir_store.entries());
+    let mut report = xmp::XmpReport::default();
+
+    for imgdir in imgdirs.values().filter(|d| d.write_xmp) {
+        if root.as_ref().is_some_and(|r| r != &imgdir.root) {
+            continue;
+        }
+
+        let r = xmp::export(
+            &imgdir.root,
+            state.img_idx_tbl.clone(),
+            state.img_meta_tbl.clone(),
+        )
+        .await?;
+        report.written += r.written;
+        report.unchanged += r.unchanged;
+        report.skipped += r.skipped;
+        report.failed += r.failed;
+    }
+
+    Ok(report)
+}
+
+/**
+ * 读取 root 下已有的 .xmp 附属文件
+ */
+#[tauri::command]
+pub async fn import_xmp(root: String, state: State<'_, GlobalState>) -> Result<usize, AppError> {
+    xmp::import(&root, state.img_idx_tbl.clone(), state.img_meta_tbl.clone()).await
+}
+
+/**
+ * 待确认的重命名建议，root 为空时列出全部
+ */
+#[tauri::command]
+pub async fn list_rename_proposals(
+    root: Option<String>,
+    state: State<'_, GlobalState>,
+) -> Result<Vec<ImgSearchResult>, AppError> {
+    api::list_rename_proposals(root.as_deref(), state.img_idx_tbl.clone()).await
+}
+
+#[tauri::command]
+pub async fn apply_rename_proposals(
+    ids: Vec<String>,
+    state: State<'_, GlobalState>,
+) -> Result<Vec<api::AppliedRename>, AppError> {
+    log::info!("apply {} rename proposals", ids.len());
+    let imgdirs = load_imgdirs(state.imgdir_store.entries());
+    api::apply_rename_proposals(&ids, &imgdirs, state.img_idx_tbl.clone()).await
+}
+
+#[tauri::command]
+pub async fn reject_rename_proposals(
+    ids: Vec<String>,
+    state: State<'_, GlobalState>,
+) -> Result<(), AppError> {
+    log::info!("reject {} rename proposals", ids.len());
+    idx::clear_suggested_name(state.img_idx_tbl.clone(), &ids).await
+}
+
+/**
+ * 应用执行过的重命名，最新的在前
+ */
+#[tauri::command]
+pub async fn list_renames(limit: Option<usize>) -> Result<Vec<journal::JournalEntry>, AppError> {
+    let r = journal::entries()?;
+    Ok(r.into_iter().take(limit.unwrap_or(usize::MAX)).collect())
+}
+
+/**
+ * 撤销一次重命名，恢复原文件名并更新索引
+ */
+#[tauri::command]
+pub async fn undo_rename(
+    id: String,
+    state: State<'_, GlobalState>,
+) -> Result<Vec<api::AppliedRename>, AppError> {
+    journal::undo(|e| e.id == id, state.img_idx_tbl.clone()).await
+}
+
+/**
+ * 撤销同一批次中的全部重命名
+ */
+#[tauri::command]
+pub async fn undo_batch(
+    batch: String,
+    state: State<'_, GlobalState>,
+) -> Result<Vec<api::AppliedRename>, AppError> {
+    journal::undo(|e| e.batch == batch, state.img_idx_tbl.clone()).await
+}
+
+/**
+ * imgdir 所在卷的状态，先检查再返回
+ */
+#[tauri::command]
+pub async fn list_roots(state: State<'_, GlobalState>) -> Result<Vec<volume::RootState>, AppError> {
+    let imgdirs = load_imgdirs(state.imgdir_store.entries());
+    volume::refresh(state.img_root_tbl.clone(), &imgdirs).await?;
+    volume::load(state.img_root_tbl.clone()).await
+}
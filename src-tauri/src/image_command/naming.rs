@@ -0,0 +1,394 @@
+/**
+ * 自动重命名的文件名规则，按 imgdir 配置
+ */
+use std::path::Path;
+
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+
+// 模板中的占位符
+static AI_NAME: &str = "{ai_name}";
+static ORIG_NAME: &str = "{orig_name}";
+static DATE: &str = "{date}";
+static COUNTER: &str = "{counter}";
+
+// 渲染后 {counter} 所在位置的分隔符，不会出现在清理后的文件名中
+const COUNTER_MARK: char = '\u{0}';
+
+// windows 文件名中不允许的字符，':' 在 macOS 中同样不允许
+const ILLEGAL_CHARS: &[char] = &['<', '>', ':', '"', '/', '\\', '|', '?', '*'];
+
+// windows 保留的设备名，不区分大小写，带后缀也不允许
+const RESERVED_NAMES: &[&str] = &[
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+static FALLBACK_NAME: &str = "image";
+
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Debug, Default)]
+#[serde(rename_all = "camelCase")]
+pub enum CaseStyle {
+    // 保持服务端返回的大小写
+    #[default]
+    Keep,
+    Lower,
+    Upper,
+    // 小写，空格与 '-' 替换为 '_'
+    Snake,
+    // 小写，空格与 '_' 替换为 '-'
+    Kebab,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+#[serde(rename_all = "camelCase", default)]
+pub struct RenameRule {
+    // 支持 {ai_name} {orig_name} {date} {counter}
+    pub template: String,
+    // 文件名（不含后缀）的最大字符数
+    pub max_len: usize,
+    pub case: CaseStyle,
+    // 非 ascii 字符转写为 ascii
+    pub transliterate: bool,
+}
+
+impl Default for RenameRule {
+    fn default() -> Self {
+        Self {
+            template: AI_NAME.to_string(),
+            max_len: 120,
+            case: CaseStyle::Keep,
+            transliterate: false,
+        }
+    }
+}
+
+impl RenameRule {
+    /**
+     * 按规则生成候选文件名（不含后缀），n 为重名时的第几次尝试，从 0 开始
+     * 模板中有 {counter} 时从 1 开始编号，否则重名时追加 _1, _2
+     */
+    pub fn candidates(&self, ai_name: &str, current: &Path) -> impl Fn(usize) -> String {
+        let orig_name = current
+            .file_stem()
+            .map(|s| s.to_string_lossy().to_string())
+            .unwrap_or_default();
+        let date = std::fs::metadata(current)
+            .and_then(|m| m.modified())
+            .map(DateTime::<Local>::from)
+            .unwrap_or_else(|_| Local::now())
+            .format("%Y%m%d")
+            .to_string();
+
+        let template = if self.template.trim().is_empty() {
+            AI_NAME
+        } else {
+            self.template.as_str()
+        };
+        let rendered = template
+            .replace(AI_NAME, &ai_name.replace(COUNTER_MARK, ""))
+            .replace(ORIG_NAME, &orig_name.replace(COUNTER_MARK, ""))
+            .replace(DATE, &date)
+            .replace(COUNTER, &COUNTER_MARK.to_string());
+
+        let rendered = if self.transliterate {
+            deunicode::deunicode(&rendered)
+        } else {
+            rendered
+        };
+        let rendered = apply_case(&rendered, self.case);
+
+        let has_counter = rendered.contains(COUNTER_MARK);
+        // 以 {counter} 分段清理，避免编号被替换或截断
+        let parts = rendered
+            .split(COUNTER_MARK)
+            .map(sanitize)
+            .collect::<Vec<_>>();
+        let max_len = self.max_len.max(1);
+
+        move |n| {
+            let counter = match (has_counter, n) {
+                (true, n) => (n + 1).to_string(),
+                (false, 0) => "".to_string(),
+                (false, n) => format!("_{n}"),
+            };
+
+            // 编号不截断，其余部分按顺序分配剩余长度
+            let mut keep =
+                max_len.saturating_sub(counter.chars().count() * (parts.len() - 1).max(1));
+            let parts = parts
+                .iter()
+                .map(|p| {
+                    let p = truncate(p, keep);
+                    keep -= p.chars().count();
+                    p
+                })
+                .collect::<Vec<_>>();
+
+            let name = if has_counter {
+                parts.join(&counter)
+            } else {
+                format!("{}{counter}", parts[0])
+            };
+            finalize(&name)
+        }
+    }
+}
+
+fn apply_case(s: &str, case: CaseStyle) -> String {
+    match case {
+        CaseStyle::Keep => s.to_string(),
+        CaseStyle::Lower => s.to_lowercase(),
+        CaseStyle::Upper => s.to_uppercase(),
+        CaseStyle::Snake => join_words(&s.to_lowercase(), '_'),
+        CaseStyle::Kebab => join_words(&s.to_lowercase(), '-'),
+    }
+}
+
+fn join_words(s: &str, sep: char) -> String {
+    s.split(|c: char| c.is_whitespace() || c == '_' || c == '-')
+        .filter(|w| !w.is_empty())
+        .collect::<Vec<_>>()
+        .join(&sep.to_string())
+}
+
+/**
+ * 替换非法字符与控制字符，合并连续的 '_'
+ */
+fn sanitize(s: &str) -> String {
+    let mut r = String::with_capacity(s.len());
+    for c in s.chars() {
+        let c = if ILLEGAL_CHARS.contains(&c) || c.is_control() {
+            '_'
+        } else {
+            c
+        };
+        if c == '_' && r.ends_with('_') {
+            continue;
+        }
+        r.push(c);
+    }
+    r
+}
+
+fn truncate(s: &str, max: usize) -> String {
+    s.chars().take(max).collect()
+}
+
+/**
+ * 去掉首尾的 '.'、空格与 '_'，避开 windows 保留名，结果为空时使用默认名
+ */
+fn finalize(name: &str) -> String {
+    let name = name.trim_matches(|c: char| c == '.' || c == '_' || c.is_whitespace());
+    if name.is_empty() {
+        return FALLBACK_NAME.to_string();
+    }
+
+    let stem = name.split('.').next().unwrap_or(name);
+    if RESERVED_NAMES.iter().any(|r| r.eq_ignore_ascii_case(stem)) {
+        return format!("{name}_");
+    }
+
+    name.to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+    use crate::{path_utils, uuid_utils};
+
+    fn rule(template: &str) -> RenameRule {
+        RenameRule {
+            template: template.to_string(),
+            ..Default::default()
+        }
+    }
+
+    fn temp_dir() -> PathBuf {
+        let p = std::env::temp_dir().join(format!("imgsearch_naming_{}", uuid_utils::get()));
+        std::fs::create_dir_all(&p).unwrap();
+        p
+    }
+
+    #[test]
+    fn default_template_uses_ai_name() {
+        let f = RenameRule::default().candidates("a sunny beach", Path::new("/x/IMG_1.jpg"));
+        assert_eq!(f(0), "a sunny beach");
+        assert_eq!(f(1), "a sunny beach_1");
+        assert_eq!(f(2), "a sunny beach_2");
+
+        let f = rule("  ").candidates("cat", Path::new("/x/IMG_1.jpg"));
+        assert_eq!(f(0), "cat");
+    }
+
+    #[test]
+    fn template_tokens() {
+        let f = rule("{orig_name}-{ai_name}").candidates("dog", Path::new("/x/IMG_001.jpg"));
+        assert_eq!(f(0), "IMG_001-dog");
+        assert_eq!(f(1), "IMG_001-dog_1");
+    }
+
+    #[test]
+    fn date_uses_modified_time() {
+        let dir = temp_dir();
+        let p = dir.join("a.jpg");
+        std::fs::write(&p, b"x").unwrap();
+        let expected = DateTime::<Local>::from(std::fs::metadata(&p).unwrap().modified().unwrap())
+            .format("%Y%m%d")
+            .to_string();
+
+        let f = rule("{date}_{ai_name}").candidates("cat", &p);
+        assert_eq!(f(0), format!("{expected}_cat"));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn counter_starts_at_one() {
+        let f = rule("{ai_name}_{counter}").candidates("cat", Path::new("/x/a.jpg"));
+        assert_eq!(f(0), "cat_1");
+        assert_eq!(f(1), "cat_2");
+        assert_eq!(f(9), "cat_10");
+
+        let f = rule("{counter}-{ai_name}").candidates("cat", Path::new("/x/a.jpg"));
+        assert_eq!(f(0), "1-cat");
+    }
+
+    #[test]
+    fn case_styles() {
+        let name = "A Sunny-Beach_day";
+        let case = |case| {
+            RenameRule {
+                case,
+                ..Default::default()
+            }
+            .candidates(name, Path::new("/x/a.jpg"))(0)
+        };
+        assert_eq!(case(CaseStyle::Keep), "A Sunny-Beach_day");
+        assert_eq!(case(CaseStyle::Lower), "a sunny-beach_day");
+        assert_eq!(case(CaseStyle::Upper), "A SUNNY-BEACH_DAY");
+        assert_eq!(case(CaseStyle::Snake), "a_sunny_beach_day");
+        assert_eq!(case(CaseStyle::Kebab), "a-sunny-beach-day");
+    }
+
+    #[test]
+    fn invalid_characters_are_replaced() {
+        let f = RenameRule::default().candidates("a<b>c:d\"e/f\\g|h?i*j", Path::new("/x/a.jpg"));
+        assert_eq!(f(0), "a_b_c_d_e_f_g_h_i_j");
+
+        let f = RenameRule::default().candidates("a\tb\u{7}c", Path::new("/x/a.jpg"));
+        assert_eq!(f(0), "a_b_c");
+
+        let f = RenameRule::default().candidates("a<<>>b", Path::new("/x/a.jpg"));
+        assert_eq!(f(0), "a_b");
+    }
+
+    #[test]
+    fn trims_and_falls_back() {
+        let name = |ai: &str| RenameRule::default().candidates(ai, Path::new("/x/a.jpg"))(0);
+        assert_eq!(name("..name.. "), "name");
+        assert_eq!(name(""), FALLBACK_NAME);
+        assert_eq!(name("???"), FALLBACK_NAME);
+    }
+
+    #[test]
+    fn reserved_names_get_suffix() {
+        let name = |ai: &str| RenameRule::default().candidates(ai, Path::new("/x/a.jpg"))(0);
+        assert_eq!(name("con"), "con_");
+        assert_eq!(name("LPT1"), "LPT1_");
+        assert_eq!(name("nul.backup"), "nul.backup_");
+        assert_eq!(name("console"), "console");
+    }
+
+    #[test]
+    fn max_len_keeps_suffix_and_counter() {
+        let r = RenameRule {
+            max_len: 5,
+            ..Default::default()
+        };
+        let f = r.candidates("abcdefghij", Path::new("/x/a.jpg"));
+        assert_eq!(f(0), "abcde");
+        assert_eq!(f(1), "abc_1");
+
+        let r = RenameRule {
+            template: "{ai_name}-{counter}".to_string(),
+            max_len: 6,
+            ..Default::default()
+        };
+        let f = r.candidates("abcdefghij", Path::new("/x/a.jpg"));
+        assert_eq!(f(0), "abcde1");
+
+        let r = RenameRule {
+            template: "{counter}-{ai_name}".to_string(),
+            max_len: 6,
+            ..Default::default()
+        };
+        let f = r.candidates("abcdefghij", Path::new("/x/a.jpg"));
+        assert_eq!(f(0), "1-abcd");
+        assert_eq!(f(9), "10-abc");
+
+        // 按字符而不是字节截断
+        let r = RenameRule {
+            max_len: 2,
+            ..Default::default()
+        };
+        assert_eq!(r.candidates("北京故宫", Path::new("/x/a.jpg"))(0), "北京");
+    }
+
+    #[test]
+    fn transliterate_non_ascii() {
+        let r = RenameRule {
+            transliterate: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            r.candidates("Café Zürich", Path::new("/x/a.jpg"))(0),
+            "Cafe Zurich"
+        );
+
+        let r = RenameRule {
+            transliterate: true,
+            case: CaseStyle::Snake,
+            ..Default::default()
+        };
+        assert_eq!(
+            r.candidates("Café Zürich", Path::new("/x/a.jpg"))(0),
+            "cafe_zurich"
+        );
+    }
+
+    #[test]
+    fn rename_adds_suffix_on_collision() {
+        let dir = temp_dir();
+        let src = dir.join("IMG_1.jpg");
+        std::fs::write(&src, b"1").unwrap();
+        std::fs::write(dir.join("cat.jpg"), b"0").unwrap();
+
+        let p = path_utils::rename(&src, RenameRule::default().candidates("cat", &src)).unwrap();
+        assert_eq!(p, dir.join("cat_1.jpg"));
+        assert!(!src.exists());
+
+        let src = dir.join("IMG_2.jpg");
+        std::fs::write(&src, b"2").unwrap();
+        let p = path_utils::rename(&src, RenameRule::default().candidates("cat", &src)).unwrap();
+        assert_eq!(p, dir.join("cat_2.jpg"));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn rename_increments_counter_on_collision() {
+        let dir = temp_dir();
+        let src = dir.join("IMG_1.png");
+        std::fs::write(&src, b"1").unwrap();
+        std::fs::write(dir.join("cat_1.png"), b"0").unwrap();
+
+        let f = rule("{ai_name}_{counter}").candidates("cat", &src);
+        let p = path_utils::rename(&src, f).unwrap();
+        assert_eq!(p, dir.join("cat_2.png"));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,150 @@
+/**
+ * imgdir 之间可以嵌套，路径归属于包含它的最深的 root
+ * 如 /photos 与 /photos/2023 同时存在时，/photos/2023 下的图片只属于 /photos/2023
+ */
+use std::path::Path;
+
+use serde::Serialize;
+
+use crate::image_command::ImgDirs;
+
+#[derive(Serialize, Default, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct RootOverlap {
+    // 包含该 root 的最深的 imgdir
+    pub parent: Option<String>,
+    // 该 root 下的 imgdir，其中的图片不属于该 root
+    pub children: Vec<String>,
+}
+
+fn depth(root: &str) -> usize {
+    Path::new(root).components().count()
+}
+
+/**
+ * 包含 root 的其他 root，由深到浅
+ */
+pub fn ancestors<'a, I>(roots: I, root: &str) -> Vec<&'a str>
+where
+    I: IntoIterator<Item = &'a String>,
+{
+    let mut r = roots
+        .into_iter()
+        .map(|r| r.as_str())
+        .filter(|r| *r != root && Path::new(root).starts_with(r))
+        .collect::<Vec<_>>();
+    r.sort_by_key(|r| std::cmp::Reverse(depth(r)));
+    r
+}
+
+pub fn children<'a, I>(roots: I, root: &str) -> Vec<&'a str>
+where
+    I: IntoIterator<Item = &'a String>,
+{
+    roots
+        .into_iter()
+        .map(|r| r.as_str())
+        .filter(|r| *r != root && Path::new(r).starts_with(root))
+        .collect()
+}
+
+pub fn overlap(imgdirs: &ImgDirs, root: &str) -> RootOverlap {
+    RootOverlap {
+        parent: ancestors(imgdirs.keys(), root)
+            .first()
+            .map(|r| r.to_string()),
+        children: children(imgdirs.keys(), root)
+            .into_iter()
+            .map(|r| r.to_string())
+            .collect(),
+    }
+}
+
+/**
+ * path 是否归属于 root，root 不在 imgdirs 中时同样参与比较
+ */
+pub fn is_owner(imgdirs: &ImgDirs, root: &str, path: &Path) -> bool {
+    path.starts_with(root)
+        && !children(imgdirs.keys(), root)
+            .into_iter()
+            .any(|c| path.starts_with(c))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::image_command::ImgDir;
+
+    fn imgdirs(roots: &[&str]) -> ImgDirs {
+        roots
+            .iter()
+            .map(|r| {
+                let imgdir: ImgDir = serde_json::from_value(serde_json::json!({
+                    "name": r,
+                    "root": r,
+                    "enableRename": false,
+                }))
+                .unwrap();
+                (r.to_string(), imgdir)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn ancestors_deepest_first() {
+        let roots = ["/photos", "/photos/2023", "/photos/2023/trip", "/photos2"].map(String::from);
+
+        assert_eq!(
+            ancestors(&roots, "/photos/2023/trip"),
+            ["/photos/2023", "/photos"]
+        );
+        assert!(ancestors(&roots, "/photos").is_empty());
+        // 按路径组件比较，/photos2 不在 /photos 下
+        assert!(ancestors(&roots, "/photos2").is_empty());
+    }
+
+    #[test]
+    fn children_are_nested_roots() {
+        let roots = ["/photos", "/photos/2023", "/photos/2023/trip", "/photos2"].map(String::from);
+
+        let mut c = children(&roots, "/photos");
+        c.sort();
+        assert_eq!(c, ["/photos/2023", "/photos/2023/trip"]);
+        assert!(children(&roots, "/photos2").is_empty());
+    }
+
+    #[test]
+    fn overlap_reports_parent_and_children() {
+        let dirs = imgdirs(&["/photos", "/photos/2023/trip"]);
+
+        let o = overlap(&dirs, "/photos/2023");
+        assert_eq!(o.parent.as_deref(), Some("/photos"));
+        assert_eq!(o.children, ["/photos/2023/trip"]);
+
+        let o = overlap(&dirs, "/other");
+        assert!(o.parent.is_none());
+        assert!(o.children.is_empty());
+    }
+
+    #[test]
+    fn owner_is_deepest_root() {
+        let dirs = imgdirs(&["/photos", "/photos/2023"]);
+
+        assert!(is_owner(&dirs, "/photos", Path::new("/photos/a.jpg")));
+        assert!(is_owner(&dirs, "/photos", Path::new("/photos/2022/a.jpg")));
+        assert!(!is_owner(&dirs, "/photos", Path::new("/photos/2023/a.jpg")));
+        assert!(is_owner(
+            &dirs,
+            "/photos/2023",
+            Path::new("/photos/2023/a.jpg")
+        ));
+        assert!(!is_owner(&dirs, "/photos/2023", Path::new("/photos/a.jpg")));
+        // 尚未加入 imgdirs 的 root
+        assert!(is_owner(
+            &dirs,
+            "/photos/2023/trip",
+            Path::new("/photos/2023/trip/a.jpg")
+        ));
+        assert!(is_owner(&dirs, "/photos", Path::new("/photos/20230/a.jpg")));
+    }
+}
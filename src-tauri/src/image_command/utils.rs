@@ -11,13 +11,79 @@ use bytes::Bytes;
 use fast_image_resize::{images::Image, IntoImageView, Resizer};
 use image::{
     codecs::{jpeg, png, webp},
-    ImageEncoder, ImageFormat,
+    ImageEncoder, ImageFormat, ImageReader,
 };
+use serde::{Deserialize, Serialize};
 
 use crate::error::AppError;
 
 const IMAGE_WIDTH: u32 = 512;
 
+/**
+ * 跳过图标、精灵图等小图的阈值，0 表示不限制
+ */
+#[derive(Deserialize, Serialize, Clone, Debug, Default)]
+#[serde(rename_all = "camelCase", default)]
+pub struct SizeThresholds {
+    pub min_width: u32,
+    pub min_height: u32,
+    pub min_bytes: u64,
+    pub max_bytes: u64,
+    // 跳过完全透明的图片
+    pub skip_transparent: bool,
+}
+
+impl SizeThresholds {
+    /**
+     * 生成缩略图前检查，返回跳过的原因
+     * 尺寸只读取文件头，透明检查需要解码整张图片
+     */
+    pub fn check(&self, path: &Path) -> Result<Option<String>, AppError> {
+        let size = std::fs::metadata(path)
+            .map_err(|e| AppError::io(e, path))?
+            .len();
+        if size < self.min_bytes {
+            return Ok(Some(format!(
+                "file size {size} bytes is less than {} bytes",
+                self.min_bytes
+            )));
+        }
+        if self.max_bytes > 0 && size > self.max_bytes {
+            return Ok(Some(format!(
+                "file size {size} bytes is greater than {} bytes",
+                self.max_bytes
+            )));
+        }
+
+        if self.min_width > 0 || self.min_height > 0 {
+            let (w, h) = ImageReader::open(path)
+                .map_err(|e| AppError::io(e, path))?
+                .with_guessed_format()
+                .map_err(|e| AppError::io(e, path))?
+                .into_dimensions()?;
+            if w < self.min_width || h < self.min_height {
+                return Ok(Some(format!(
+                    "image size {w}x{h} is less than {}x{}",
+                    self.min_width, self.min_height
+                )));
+            }
+        }
+
+        if self.skip_transparent {
+            let img = ImageReader::open(path)
+                .map_err(|e| AppError::io(e, path))?
+                .with_guessed_format()
+                .map_err(|e| AppError::io(e, path))?
+                .decode()?;
+            if img.color().has_alpha() && img.to_rgba8().pixels().all(|p| p[3] == 0) {
+                return Ok(Some("image is fully transparent".to_string()));
+            }
+        }
+
+        Ok(None)
+    }
+}
+
 pub fn guess_format(buf: &[u8]) -> Result<image::ImageFormat, AppError> {
     Ok(image::guess_format(buf)?)
 }
@@ -42,6 +108,14 @@ fn save_local(root: &Path, bs: &[u8], format: ImageFormat) -> Result<PathBuf, Ap
     Ok(p)
 }
 
+/**
+ * 保存已有的缩略图数据，如导入的缩略图
+ */
+pub fn save_thumbnail(root: &str, bs: &[u8]) -> Result<PathBuf, AppError> {
+    let format = guess_format(bs)?;
+    save_local(Path::new(root), bs, format)
+}
+
 pub fn remove_dir(root: &str) -> Result<(), AppError> {
     let root_hex = sign(root.as_bytes());
 
@@ -51,7 +125,7 @@ pub fn remove_dir(root: &str) -> Result<(), AppError> {
 }
 
 pub fn gen_thumbnail(root: &str, path: &Path) -> Result<(String, PathBuf), AppError> {
-    let source_bs = std::fs::read(path)?;
+    let source_bs = std::fs::read(path).map_err(|e| AppError::io(e, path))?;
 
     let sign = path_utils::sign(&source_bs);
 
@@ -66,6 +140,30 @@ pub fn gen_thumbnail(root: &str, path: &Path) -> Result<(String, PathBuf), AppEr
     Ok((sign, thumbnail_path))
 }
 
+/**
+ * 生成临时缩略图，用于未索引图片的相似搜索，调用方负责删除
+ */
+pub fn gen_temp_thumbnail(path: &Path) -> Result<PathBuf, AppError> {
+    let source_bs = std::fs::read(path).map_err(|e| AppError::io(e, path))?;
+
+    let format = guess_format(source_bs.as_slice())?;
+    let bs = downscale(&source_bs, format)?;
+
+    let p = std::env::temp_dir().join(format!(
+        "imgsearch_{}.{}",
+        uuid_utils::get(),
+        format.extensions_str()[0]
+    ));
+
+    match bs {
+        Some(bs) => std::fs::write(&p, bs.as_ref()),
+        None => std::fs::write(&p, &source_bs),
+    }
+    .map_err(|e| AppError::io(e, &p))?;
+
+    Ok(p)
+}
+
 /**
  * 缩放图片，当传入图片的宽度小于512，则不进行缩放
  */
@@ -77,12 +175,11 @@ pub fn downscale(buf: &[u8], format: ImageFormat) -> Result<Option<Bytes>, AppEr
     }
 
     let target_width = IMAGE_WIDTH;
-    let target_height: u32 = IMAGE_WIDTH * src_image.height() / src_image.width();
-    let pixel_type = src_image.pixel_type();
-    if pixel_type.is_none() {
-        return Err(AppError::ImgFormat("pixel_type is none".to_string()));
-    }
-    let mut dst_image = Image::new(target_width, target_height, pixel_type.unwrap());
+    let target_height: u32 = (IMAGE_WIDTH * src_image.height() / src_image.width()).max(1);
+    let pixel_type = src_image.pixel_type().ok_or_else(|| {
+        AppError::Image(format!("unsupported pixel layout: {:?}", src_image.color()))
+    })?;
+    let mut dst_image = Image::new(target_width, target_height, pixel_type);
 
     // Create Resizer instance and resize source image
     // into buffer of destination image
@@ -93,36 +190,34 @@ pub fn downscale(buf: &[u8], format: ImageFormat) -> Result<Option<Bytes>, AppEr
     let mut writer = BufWriter::new(Vec::new());
     match format {
         ImageFormat::Png => {
-            png::PngEncoder::new(&mut writer)
-                .write_image(
-                    dst_image.buffer(),
-                    target_width,
-                    target_height,
-                    src_image.color().into(),
-                )
-                .unwrap();
+            png::PngEncoder::new(&mut writer).write_image(
+                dst_image.buffer(),
+                target_width,
+                target_height,
+                src_image.color().into(),
+            )?;
         }
         ImageFormat::Jpeg => {
-            jpeg::JpegEncoder::new(&mut writer)
-                .write_image(
-                    dst_image.buffer(),
-                    target_width,
-                    target_height,
-                    src_image.color().into(),
-                )
-                .unwrap();
+            jpeg::JpegEncoder::new(&mut writer).write_image(
+                dst_image.buffer(),
+                target_width,
+                target_height,
+                src_image.color().into(),
+            )?;
         }
         ImageFormat::WebP => {
-            webp::WebPEncoder::new_lossless(&mut writer)
-                .write_image(
-                    dst_image.buffer(),
-                    target_width,
-                    target_height,
-                    src_image.color().into(),
-                )
-                .unwrap();
+            webp::WebPEncoder::new_lossless(&mut writer).write_image(
+                dst_image.buffer(),
+                target_width,
+                target_height,
+                src_image.color().into(),
+            )?;
+        }
+        _ => {
+            return Err(AppError::ImgFormat(format!(
+                "unsupported image format: {format:?}"
+            )));
         }
-        _ => {}
     };
 
     // 将 writer  写入到本地的image.png文件中
@@ -134,6 +229,141 @@ pub fn downscale(buf: &[u8], format: ImageFormat) -> Result<Option<Bytes>, AppEr
 
 impl<T> From<IntoInnerError<T>> for AppError {
     fn from(e: IntoInnerError<T>) -> Self {
-        AppError::Internal(format!("IntoInnerError: {:?}", e.error()))
+        AppError::Io {
+            message: format!("IntoInnerError: {:?}", e.error()),
+            path: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write_temp(ext: &str, bs: &[u8]) -> PathBuf {
+        let p = std::env::temp_dir().join(format!("imgsearch_test_{}.{ext}", uuid_utils::get()));
+        std::fs::write(&p, bs).unwrap();
+        p
+    }
+
+    fn jpeg_bytes(width: u32, height: u32) -> Vec<u8> {
+        let img = image::RgbImage::from_fn(width, height, |x, y| {
+            image::Rgb([(x % 256) as u8, (y % 256) as u8, 128])
+        });
+        let mut bs = Vec::new();
+        jpeg::JpegEncoder::new(&mut bs)
+            .write_image(img.as_raw(), width, height, image::ExtendedColorType::Rgb8)
+            .unwrap();
+        bs
+    }
+
+    // 直接调用 downscale，gen_thumbnail 会把缩略图写入数据目录
+    #[test]
+    fn truncated_jpeg_returns_error() {
+        let bs = jpeg_bytes(800, 600);
+        // 截断在文件头中，解码器容忍缺失的扫描数据，但无法读取尺寸
+        let r = downscale(&bs[..20], ImageFormat::Jpeg);
+
+        assert!(r.is_err());
+    }
+
+    #[test]
+    fn garbage_png_returns_error() {
+        let mut bs = b"\x89PNG\r\n\x1a\n".to_vec();
+        bs.extend((0..4096u32).map(|i| (i * 31 % 251) as u8));
+
+        assert!(downscale(&bs, ImageFormat::Png).is_err());
+    }
+
+    fn png_bytes(width: u32, height: u32, alpha: u8) -> Vec<u8> {
+        let img = image::RgbaImage::from_pixel(width, height, image::Rgba([10, 20, 30, alpha]));
+        let mut bs = Vec::new();
+        png::PngEncoder::new(&mut bs)
+            .write_image(img.as_raw(), width, height, image::ExtendedColorType::Rgba8)
+            .unwrap();
+        bs
+    }
+
+    #[test]
+    fn thresholds_default_keeps_everything() {
+        let p = write_temp("png", &png_bytes(1, 1, 0));
+        let r = SizeThresholds::default().check(&p).unwrap();
+        std::fs::remove_file(&p).unwrap();
+
+        assert!(r.is_none());
+    }
+
+    #[test]
+    fn thresholds_file_size() {
+        let bs = jpeg_bytes(64, 64);
+        let p = write_temp("jpg", &bs);
+        let len = bs.len() as u64;
+
+        let check = |t: SizeThresholds| t.check(&p).unwrap();
+        let too_small = check(SizeThresholds {
+            min_bytes: len + 1,
+            ..Default::default()
+        });
+        let too_large = check(SizeThresholds {
+            max_bytes: len - 1,
+            ..Default::default()
+        });
+        let ok = check(SizeThresholds {
+            min_bytes: len,
+            max_bytes: len,
+            ..Default::default()
+        });
+        std::fs::remove_file(&p).unwrap();
+
+        assert!(too_small.unwrap().contains("less than"));
+        assert!(too_large.unwrap().contains("greater than"));
+        assert!(ok.is_none());
+    }
+
+    #[test]
+    fn thresholds_dimensions() {
+        let p = write_temp("jpg", &jpeg_bytes(64, 32));
+
+        let check = |min_width, min_height| {
+            SizeThresholds {
+                min_width,
+                min_height,
+                ..Default::default()
+            }
+            .check(&p)
+            .unwrap()
+        };
+        let narrow = check(65, 0);
+        let short = check(0, 33);
+        let ok = check(64, 32);
+        std::fs::remove_file(&p).unwrap();
+
+        assert_eq!(narrow.unwrap(), "image size 64x32 is less than 65x0");
+        assert!(short.is_some());
+        assert!(ok.is_none());
+    }
+
+    #[test]
+    fn thresholds_transparent() {
+        let transparent = write_temp("png", &png_bytes(8, 8, 0));
+        let visible = write_temp("png", &png_bytes(8, 8, 1));
+        let t = SizeThresholds {
+            skip_transparent: true,
+            ..Default::default()
+        };
+
+        let r1 = t.check(&transparent).unwrap();
+        let r2 = t.check(&visible).unwrap();
+        std::fs::remove_file(&transparent).unwrap();
+        std::fs::remove_file(&visible).unwrap();
+
+        assert_eq!(r1.unwrap(), "image is fully transparent");
+        assert!(r2.is_none());
+    }
+
+    #[test]
+    fn downscale_keeps_small_images() {
+        let bs = jpeg_bytes(64, 48);
+        assert!(downscale(&bs, ImageFormat::Jpeg).unwrap().is_none());
     }
 }
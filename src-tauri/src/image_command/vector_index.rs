@@ -0,0 +1,234 @@
+/**
+ * embedding 列的向量索引：按记录数选择索引类型与参数，表增长到一定倍数后重新训练
+ */
+use std::{
+    collections::HashSet,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+};
+
+use lancedb::{
+    index::{
+        vector::{IvfFlatIndexBuilder, IvfPqIndexBuilder},
+        Index,
+    },
+    Table,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    error::AppError,
+    image_command::idx::{sql_str, DIM, STATUS_INDEXED},
+    path_utils,
+};
+
+static EMBEDDING_COLUMN: &str = "embedding";
+// 保存上次训练的参数，每个表一个文件，放在 db 目录下，随备份一起拷贝
+static STATE_FILE_PREFIX: &str = "vector_index";
+// 少于该数量时直接暴力搜索
+const MIN_ROWS: usize = 256;
+// 少于该数量时使用 IVF_FLAT，距离精确；更多时使用 IVF_PQ 压缩
+const PQ_MIN_ROWS: usize = 50_000;
+// 记录数达到上次训练时的倍数后重新训练
+const RETRAIN_FACTOR: usize = 2;
+
+// 正在建立索引的表
+static BUILDING: Mutex<Option<HashSet<String>>> = Mutex::new(None);
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum IndexKind {
+    IvfFlat,
+    IvfPq,
+}
+
+/**
+ * 根据记录数得到的索引参数
+ */
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct IndexPlan {
+    pub kind: IndexKind,
+    pub partitions: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sub_vectors: Option<u32>,
+    // 训练时的记录数
+    pub rows: usize,
+}
+
+impl IndexPlan {
+    pub fn for_rows(rows: usize) -> Option<Self> {
+        if rows < MIN_ROWS {
+            return None;
+        }
+
+        // 每个分区约 sqrt(rows) 条
+        let partitions = ((rows as f64).sqrt() as u32).max(1);
+        let plan = if rows < PQ_MIN_ROWS {
+            IndexPlan {
+                kind: IndexKind::IvfFlat,
+                partitions,
+                sub_vectors: None,
+                rows,
+            }
+        } else {
+            IndexPlan {
+                kind: IndexKind::IvfPq,
+                partitions,
+                // 每个子向量 16 维
+                sub_vectors: Some(DIM as u32 / 16),
+                rows,
+            }
+        };
+        Some(plan)
+    }
+
+    fn index(&self) -> Index {
+        match self.kind {
+            IndexKind::IvfFlat => {
+                Index::IvfFlat(IvfFlatIndexBuilder::default().num_partitions(self.partitions))
+            }
+            IndexKind::IvfPq => {
+                let mut builder = IvfPqIndexBuilder::default().num_partitions(self.partitions);
+                if let Some(m) = self.sub_vectors {
+                    builder = builder.num_sub_vectors(m);
+                }
+                Index::IvfPq(builder)
+            }
+        }
+    }
+
+    fn needs_retrain(&self, rows: usize) -> bool {
+        match IndexPlan::for_rows(rows) {
+            Some(p) => p.kind != self.kind || rows >= self.rows * RETRAIN_FACTOR,
+            None => false,
+        }
+    }
+}
+
+fn state_path(table: &str) -> Result<PathBuf, AppError> {
+    Ok(path_utils::lancedb_dir()?.join(format!("{STATE_FILE_PREFIX}.{table}.json")))
+}
+
+fn load_plan(table: &str) -> Option<IndexPlan> {
+    let p = state_path(table).ok()?;
+    let s = std::fs::read_to_string(p).ok()?;
+    serde_json::from_str(&s).ok()
+}
+
+fn save_plan(table: &str, plan: &IndexPlan) -> Result<(), AppError> {
+    let p = state_path(table)?;
+    std::fs::write(&p, serde_json::to_string(plan)?).map_err(|e| AppError::io(e, &p))?;
+    Ok(())
+}
+
+async fn has_index(table: &Table) -> Result<bool, AppError> {
+    let indices = table.list_indices().await?;
+    Ok(indices
+        .iter()
+        .any(|i| i.columns.iter().any(|c| c == EMBEDDING_COLUMN)))
+}
+
+/**
+ * 没有索引时建立，表增长到训练时的 RETRAIN_FACTOR 倍后重建
+ * 新增数据在两次训练之间由 maintenance 合入已有索引
+ */
+pub async fn check_or_build(table: Arc<Table>) -> Result<(), AppError> {
+    let name = table.name().to_string();
+    if !BUILDING
+        .lock()
+        .unwrap()
+        .get_or_insert_with(HashSet::new)
+        .insert(name.clone())
+    {
+        return Ok(());
+    }
+    let r = build_if_needed(&table).await;
+    if let Some(building) = BUILDING.lock().unwrap().as_mut() {
+        building.remove(&name);
+    }
+    r
+}
+
+/**
+ * 已索引的记录数，未索引与失败的记录 embedding 为全 null 的列表，不参与训练
+ */
+async fn indexed_rows(table: &Table) -> Result<usize, AppError> {
+    let filter = format!("status = {}", sql_str(STATUS_INDEXED));
+    Ok(table.count_rows(Some(filter)).await?)
+}
+
+async fn build_if_needed(table: &Table) -> Result<(), AppError> {
+    let rows = indexed_rows(table).await?;
+    let Some(plan) = IndexPlan::for_rows(rows) else {
+        return Ok(());
+    };
+
+    let exists = has_index(table).await?;
+    let rebuild = match load_plan(table.name()) {
+        Some(old) if exists => old.needs_retrain(rows),
+        _ => true,
+    };
+    if !rebuild {
+        return Ok(());
+    }
+
+    log::info!("build vector index, {plan:?}");
+    table
+        .create_index(&[EMBEDDING_COLUMN], plan.index())
+        .replace(true)
+        .execute()
+        .await?;
+    save_plan(table.name(), &plan)?;
+
+    Ok(())
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct IndexInfo {
+    pub name: String,
+    pub columns: Vec<String>,
+    pub index_type: String,
+    pub indexed_rows: usize,
+    pub unindexed_rows: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub distance_type: Option<String>,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct IndexReport {
+    pub rows: usize,
+    // 上次训练使用的参数
+    pub trained: Option<IndexPlan>,
+    // 按当前记录数应使用的参数
+    pub recommended: Option<IndexPlan>,
+    pub indices: Vec<IndexInfo>,
+}
+
+pub async fn report(table: Arc<Table>) -> Result<IndexReport, AppError> {
+    let rows = indexed_rows(&table).await?;
+    let mut indices = vec![];
+
+    for config in table.list_indices().await? {
+        let Some(stats) = table.index_stats(&config.name).await? else {
+            continue;
+        };
+        indices.push(IndexInfo {
+            name: config.name,
+            columns: config.columns,
+            index_type: format!("{}", stats.index_type),
+            indexed_rows: stats.num_indexed_rows,
+            unindexed_rows: stats.num_unindexed_rows,
+            distance_type: stats.distance_type.map(|d| format!("{d:?}")),
+        });
+    }
+
+    Ok(IndexReport {
+        rows,
+        trained: load_plan(table.name()),
+        recommended: IndexPlan::for_rows(rows),
+        indices,
+    })
+}
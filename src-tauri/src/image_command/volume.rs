@@ -0,0 +1,351 @@
+/**
+ * imgdir 所在卷的状态，保存在 img_root 表中
+ * 移动硬盘、网络盘断开时 root 标记为离线，保留索引记录，重新连接后继续索引
+ */
+use std::{
+    collections::{HashMap, HashSet},
+    path::Path,
+    sync::{Arc, OnceLock},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use arrow_array::{
+    builder::{BooleanBuilder, Int64Builder, StringBuilder},
+    Array, ArrayRef, RecordBatch, RecordBatchIterator,
+};
+use futures::TryStreamExt;
+use itertools::Itertools;
+use lancedb::{
+    arrow::{
+        arrow_schema::{DataType, Field, Schema},
+        IntoArrowStream,
+    },
+    query::{ExecutableQuery, QueryBase},
+    Connection, Table,
+};
+use serde::Serialize;
+use tauri::{async_runtime::RwLock, Wry};
+use tauri_plugin_store::Store;
+
+use crate::{
+    error::AppError,
+    image_command::{
+        api,
+        idx::{sql_str, ImgSearchResult},
+        load_imgdirs, ImgDirs,
+    },
+    server::imgsearch_server::ImgseachServer,
+    settings_command::index_settings,
+};
+
+static IMG_ROOT_TABLE_NAME: &str = "img_root";
+static SCHEMA: OnceLock<Arc<Schema>> = OnceLock::new();
+
+const CHECK_INTERVAL: Duration = Duration::from_secs(30);
+
+#[derive(Serialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct RootState {
+    pub root: String,
+    // 卷标识，unix 为设备号，windows 为盘符或共享路径
+    pub volume: Option<String>,
+    pub online: bool,
+    // 最后一次在线的时间，unix 秒
+    pub last_seen: Option<i64>,
+}
+
+fn get_schema() -> &'static Arc<Schema> {
+    SCHEMA.get_or_init(|| {
+        Arc::new(Schema::new(vec![
+            Field::new("root", DataType::Utf8, false),
+            Field::new("volume", DataType::Utf8, true),
+            Field::new("online", DataType::Boolean, false),
+            Field::new("last_seen", DataType::Int64, true),
+        ]))
+    })
+}
+
+pub async fn get_table(db: &Connection) -> Result<Table, AppError> {
+    let tbls = db.table_names().execute().await?;
+    if tbls.contains(&IMG_ROOT_TABLE_NAME.to_string()) {
+        return Ok(db.open_table(IMG_ROOT_TABLE_NAME).execute().await?);
+    }
+
+    Ok(db
+        .create_empty_table(IMG_ROOT_TABLE_NAME, get_schema().clone())
+        .execute()
+        .await?)
+}
+
+fn map_batch_to_state(batch: &RecordBatch) -> Result<Vec<RootState>, AppError> {
+    let root_array = batch
+        .column_by_name("root")
+        .ok_or_else(|| AppError::Db("Missing column: root".to_string()))?
+        .as_any()
+        .downcast_ref::<arrow_array::StringArray>()
+        .ok_or_else(|| AppError::Db("Invalid type for column: root".to_string()))?;
+
+    let volume_array = batch
+        .column_by_name("volume")
+        .ok_or_else(|| AppError::Db("Missing column: volume".to_string()))?
+        .as_any()
+        .downcast_ref::<arrow_array::StringArray>()
+        .ok_or_else(|| AppError::Db("Invalid type for column: volume".to_string()))?;
+
+    let online_array = batch
+        .column_by_name("online")
+        .ok_or_else(|| AppError::Db("Missing column: online".to_string()))?
+        .as_any()
+        .downcast_ref::<arrow_array::BooleanArray>()
+        .ok_or_else(|| AppError::Db("Invalid type for column: online".to_string()))?;
+
+    let last_seen_array = batch
+        .column_by_name("last_seen")
+        .ok_or_else(|| AppError::Db("Missing column: last_seen".to_string()))?
+        .as_any()
+        .downcast_ref::<arrow_array::Int64Array>()
+        .ok_or_else(|| AppError::Db("Invalid type for column: last_seen".to_string()))?;
+
+    Ok((0..batch.num_rows())
+        .map(|row| RootState {
+            root: root_array.value(row).to_string(),
+            volume: (!volume_array.is_null(row)).then(|| volume_array.value(row).to_string()),
+            online: online_array.value(row),
+            last_seen: (!last_seen_array.is_null(row)).then(|| last_seen_array.value(row)),
+        })
+        .collect())
+}
+
+pub async fn load(table: Arc<Table>) -> Result<Vec<RootState>, AppError> {
+    let stream = table.query().execute().await?.into_arrow()?;
+    let mut results = Vec::new();
+
+    // 消费 stream
+    futures::pin_mut!(stream);
+    while let Some(batch) = stream.try_next().await? {
+        let mut items = map_batch_to_state(&batch)?;
+        results.append(&mut items);
+    }
+    Ok(results)
+}
+
+async fn save(table: Arc<Table>, records: &[RootState]) -> Result<(), AppError> {
+    if records.is_empty() {
+        return Ok(());
+    }
+
+    let mut root_builder = StringBuilder::new();
+    let mut volume_builder = StringBuilder::new();
+    let mut online_builder = BooleanBuilder::new();
+    let mut last_seen_builder = Int64Builder::new();
+
+    for r in records.iter() {
+        root_builder.append_value(&r.root);
+        volume_builder.append_option(r.volume.as_ref());
+        online_builder.append_value(r.online);
+        last_seen_builder.append_option(r.last_seen);
+    }
+
+    let schema = get_schema().clone();
+    let batch = RecordBatch::try_new(
+        schema.clone(),
+        vec![
+            Arc::new(root_builder.finish()) as ArrayRef,
+            Arc::new(volume_builder.finish()) as ArrayRef,
+            Arc::new(online_builder.finish()) as ArrayRef,
+            Arc::new(last_seen_builder.finish()) as ArrayRef,
+        ],
+    )?;
+
+    let reader = Box::new(RecordBatchIterator::new(
+        vec![batch].into_iter().map(Ok),
+        schema,
+    ));
+
+    let mut merge_insert = table.merge_insert(&["root"]);
+    merge_insert
+        .when_matched_update_all(None)
+        .when_not_matched_insert_all();
+
+    merge_insert.execute(reader).await?;
+
+    Ok(())
+}
+
+/**
+ * root 所在卷的标识，无法读取时返回 None
+ */
+#[cfg(unix)]
+fn volume_id(root: &Path) -> Option<String> {
+    use std::os::unix::fs::MetadataExt;
+
+    std::fs::metadata(root)
+        .ok()
+        .map(|m| format!("dev:{:x}", m.dev()))
+}
+
+#[cfg(windows)]
+fn volume_id(root: &Path) -> Option<String> {
+    use std::path::Component;
+
+    match root.components().next() {
+        Some(Component::Prefix(p)) => Some(p.as_os_str().to_string_lossy().to_uppercase()),
+        _ => None,
+    }
+}
+
+#[cfg(not(any(unix, windows)))]
+fn volume_id(_root: &Path) -> Option<String> {
+    None
+}
+
+/**
+ * root 目录存在且可以读取
+ */
+pub fn is_available(root: &str) -> bool {
+    std::fs::read_dir(root).is_ok()
+}
+
+/**
+ * path 所在的某个 root 不可用，此时文件监听报告的删除不可信
+ */
+pub fn is_offline_path(imgdirs: &ImgDirs, path: &str) -> bool {
+    imgdirs
+        .keys()
+        .filter(|r| Path::new(path).starts_with(r))
+        .any(|r| !is_available(r))
+}
+
+/**
+ * 检查所有 imgdir 的状态并保存，返回由离线变为在线的 root
+ */
+pub async fn refresh(table: Arc<Table>, imgdirs: &ImgDirs) -> Result<Vec<String>, AppError> {
+    let mut existing = load(table.clone())
+        .await?
+        .into_iter()
+        .map(|r| (r.root.clone(), r))
+        .collect::<HashMap<_, _>>();
+
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .ok();
+
+    let mut resumed = vec![];
+    let mut records = Vec::with_capacity(imgdirs.len());
+    for root in imgdirs.keys().sorted() {
+        let online = is_available(root);
+        let prev = existing.remove(root);
+
+        let volume = if online {
+            volume_id(Path::new(root))
+        } else {
+            prev.as_ref().and_then(|p| p.volume.clone())
+        };
+
+        match &prev {
+            Some(p) if !p.online && online => {
+                log::info!("root {root} is back online");
+                resumed.push(root.clone());
+            }
+            Some(p) if p.online && !online => log::warn!("root {root} is offline"),
+            None if !online => log::warn!("root {root} is offline"),
+            _ => {}
+        }
+        if let (Some(prev), Some(cur)) = (prev.as_ref().and_then(|p| p.volume.as_ref()), &volume) {
+            if prev != cur {
+                log::warn!("root {root} is now on another volume, {prev} -> {cur}");
+            }
+        }
+
+        records.push(RootState {
+            root: root.clone(),
+            volume,
+            online,
+            last_seen: if online {
+                now
+            } else {
+                prev.and_then(|p| p.last_seen)
+            },
+        });
+    }
+
+    save(table.clone(), &records).await?;
+
+    // 已移除的 imgdir
+    if !existing.is_empty() {
+        let roots = existing.keys().map(|r| sql_str(r)).join(",");
+        table.delete(&format!("root in ({roots})")).await?;
+    }
+
+    Ok(resumed)
+}
+
+/**
+ * 为结果附加离线标记
+ */
+pub async fn attach(table: Arc<Table>, results: &mut [ImgSearchResult]) -> Result<(), AppError> {
+    if results.is_empty() {
+        return Ok(());
+    }
+
+    let offline = load(table)
+        .await?
+        .into_iter()
+        .filter(|r| !r.online)
+        .map(|r| r.root)
+        .collect::<HashSet<_>>();
+
+    for r in results.iter_mut() {
+        r.offline = offline.contains(&r.root);
+    }
+    Ok(())
+}
+
+/**
+ * 定期检查 imgdir 所在的卷，重新连接后继续索引
+ */
+pub fn schedule(
+    img_idx_tbl: Arc<Table>,
+    img_root_tbl: Arc<Table>,
+    imgdir_store: Arc<Store<Wry>>,
+    settings_store: Arc<Store<Wry>>,
+    server: Arc<RwLock<Option<ImgseachServer>>>,
+) {
+    tauri::async_runtime::spawn(async move {
+        // 启动时未完成的图片由 on_start_up 处理，第一次检查不继续索引
+        let mut first = true;
+        loop {
+            let imgdirs = load_imgdirs(imgdir_store.entries());
+            let resumed = match refresh(img_root_tbl.clone(), &imgdirs).await {
+                Ok(r) => r,
+                Err(e) => {
+                    log::error!("refresh root state error, {e}");
+                    vec![]
+                }
+            };
+
+            if !first && !resumed.is_empty() {
+                let settings = index_settings(&settings_store);
+                // 继续索引可能持续很久，不持有读锁
+                let server = server.read().await.clone();
+                for root in resumed.iter() {
+                    let r = api::resume_root(
+                        root,
+                        &imgdirs,
+                        server.as_ref(),
+                        &settings,
+                        img_idx_tbl.clone(),
+                    )
+                    .await;
+                    if let Err(e) = r {
+                        log::error!("resume root {root} error, {e}");
+                    }
+                }
+            }
+
+            first = false;
+            tokio::time::sleep(CHECK_INTERVAL).await;
+        }
+    });
+}
@@ -0,0 +1,278 @@
+/**
+ * 把描述、标签写入 .xmp 附属文件，供 Lightroom、Finder 等工具读取；也能读取已有的附属文件
+ * 只覆盖本应用生成的附属文件，其他工具生成的保持不变
+ */
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use lancedb::Table;
+use serde::Serialize;
+
+use crate::{
+    error::AppError,
+    image_command::{
+        idx::{self, sql_str, ImgIdx},
+        meta::{self, MetaChange},
+    },
+};
+
+// 本应用的命名空间，同时用于判断附属文件是否由本应用生成
+static IMGSEARCH_NS: &str = "https://imgsearch.dev/ns/1.0/";
+
+#[derive(Debug, Default, PartialEq)]
+pub struct XmpData {
+    pub description: Option<String>,
+    pub subject: Vec<String>,
+    pub suggested_name: Option<String>,
+}
+
+#[derive(Serialize, Debug, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct XmpReport {
+    pub written: usize,
+    pub unchanged: usize,
+    // 其他工具生成的附属文件，不覆盖
+    pub skipped: usize,
+    pub failed: usize,
+}
+
+/**
+ * photo.jpg -> photo.xmp，与 Lightroom 的约定一致
+ */
+pub fn sidecar_path(img: &Path) -> PathBuf {
+    img.with_extension("xmp")
+}
+
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn unescape(s: &str) -> String {
+    s.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+fn render(data: &XmpData) -> String {
+    let mut fields = String::new();
+    if let Some(desc) = &data.description {
+        fields.push_str(&format!(
+            "   <dc:description><rdf:Alt><rdf:li xml:lang=\"x-default\">{}</rdf:li></rdf:Alt></dc:description>\n",
+            escape(desc)
+        ));
+    }
+    if !data.subject.is_empty() {
+        let items = data
+            .subject
+            .iter()
+            .map(|s| format!("<rdf:li>{}</rdf:li>", escape(s)))
+            .collect::<String>();
+        fields.push_str(&format!(
+            "   <dc:subject><rdf:Bag>{items}</rdf:Bag></dc:subject>\n"
+        ));
+    }
+    if let Some(name) = &data.suggested_name {
+        fields.push_str(&format!(
+            "   <imgsearch:SuggestedName>{}</imgsearch:SuggestedName>\n",
+            escape(name)
+        ));
+    }
+
+    format!(
+        "<?xpacket begin=\"\u{feff}\" id=\"W5M0MpCehiHzreSzNTczkc9d\"?>
+<x:xmpmeta xmlns:x=\"adobe:ns:meta/\">
+ <rdf:RDF xmlns:rdf=\"http://www.w3.org/1999/02/22-rdf-syntax-ns#\">
+  <rdf:Description rdf:about=\"\"
+    xmlns:dc=\"http://purl.org/dc/elements/1.1/\"
+    xmlns:imgsearch=\"{IMGSEARCH_NS}\">
+{fields}  </rdf:Description>
+ </rdf:RDF>
+</x:xmpmeta>
+<?xpacket end=\"w\"?>
+"
+    )
+}
+
+/**
+ * 取 <tag ...>内容</tag> 中的内容
+ */
+fn element<'a>(xml: &'a str, tag: &str) -> Option<&'a str> {
+    let start = xml.find(&format!("<{tag}"))?;
+    let rest = &xml[start..];
+    let open_end = rest.find('>')?;
+    if rest[..open_end].ends_with('/') {
+        return None;
+    }
+    let rest = &rest[open_end + 1..];
+    let end = rest.find(&format!("</{tag}>"))?;
+    Some(&rest[..end])
+}
+
+fn list_items(xml: &str) -> Vec<String> {
+    let mut r = vec![];
+    let mut rest = xml;
+    while let Some(item) = element(rest, "rdf:li") {
+        let item_text = item.trim();
+        if !item_text.is_empty() {
+            r.push(unescape(item_text));
+        }
+        match rest.find("</rdf:li>") {
+            Some(i) => rest = &rest[i + "</rdf:li>".len()..],
+            None => break,
+        }
+    }
+    r
+}
+
+/**
+ * 只解析 dc:description、dc:subject 与建议的文件名
+ */
+pub fn parse(xml: &str) -> XmpData {
+    XmpData {
+        description: element(xml, "dc:description").and_then(|d| list_items(d).into_iter().next()),
+        subject: element(xml, "dc:subject")
+            .map(list_items)
+            .unwrap_or_default(),
+        suggested_name: element(xml, "imgsearch:SuggestedName").map(|n| unescape(n.trim())),
+    }
+}
+
+pub fn read(img: &Path) -> Option<XmpData> {
+    let xml = std::fs::read_to_string(sidecar_path(img)).ok()?;
+    Some(parse(&xml))
+}
+
+enum WriteOutcome {
+    Written,
+    Unchanged,
+    Foreign,
+}
+
+fn write(img: &Path, data: &XmpData) -> Result<WriteOutcome, AppError> {
+    let p = sidecar_path(img);
+    let xml = render(data);
+
+    if p.exists() {
+        let old = std::fs::read_to_string(&p).map_err(|e| AppError::io(e, &p))?;
+        if !old.contains(IMGSEARCH_NS) {
+            return Ok(WriteOutcome::Foreign);
+        }
+        if old == xml {
+            return Ok(WriteOutcome::Unchanged);
+        }
+    }
+
+    std::fs::write(&p, xml).map_err(|e| AppError::io(e, &p))?;
+    Ok(WriteOutcome::Written)
+}
+
+async fn indexed_records(root: &str, img_idx_tbl: Arc<Table>) -> Result<Vec<ImgIdx>, AppError> {
+    let filter = format!(
+        "root = {} and status = {}",
+        sql_str(root),
+        sql_str(idx::STATUS_INDEXED)
+    );
+    idx::get_records(img_idx_tbl, Some(filter)).await
+}
+
+/**
+ * 为 root 下已索引的图片写入附属文件
+ */
+pub async fn export(
+    root: &str,
+    img_idx_tbl: Arc<Table>,
+    img_meta_tbl: Arc<Table>,
+) -> Result<XmpReport, AppError> {
+    let records = indexed_records(root, img_idx_tbl).await?;
+    let ids = records.iter().map(|r| r.id.clone()).collect::<Vec<_>>();
+    let mut metas = meta::get_by_ids(img_meta_tbl, &ids).await?;
+
+    let items = records
+        .into_iter()
+        .map(|r| {
+            let subject = metas.remove(&r.id).map(|m| m.tags).unwrap_or_default();
+            (
+                PathBuf::from(r.path),
+                XmpData {
+                    description: r.desc,
+                    subject,
+                    suggested_name: r.suggested_name,
+                },
+            )
+        })
+        .collect::<Vec<_>>();
+
+    let report = tauri::async_runtime::spawn_blocking(move || {
+        let mut report = XmpReport::default();
+        for (path, data) in items.iter() {
+            match write(path, data) {
+                Ok(WriteOutcome::Written) => report.written += 1,
+                Ok(WriteOutcome::Unchanged) => report.unchanged += 1,
+                Ok(WriteOutcome::Foreign) => report.skipped += 1,
+                Err(e) => {
+                    log::warn!("write xmp error, {e}");
+                    report.failed += 1;
+                }
+            }
+        }
+        report
+    })
+    .await?;
+
+    log::info!("write xmp sidecars under {root}: {report:?}");
+    Ok(report)
+}
+
+/**
+ * 读取 root 下已有的附属文件，描述作为用户编辑的描述，关键字加入标签
+ * 已由用户编辑过描述的图片不覆盖描述
+ */
+pub async fn import(
+    root: &str,
+    img_idx_tbl: Arc<Table>,
+    img_meta_tbl: Arc<Table>,
+) -> Result<usize, AppError> {
+    let records = indexed_records(root, img_idx_tbl.clone()).await?;
+    let mut count = 0;
+
+    for r in records.into_iter() {
+        let Some(data) = read(Path::new(&r.path)) else {
+            continue;
+        };
+
+        if let Some(desc) = data.description.filter(|d| !d.trim().is_empty()) {
+            if r.desc_source == idx::DESC_SOURCE_SERVER && r.desc.as_ref() != Some(&desc) {
+                idx::update_desc(
+                    img_idx_tbl.clone(),
+                    &r.id,
+                    desc.trim(),
+                    idx::DESC_SOURCE_USER,
+                    None,
+                )
+                .await?;
+            }
+        }
+
+        if !data.subject.is_empty() {
+            let change = MetaChange::AddTags { tags: data.subject };
+            meta::apply(
+                img_idx_tbl.clone(),
+                img_meta_tbl.clone(),
+                std::slice::from_ref(&r.id),
+                &change,
+            )
+            .await?;
+        }
+        count += 1;
+    }
+
+    log::info!("import {count} xmp sidecars under {root}");
+    Ok(count)
+}
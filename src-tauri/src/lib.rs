@@ -0,0 +1,232 @@
+use std::{fs::File, str::FromStr, sync::Arc};
+
+use crate::server::init_server;
+use tauri::{
+    async_runtime::{JoinHandle, Mutex, RwLock},
+    Manager, Wry,
+};
+use tauri_plugin_store::{Store, StoreExt};
+mod auth_command;
+pub mod backup;
+pub mod db;
+pub mod error;
+pub mod image_command;
+mod local_api;
+pub mod maintenance;
+pub mod mcp;
+pub mod path_utils;
+pub mod server;
+pub mod settings_command;
+mod uuid_utils;
+use server::imgsearch_server::ImgseachServer;
+pub struct GlobalState {
+    // pub cache:
+    pub server: Arc<RwLock<Option<ImgseachServer>>>,
+    pub auth_store: Arc<Store<Wry>>,
+    pub imgdir_store: Arc<Store<Wry>>,
+    pub settings_store: Arc<Store<Wry>>,
+    pub img_idx_tbl: Arc<lancedb::Table>,
+    pub img_meta_tbl: Arc<lancedb::Table>,
+    pub img_root_tbl: Arc<lancedb::Table>,
+    // 启动时的完整性检查结果，回滚后更新
+    pub db_integrity: RwLock<db::IntegrityReport>,
+    // 持有数据目录的共享锁，防止命令行在运行期间写入
+    pub data_dir_lock: File,
+    // 本地 http 接口，未开启时为 None
+    pub local_api: Mutex<Option<JoinHandle<()>>>,
+}
+
+impl GlobalState {
+    pub async fn set_server(&self, server: ImgseachServer) {
+        let mut w = self.server.write().await;
+        *w = Some(server);
+    }
+}
+
+pub fn run() {
+    if dotenvy::dotenv().is_err() {
+        log::warn!("not .env fount");
+    }
+
+    let log_level = match std::env::var("RUST_LOG") {
+        Ok(v) => v,
+        Err(_) => "info".to_string(),
+    };
+
+    tauri::Builder::default()
+        .plugin(tauri_plugin_opener::init())
+        .plugin(
+            tauri_plugin_log::Builder::new()
+                .target(tauri_plugin_log::Target::new(
+                    tauri_plugin_log::TargetKind::Folder {
+                        path: path_utils::logs_dir().expect("Failed to get logs dir"),
+                        file_name: None,
+                    },
+                ))
+                .level(log::LevelFilter::from_str(log_level.as_str()).unwrap())
+                .max_file_size(50_000 /* bytes */)
+                .rotation_strategy(tauri_plugin_log::RotationStrategy::KeepAll)
+                .build(),
+        )
+        .plugin(tauri_plugin_http::init())
+        .invoke_handler(tauri::generate_handler![
+            image_command::search,
+            image_command::rename,
+            image_command::delete,
+            image_command::modify_content,
+            image_command::show_all,
+            image_command::after_add_imgdir,
+            image_command::after_remove_imgdir,
+            image_command::list_failed,
+            image_command::list_skipped,
+            image_command::list_roots,
+            image_command::relocate_root,
+            image_command::update_imgdir_options,
+            image_command::retry_failed,
+            image_command::export_index,
+            image_command::import_index,
+            image_command::export_results,
+            image_command::index_stats,
+            image_command::update_meta,
+            image_command::edit_description,
+            image_command::write_xmp,
+            image_command::import_xmp,
+            image_command::list_rename_proposals,
+            image_command::apply_rename_proposals,
+            image_command::reject_rename_proposals,
+            image_command::list_renames,
+            image_command::undo_rename,
+            image_command::undo_batch,
+            image_command::list_tags,
+            image_command::list_albums,
+            auth_command::after_apikey_set,
+            settings_command::get_index_settings,
+            settings_command::set_index_settings,
+            settings_command::get_backup_settings,
+            settings_command::set_backup_settings,
+            settings_command::get_maintenance_settings,
+            settings_command::set_maintenance_settings,
+            maintenance::optimize_db,
+            backup::list_db_backups,
+            backup::create_db_backup,
+            backup::restore_db_backup,
+            backup::db_integrity,
+            backup::rollback_db,
+            local_api::get_local_api_settings,
+            local_api::set_local_api_settings,
+            local_api::reset_local_api_token
+        ])
+        .plugin(tauri_plugin_fs::init())
+        .plugin(tauri_plugin_dialog::init())
+        .plugin(tauri_plugin_os::init())
+        .plugin(tauri_plugin_shell::init())
+        .plugin(tauri_plugin_store::Builder::new().build())
+        .setup(|app| {
+            let data_dir_lock = path_utils::lock_data_dir(false)?;
+            if let Err(e) = backup::apply_pending_restore() {
+                log::error!("restore backup error, {e}");
+            }
+            let unclean = path_utils::begin_session().unwrap_or_else(|e| {
+                log::error!("begin session error, {e}");
+                true
+            });
+            if unclean {
+                log::warn!("the app did not exit cleanly, run a full integrity check");
+            }
+            // 任一表无法打开时以只读的空表启动，提示用户恢复快照
+            let (img_idx_tbl, img_meta_tbl, img_root_tbl, db_integrity) =
+                tauri::async_runtime::block_on(async {
+                    let (img_idx_tbl, mut db_integrity) = match db::init_db(unclean).await {
+                        Ok(r) => r,
+                        Err(e) => {
+                            log::error!("init db error, start with an empty table, {e}");
+                            db::init_degraded_db(&e).await?
+                        }
+                    };
+                    let img_meta_tbl = match db::init_meta_db().await {
+                        Ok(t) => t,
+                        Err(e) => {
+                            log::error!("init meta db error, start with an empty table, {e}");
+                            db_integrity.degrade(&e);
+                            db::init_degraded_meta_db().await?
+                        }
+                    };
+                    let img_root_tbl = match db::init_root_db().await {
+                        Ok(t) => t,
+                        Err(e) => {
+                            log::error!("init root db error, start with an empty table, {e}");
+                            db_integrity.degrade(&e);
+                            db::init_degraded_root_db().await?
+                        }
+                    };
+                    Ok::<_, error::AppError>((
+                        img_idx_tbl,
+                        img_meta_tbl,
+                        img_root_tbl,
+                        db_integrity,
+                    ))
+                })?;
+            let db_ok = db_integrity.ok;
+            let img_root_tbl = Arc::new(img_root_tbl);
+            let img_meta_tbl = Arc::new(img_meta_tbl);
+
+            let img_idx_tbl = Arc::new(img_idx_tbl);
+            let auth_store = app.store("Auth.json")?;
+            let imgdir_store = app.store("ImgDirStore.json")?;
+            let settings_store = app.store("Settings.json")?;
+
+            let server = Arc::new(RwLock::new(init_server(auth_store.clone())?));
+
+            app.manage(GlobalState {
+                server: server.clone(),
+                img_idx_tbl: img_idx_tbl.clone(),
+                img_meta_tbl: img_meta_tbl.clone(),
+                img_root_tbl: img_root_tbl.clone(),
+                auth_store: auth_store.clone(),
+                imgdir_store: imgdir_store.clone(),
+                settings_store: settings_store.clone(),
+                db_integrity: RwLock::new(db_integrity),
+                data_dir_lock,
+                local_api: Mutex::new(None),
+            });
+
+            let handle = app.handle().clone();
+            tauri::async_runtime::spawn(async move {
+                let state = handle.state::<GlobalState>();
+                if let Err(e) = local_api::restart(&state).await {
+                    log::error!("start local api error, {e}");
+                }
+            });
+
+            // 完整性检查失败时 table 处于只读的旧版本，等待用户确认回滚，不做写入
+            if db_ok {
+                backup::schedule(settings_store.clone());
+                maintenance::schedule(img_idx_tbl.clone(), settings_store.clone());
+                image_command::volume::schedule(
+                    img_idx_tbl.clone(),
+                    img_root_tbl,
+                    imgdir_store.clone(),
+                    settings_store.clone(),
+                    server.clone(),
+                );
+                image_command::after_start_up(
+                    img_idx_tbl,
+                    img_meta_tbl,
+                    imgdir_store,
+                    settings_store,
+                    server,
+                );
+            }
+
+            Ok(())
+        })
+        .build(tauri::generate_context!())
+        .expect("error while building tauri application")
+        .run(|_, event| {
+            if let tauri::RunEvent::Exit = event {
+                if let Err(e) = path_utils::end_session() {
+                    log::error!("end session error, {e}");
+                }
+            }
+        });
+}
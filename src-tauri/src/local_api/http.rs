@@ -0,0 +1,190 @@
+/**
+ * 本地接口使用的最小 http/1.1 实现，只支持不带 body 的请求，每个连接处理一个请求
+ */
+use std::collections::HashMap;
+
+use serde::Serialize;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+};
+
+use crate::error::AppError;
+
+const MAX_HEADER_SIZE: usize = 16 * 1024;
+
+pub struct Request {
+    pub method: String,
+    pub path: String,
+    pub query: HashMap<String, String>,
+    // key 为小写
+    pub headers: HashMap<String, String>,
+}
+
+impl Request {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(name).map(|s| s.as_str())
+    }
+
+    pub fn query(&self, name: &str) -> Option<&str> {
+        self.query.get(name).map(|s| s.as_str())
+    }
+}
+
+pub struct Response {
+    pub status: u16,
+    pub content_type: &'static str,
+    pub body: Vec<u8>,
+}
+
+impl Response {
+    pub fn json<T: Serialize>(status: u16, body: &T) -> Self {
+        match serde_json::to_vec(body) {
+            Ok(body) => Self {
+                status,
+                content_type: "application/json",
+                body,
+            },
+            Err(e) => Self::error(&AppError::from(e)),
+        }
+    }
+
+    pub fn bytes(content_type: &'static str, body: Vec<u8>) -> Self {
+        Self {
+            status: 200,
+            content_type,
+            body,
+        }
+    }
+
+    pub fn empty(status: u16) -> Self {
+        Self {
+            status,
+            content_type: "text/plain",
+            body: vec![],
+        }
+    }
+
+    pub fn error(e: &AppError) -> Self {
+        let status = match e {
+            AppError::Config(_) => 400,
+            AppError::Auth(_) => 401,
+            AppError::RightsLimit(_) => 402,
+            AppError::RateLimited { .. } => 429,
+            AppError::ServerNotReady | AppError::Network(_) => 503,
+            AppError::Http { .. } => 502,
+            _ => 500,
+        };
+        Self::json(status, e)
+    }
+
+    pub fn not_found(msg: &str) -> Self {
+        Self::json(
+            404,
+            &serde_json::json!({ "code": "not_found", "message": msg, "details": {} }),
+        )
+    }
+
+    pub fn forbidden(msg: &str) -> Self {
+        Self::json(
+            403,
+            &serde_json::json!({ "code": "forbidden", "message": msg, "details": {} }),
+        )
+    }
+
+    pub fn unauthorized() -> Self {
+        Self::json(
+            401,
+            &serde_json::json!({ "code": "unauthorized", "message": "invalid token", "details": {} }),
+        )
+    }
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        204 => "No Content",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        402 => "Payment Required",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        429 => "Too Many Requests",
+        431 => "Request Header Fields Too Large",
+        502 => "Bad Gateway",
+        503 => "Service Unavailable",
+        _ => "Internal Server Error",
+    }
+}
+
+/**
+ * 读取请求头，连接关闭时返回 None
+ */
+pub async fn read_request(stream: &mut TcpStream) -> Result<Option<Request>, Response> {
+    let mut buf = Vec::with_capacity(1024);
+    let mut chunk = [0u8; 1024];
+
+    let end = loop {
+        let n = stream
+            .read(&mut chunk)
+            .await
+            .map_err(|e| Response::error(&AppError::from(e)))?;
+        if n == 0 {
+            return Ok(None);
+        }
+        buf.extend_from_slice(&chunk[..n]);
+
+        if let Some(i) = buf.windows(4).position(|w| w == b"\r\n\r\n") {
+            break i;
+        }
+        if buf.len() > MAX_HEADER_SIZE {
+            return Err(Response::empty(431));
+        }
+    };
+
+    let head = String::from_utf8_lossy(&buf[..end]);
+    let mut lines = head.split("\r\n");
+
+    let mut request_line = lines.next().unwrap_or_default().split(' ');
+    let (Some(method), Some(target)) = (request_line.next(), request_line.next()) else {
+        return Err(Response::empty(400));
+    };
+
+    let headers = lines
+        .filter_map(|l| l.split_once(':'))
+        .map(|(k, v)| (k.trim().to_lowercase(), v.trim().to_string()))
+        .collect::<HashMap<_, _>>();
+
+    // 只用于解析 path 与 query
+    let url = tauri::Url::parse(&format!("http://localhost{target}"))
+        .map_err(|_| Response::empty(400))?;
+
+    Ok(Some(Request {
+        method: method.to_string(),
+        path: url.path().to_string(),
+        query: url.query_pairs().into_owned().collect(),
+        headers,
+    }))
+}
+
+pub async fn write_response(stream: &mut TcpStream, resp: Response) -> Result<(), AppError> {
+    let head = format!(
+        "HTTP/1.1 {} {}\r\n\
+         Content-Type: {}\r\n\
+         Content-Length: {}\r\n\
+         Access-Control-Allow-Origin: *\r\n\
+         Access-Control-Allow-Headers: Authorization\r\n\
+         Access-Control-Allow-Methods: GET, OPTIONS\r\n\
+         Connection: close\r\n\r\n",
+        resp.status,
+        reason(resp.status),
+        resp.content_type,
+        resp.body.len()
+    );
+
+    stream.write_all(head.as_bytes()).await?;
+    stream.write_all(&resp.body).await?;
+    stream.flush().await?;
+    Ok(())
+}
@@ -0,0 +1,293 @@
+mod http;
+
+/**
+ * 本地 http 接口，供同一台机器上的其他工具查询索引
+ * 只绑定 127.0.0.1，所有请求需携带 Authorization: Bearer <token>
+ */
+use std::{net::Ipv4Addr, path::Path, sync::Arc};
+
+use lancedb::Table;
+use tauri::{
+    async_runtime::{JoinHandle, RwLock},
+    State, Wry,
+};
+use tauri_plugin_store::Store;
+use tokio::net::{TcpListener, TcpStream};
+
+use crate::{
+    error::AppError,
+    image_command::{
+        api,
+        idx::{self, ImgSearchResult},
+        load_imgdirs, SearchModel,
+    },
+    server::imgsearch_server::ImgseachServer,
+    settings_command::{local_api_settings, save_local_api_settings, LocalApiSettings},
+    uuid_utils, GlobalState,
+};
+use http::{Request, Response};
+
+const DEFAULT_TOP: usize = 20;
+// 单次请求最多返回的结果数
+const MAX_TOP: usize = 100;
+
+struct Context {
+    port: u16,
+    token: String,
+    server: Arc<RwLock<Option<ImgseachServer>>>,
+    imgdir_store: Arc<Store<Wry>>,
+    img_idx_tbl: Arc<Table>,
+}
+
+pub async fn start(
+    settings: &LocalApiSettings,
+    server: Arc<RwLock<Option<ImgseachServer>>>,
+    imgdir_store: Arc<Store<Wry>>,
+    img_idx_tbl: Arc<Table>,
+) -> Result<JoinHandle<()>, AppError> {
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, settings.port)).await?;
+    log::info!("local api listening on 127.0.0.1:{}", settings.port);
+
+    let ctx = Arc::new(Context {
+        port: settings.port,
+        token: settings.token.clone(),
+        server,
+        imgdir_store,
+        img_idx_tbl,
+    });
+
+    Ok(tauri::async_runtime::spawn(async move {
+        loop {
+            match listener.accept().await {
+                Ok((stream, _)) => {
+                    let ctx = ctx.clone();
+                    tauri::async_runtime::spawn(async move {
+                        if let Err(e) = serve(stream, ctx).await {
+                            log::debug!("local api connection error: {e}");
+                        }
+                    });
+                }
+                Err(e) => log::error!("local api accept error: {e}"),
+            }
+        }
+    }))
+}
+
+/**
+ * 按设置启动或停止本地接口
+ */
+pub async fn restart(state: &GlobalState) -> Result<(), AppError> {
+    let mut handle = state.local_api.lock().await;
+    if let Some(h) = handle.take() {
+        h.abort();
+    }
+
+    let settings = local_api_settings(&state.settings_store)?;
+    if settings.enabled {
+        *handle = Some(
+            start(
+                &settings,
+                state.server.clone(),
+                state.imgdir_store.clone(),
+                state.img_idx_tbl.clone(),
+            )
+            .await?,
+        );
+    }
+
+    Ok(())
+}
+
+async fn serve(mut stream: TcpStream, ctx: Arc<Context>) -> Result<(), AppError> {
+    let resp = match http::read_request(&mut stream).await {
+        Ok(Some(req)) => handle(req, &ctx).await,
+        Ok(None) => return Ok(()),
+        Err(resp) => resp,
+    };
+
+    http::write_response(&mut stream, resp).await
+}
+
+async fn handle(req: Request, ctx: &Context) -> Response {
+    // 防止 dns rebinding，只接受以本机地址访问
+    let host_ok = req.header("host").is_some_and(|h| {
+        h == format!("127.0.0.1:{}", ctx.port) || h == format!("localhost:{}", ctx.port)
+    });
+    if !host_ok {
+        return Response::empty(403);
+    }
+
+    if req.method == "OPTIONS" {
+        return Response::empty(204);
+    }
+
+    let authorized = req
+        .header("authorization")
+        .and_then(|h| h.strip_prefix("Bearer "))
+        .is_some_and(|t| token_eq(t.trim(), &ctx.token));
+    if !authorized {
+        return Response::unauthorized();
+    }
+
+    if req.method != "GET" {
+        return Response::empty(405);
+    }
+
+    match route(&req, ctx).await {
+        Ok(resp) => resp,
+        Err(e) => Response::error(&e),
+    }
+}
+
+/**
+ * 与 token 长度相关但与内容无关的比较
+ */
+fn token_eq(a: &str, b: &str) -> bool {
+    a.len() == b.len()
+        && a.bytes()
+            .zip(b.bytes())
+            .fold(0u8, |acc, (x, y)| acc | (x ^ y))
+            == 0
+}
+
+async fn route(req: &Request, ctx: &Context) -> Result<Response, AppError> {
+    let segs = req.path.trim_matches('/').split('/').collect::<Vec<_>>();
+
+    match segs.as_slice() {
+        ["api", "search"] => {
+            let keyword = req
+                .query("q")
+                .ok_or_else(|| AppError::Config("missing query: q".to_string()))?;
+            let model = SearchModel {
+                keyword: keyword.to_string(),
+                top: top(req)?,
+                nprobes: optional(req, "nprobes")?,
+                refine_factor: optional(req, "refine_factor")?,
+            };
+
+            let server = ctx.server.read().await;
+            let server = server.as_ref().ok_or(AppError::ServerNotReady)?;
+            let r = api::search(&model, server, ctx.img_idx_tbl.clone()).await?;
+            Ok(Response::json(200, &r))
+        }
+        ["api", "similar"] => {
+            let path = match (req.query("id"), req.query("path")) {
+                (Some(id), _) => match get_by_id(ctx, id).await? {
+                    Some(r) => r.path,
+                    None => return Ok(Response::not_found("image not found")),
+                },
+                (None, Some(path)) if in_imgdirs(ctx, path) => path.to_string(),
+                (None, Some(_)) => {
+                    return Ok(Response::forbidden("path is not under an image directory"))
+                }
+                (None, None) => {
+                    return Err(AppError::Config("missing query: id or path".to_string()))
+                }
+            };
+
+            let server = ctx.server.read().await;
+            let server = server.as_ref().ok_or(AppError::ServerNotReady)?;
+            let r =
+                api::similar(Path::new(&path), top(req)?, server, ctx.img_idx_tbl.clone()).await?;
+            Ok(Response::json(200, &r))
+        }
+        ["api", "images", id] => match get_by_id(ctx, id).await? {
+            Some(r) => Ok(Response::json(200, &r)),
+            None => Ok(Response::not_found("image not found")),
+        },
+        ["api", "images", id, "thumbnail"] => {
+            let Some(r) = get_by_id(ctx, id).await? else {
+                return Ok(Response::not_found("image not found"));
+            };
+            if r.thumbnail.is_empty() {
+                return Ok(Response::not_found("thumbnail not found"));
+            }
+
+            let p = Path::new(&r.thumbnail);
+            let bs = tokio::fs::read(p).await.map_err(|e| AppError::io(e, p))?;
+            Ok(Response::bytes(content_type(p), bs))
+        }
+        _ => Ok(Response::not_found("no such endpoint")),
+    }
+}
+
+fn top(req: &Request) -> Result<usize, AppError> {
+    match req.query("top") {
+        Some(top) => top
+            .parse::<usize>()
+            .map(|t| t.min(MAX_TOP))
+            .map_err(|_| AppError::Config(format!("invalid query: top={top}"))),
+        None => Ok(DEFAULT_TOP),
+    }
+}
+
+/**
+ * path 只接受已添加目录下的文件，防止持有 token 的调用方让应用读取任意本地文件
+ */
+fn in_imgdirs(ctx: &Context, path: &str) -> bool {
+    let Ok(path) = std::fs::canonicalize(path) else {
+        return false;
+    };
+
+    load_imgdirs(ctx.imgdir_store.entries())
+        .keys()
+        .filter_map(|root| std::fs::canonicalize(root).ok())
+        .any(|root| path.starts_with(root))
+}
+
+fn optional<T: std::str::FromStr>(req: &Request, name: &str) -> Result<Option<T>, AppError> {
+    req.query(name)
+        .map(|v| {
+            v.parse()
+                .map_err(|_| AppError::Config(format!("invalid query: {name}={v}")))
+        })
+        .transpose()
+}
+
+async fn get_by_id(ctx: &Context, id: &str) -> Result<Option<ImgSearchResult>, AppError> {
+    let r = idx::get_by_ids(ctx.img_idx_tbl.clone(), &[id.to_string()]).await?;
+    Ok(r.into_iter().next())
+}
+
+fn content_type(p: &Path) -> &'static str {
+    match p.extension().and_then(|e| e.to_str()) {
+        Some("png") => "image/png",
+        Some("webp") => "image/webp",
+        _ => "image/jpeg",
+    }
+}
+
+#[tauri::command]
+pub async fn get_local_api_settings(
+    state: State<'_, GlobalState>,
+) -> Result<LocalApiSettings, AppError> {
+    local_api_settings(&state.settings_store)
+}
+
+#[tauri::command]
+pub async fn set_local_api_settings(
+    enabled: bool,
+    port: u16,
+    state: State<'_, GlobalState>,
+) -> Result<(), AppError> {
+    let mut settings = local_api_settings(&state.settings_store)?;
+    settings.enabled = enabled;
+    settings.port = port;
+    save_local_api_settings(&state.settings_store, &settings)?;
+
+    log::info!("set local api settings, enabled = {enabled}, port = {port}");
+    restart(&state).await
+}
+
+/**
+ * 重新生成 token，旧 token 立即失效
+ */
+#[tauri::command]
+pub async fn reset_local_api_token(state: State<'_, GlobalState>) -> Result<String, AppError> {
+    let mut settings = local_api_settings(&state.settings_store)?;
+    settings.token = uuid_utils::get();
+    save_local_api_settings(&state.settings_store, &settings)?;
+
+    restart(&state).await?;
+    Ok(settings.token)
+}
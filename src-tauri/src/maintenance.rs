@@ -0,0 +1,138 @@
+/**
+ * 表的整理：合并小文件、清理旧版本、将新数据合入索引
+ * 每次 merge_insert / delete 都会产生新的分片和版本，不整理时目录和查询耗时会一直增长
+ */
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use lancedb::{
+    table::{CompactionOptions, OptimizeAction, OptimizeOptions},
+    Table,
+};
+use serde::Serialize;
+use tauri::{State, Wry};
+use tauri_plugin_store::Store;
+use tokio::sync::Mutex;
+
+use crate::{
+    error::AppError,
+    image_command::vector_index,
+    settings_command::{maintenance_settings, MaintenanceSettings},
+    GlobalState,
+};
+
+// 整理与备份会删除、拷贝同一批文件，互斥执行
+pub static DB_MAINTENANCE_LOCK: Mutex<()> = Mutex::const_new(());
+// 调度器检查表是否空闲的间隔
+const CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+#[derive(Serialize, Debug, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct OptimizeReport {
+    pub fragments_removed: usize,
+    pub fragments_added: usize,
+    pub versions_removed: u64,
+    pub bytes_removed: u64,
+    pub version: u64,
+    pub elapsed_ms: u128,
+}
+
+pub async fn optimize(
+    table: Arc<Table>,
+    settings: &MaintenanceSettings,
+) -> Result<OptimizeReport, AppError> {
+    let _guard = DB_MAINTENANCE_LOCK.lock().await;
+    let start = Instant::now();
+    let mut report = OptimizeReport::default();
+
+    let r = table
+        .optimize(OptimizeAction::Compact {
+            options: CompactionOptions::default(),
+            remap_options: None,
+        })
+        .await?;
+    if let Some(c) = r.compaction {
+        report.fragments_removed = c.fragments_removed;
+        report.fragments_added = c.fragments_added;
+    }
+
+    // 保留最近的版本，供完整性检查失败时回滚
+    let r = table
+        .optimize(OptimizeAction::Prune {
+            older_than: Some(chrono::Duration::days(settings.keep_versions_days as i64)),
+            delete_unverified: Some(false),
+            error_if_tagged_old_versions: None,
+        })
+        .await?;
+    if let Some(p) = r.prune {
+        report.versions_removed = p.old_versions;
+        report.bytes_removed = p.bytes_removed;
+    }
+
+    // 按记录数建立或重新训练向量索引，已有的索引合入新数据
+    vector_index::check_or_build(table.clone()).await?;
+    table
+        .optimize(OptimizeAction::Index(OptimizeOptions::default()))
+        .await?;
+
+    report.version = table.version().await?;
+    report.elapsed_ms = start.elapsed().as_millis();
+    log::info!("optimize img_idx finished, {report:?}");
+
+    Ok(report)
+}
+
+/**
+ * 表在一段时间内没有新版本时视为空闲，自动整理
+ */
+pub fn schedule(table: Arc<Table>, settings_store: Arc<Store<Wry>>) {
+    tauri::async_runtime::spawn(async move {
+        let mut last_version = 0;
+        let mut changed_at = Instant::now();
+        let mut optimized_version = None;
+
+        loop {
+            tokio::time::sleep(CHECK_INTERVAL).await;
+
+            let settings = maintenance_settings(&settings_store);
+            if !settings.enabled {
+                continue;
+            }
+
+            match table.version().await {
+                Ok(v) if v != last_version => {
+                    last_version = v;
+                    changed_at = Instant::now();
+                    continue;
+                }
+                Ok(_) => {}
+                Err(e) => {
+                    log::error!("get img_idx version error, {e}");
+                    continue;
+                }
+            }
+
+            if optimized_version == Some(last_version)
+                || changed_at.elapsed() < Duration::from_secs(settings.idle_minutes * 60)
+            {
+                continue;
+            }
+
+            match optimize(table.clone(), &settings).await {
+                Ok(r) => {
+                    last_version = r.version;
+                    optimized_version = Some(r.version);
+                }
+                Err(e) => log::error!("scheduled optimize error, {e}"),
+            }
+        }
+    });
+}
+
+#[tauri::command]
+pub async fn optimize_db(state: State<'_, GlobalState>) -> Result<OptimizeReport, AppError> {
+    let settings = maintenance_settings(&state.settings_store);
+    optimize(state.img_idx_tbl.clone(), &settings).await
+}
@@ -0,0 +1,235 @@
+/**
+ * Model Context Protocol 服务，stdio 传输，每行一个 JSON-RPC 消息
+ * 通过 `imgsearch-cli mcp` 启动，stdout 只用于协议输出
+ */
+use std::{path::Path, sync::Arc};
+
+use lancedb::Table;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+
+use crate::{
+    error::AppError,
+    image_command::{api, idx, ImgDirs, SearchModel},
+    server::imgsearch_server::ImgseachServer,
+};
+
+static PROTOCOL_VERSION: &str = "2024-11-05";
+const DEFAULT_TOP: usize = 10;
+
+pub struct McpContext {
+    pub server: Option<ImgseachServer>,
+    pub imgdirs: ImgDirs,
+    pub img_idx_tbl: Arc<Table>,
+}
+
+#[derive(Deserialize)]
+struct RpcRequest {
+    id: Option<Value>,
+    method: String,
+    #[serde(default)]
+    params: Value,
+}
+
+pub async fn serve_stdio(ctx: McpContext) -> Result<(), AppError> {
+    let mut lines = BufReader::new(tokio::io::stdin()).lines();
+    let mut stdout = tokio::io::stdout();
+
+    while let Some(line) = lines.next_line().await? {
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let resp = match serde_json::from_str::<RpcRequest>(&line) {
+            Ok(req) => {
+                // 没有 id 的是通知，不需要回复
+                let Some(id) = req.id.clone() else {
+                    log::debug!("mcp notification: {}", req.method);
+                    continue;
+                };
+
+                match dispatch(&req, &ctx).await {
+                    Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+                    Err((code, message)) => json!({
+                        "jsonrpc": "2.0",
+                        "id": id,
+                        "error": { "code": code, "message": message },
+                    }),
+                }
+            }
+            Err(e) => json!({
+                "jsonrpc": "2.0",
+                "id": Value::Null,
+                "error": { "code": -32700, "message": format!("parse error: {e}") },
+            }),
+        };
+
+        let mut bs = serde_json::to_vec(&resp)?;
+        bs.push(b'\n');
+        stdout.write_all(&bs).await?;
+        stdout.flush().await?;
+    }
+
+    Ok(())
+}
+
+async fn dispatch(req: &RpcRequest, ctx: &McpContext) -> Result<Value, (i64, String)> {
+    match req.method.as_str() {
+        "initialize" => Ok(json!({
+            "protocolVersion": PROTOCOL_VERSION,
+            "capabilities": { "tools": {} },
+            "serverInfo": {
+                "name": "imgsearch",
+                "version": env!("CARGO_PKG_VERSION"),
+            },
+        })),
+        "ping" => Ok(json!({})),
+        "tools/list" => Ok(json!({ "tools": tools() })),
+        "tools/call" => {
+            let name = req.params["name"]
+                .as_str()
+                .ok_or((-32602, "missing tool name".to_string()))?;
+            let args = &req.params["arguments"];
+
+            // 工具执行失败作为结果返回，便于模型自行处理
+            let r = match call_tool(name, args, ctx).await {
+                Ok(v) => json!({
+                    "content": [{ "type": "text", "text": v.to_string() }],
+                    "isError": false,
+                }),
+                Err(e) => json!({
+                    "content": [{ "type": "text", "text": serde_json::to_string(&e).unwrap_or_default() }],
+                    "isError": true,
+                }),
+            };
+            Ok(r)
+        }
+        m => Err((-32601, format!("method not found: {m}"))),
+    }
+}
+
+fn tools() -> Value {
+    json!([
+        {
+            "name": "search_images",
+            "description": "Search the local image library by a natural language description.",
+            "inputSchema": {
+                "type": "object",
+                "properties": {
+                    "query": { "type": "string", "description": "what the image shows" },
+                    "top": { "type": "integer", "description": "max results, default 10" },
+                },
+                "required": ["query"],
+            },
+        },
+        {
+            "name": "find_similar",
+            "description": "Find images similar to an image in the library (by id) or a file on disk (by path).",
+            "inputSchema": {
+                "type": "object",
+                "properties": {
+                    "id": { "type": "string" },
+                    "path": { "type": "string", "description": "absolute file path" },
+                    "top": { "type": "integer", "description": "max results, default 10" },
+                },
+            },
+        },
+        {
+            "name": "describe_image",
+            "description": "Return the stored description of an indexed image, by id or absolute path.",
+            "inputSchema": {
+                "type": "object",
+                "properties": {
+                    "id": { "type": "string" },
+                    "path": { "type": "string" },
+                },
+            },
+        },
+        {
+            "name": "list_roots",
+            "description": "List the image directories that are indexed.",
+            "inputSchema": { "type": "object", "properties": {} },
+        },
+    ])
+}
+
+async fn call_tool(name: &str, args: &Value, ctx: &McpContext) -> Result<Value, AppError> {
+    let top = args["top"]
+        .as_u64()
+        .map(|t| t as usize)
+        .unwrap_or(DEFAULT_TOP);
+
+    match name {
+        "search_images" => {
+            let query = str_arg(args, "query")?
+                .ok_or_else(|| AppError::Config("missing argument: query".to_string()))?;
+            let server = ctx.server.as_ref().ok_or(AppError::ServerNotReady)?;
+
+            let model = SearchModel {
+                keyword: query.to_string(),
+                top,
+                ..Default::default()
+            };
+            let r = api::search(&model, server, ctx.img_idx_tbl.clone()).await?;
+            Ok(serde_json::to_value(r)?)
+        }
+        "find_similar" => {
+            let path = find_path(args, ctx).await?;
+            let server = ctx.server.as_ref().ok_or(AppError::ServerNotReady)?;
+
+            let r = api::similar(Path::new(&path), top, server, ctx.img_idx_tbl.clone()).await?;
+            Ok(serde_json::to_value(r)?)
+        }
+        "describe_image" => {
+            let r = find_image(args, ctx).await?;
+            Ok(json!({
+                "id": r.id,
+                "path": r.path,
+                "status": r.status,
+                "desc": r.desc,
+            }))
+        }
+        "list_roots" => {
+            let roots = ctx
+                .imgdirs
+                .values()
+                .map(|d| json!({ "name": d.name, "root": d.root }))
+                .collect::<Vec<_>>();
+            Ok(json!(roots))
+        }
+        _ => Err(AppError::Config(format!("unknown tool: {name}"))),
+    }
+}
+
+fn str_arg<'a>(args: &'a Value, name: &str) -> Result<Option<&'a str>, AppError> {
+    match &args[name] {
+        Value::Null => Ok(None),
+        Value::String(s) => Ok(Some(s.as_str())),
+        _ => Err(AppError::Config(format!(
+            "argument {name} must be a string"
+        ))),
+    }
+}
+
+async fn find_image(args: &Value, ctx: &McpContext) -> Result<idx::ImgSearchResult, AppError> {
+    let r = match (str_arg(args, "id")?, str_arg(args, "path")?) {
+        (Some(id), _) => idx::get_by_ids(ctx.img_idx_tbl.clone(), &[id.to_string()]).await?,
+        (None, Some(path)) => idx::get_by_path(ctx.img_idx_tbl.clone(), path).await?,
+        (None, None) => return Err(AppError::Config("missing argument: id or path".to_string())),
+    };
+
+    r.into_iter()
+        .next()
+        .ok_or_else(|| AppError::Config("image not found".to_string()))
+}
+
+/**
+ * id 需在索引中，path 可以是任意图片文件
+ */
+async fn find_path(args: &Value, ctx: &McpContext) -> Result<String, AppError> {
+    match str_arg(args, "path")? {
+        Some(path) => Ok(path.to_string()),
+        None => Ok(find_image(args, ctx).await?.path),
+    }
+}
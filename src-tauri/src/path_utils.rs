@@ -1,30 +1,44 @@
 use std::{
+    fs::{File, OpenOptions, TryLockError},
     path::{Path, PathBuf},
     sync::OnceLock,
 };
 
+use ignore::{
+    gitignore::{Gitignore, GitignoreBuilder},
+    overrides::{Override, OverrideBuilder},
+    WalkBuilder,
+};
+use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha256};
-use walkdir::WalkDir;
 
 use crate::error::AppError;
 
 static LOG_DIR: &str = "logs";
 static THUMBNAIL_DIR: &str = "thumbnails";
 static LANCEDB_DIR: &str = "db";
+static BACKUPS_DIR: &str = "backups";
+static LOCK_FILE: &str = "imgsearch.lock";
+static RENAME_JOURNAL_FILE: &str = "rename_journal.jsonl";
+// GUI 运行期间存在，正常退出时删除
+static SESSION_FILE: &str = "session";
+static IGNORE_FILE: &str = ".imgsearchignore";
 
 fn data_dir() -> Result<PathBuf, AppError> {
-    let p = dirs::home_dir().unwrap().join(".imgsearch");
+    let p = dirs::home_dir()
+        .ok_or_else(|| AppError::Config("home dir not found".to_string()))?
+        .join(".imgsearch");
     if !p.exists() {
-        std::fs::create_dir(&p)?;
+        std::fs::create_dir_all(&p)?;
     }
     Ok(p)
 }
 
-fn other_dir(name: &str) -> Result<PathBuf, AppError> {
+fn other_dir<P: AsRef<Path>>(name: P) -> Result<PathBuf, AppError> {
     let p = data_dir()?.join(name);
     if !p.exists() {
-        println!("create dir: {}", p.display());
-        std::fs::create_dir(&p)?;
+        log::info!("create dir: {}", p.display());
+        std::fs::create_dir_all(&p)?;
     }
     Ok(p)
 }
@@ -34,8 +48,7 @@ pub fn logs_dir() -> Result<PathBuf, AppError> {
 }
 
 pub fn thumbnail_dir(dir: &str) -> Result<PathBuf, AppError> {
-    let p = Path::new(THUMBNAIL_DIR).join(dir);
-    other_dir(p.as_path().to_str().unwrap())
+    other_dir(Path::new(THUMBNAIL_DIR).join(dir))
 }
 
 pub fn remove_thumbnail_dir(dir: &str) -> Result<(), AppError> {
@@ -48,22 +61,109 @@ pub fn remove_thumbnail_dir(dir: &str) -> Result<(), AppError> {
     Ok(())
 }
 
+/**
+ * 移动缩略图目录，目标目录已存在时逐个移动其中的文件
+ * 返回 (原目录, 新目录)，原目录不存在时返回 None
+ */
+pub fn move_thumbnail_dir(from: &str, to: &str) -> Result<Option<(PathBuf, PathBuf)>, AppError> {
+    let base = data_dir()?.join(THUMBNAIL_DIR);
+    let (from, to) = (base.join(from), base.join(to));
+    if from == to || !from.is_dir() {
+        return Ok(None);
+    }
+    log::debug!("move_thumbnail_dir: {} -> {}", from.display(), to.display());
+
+    if to.exists() {
+        for entry in std::fs::read_dir(&from)? {
+            let entry = entry?;
+            std::fs::rename(entry.path(), to.join(entry.file_name()))?;
+        }
+        std::fs::remove_dir(&from)?;
+    } else {
+        std::fs::rename(&from, &to)?;
+    }
+    Ok(Some((from, to)))
+}
+
 pub fn lancedb_dir() -> Result<PathBuf, AppError> {
     other_dir(LANCEDB_DIR)
 }
 
+pub fn backups_dir() -> Result<PathBuf, AppError> {
+    other_dir(BACKUPS_DIR)
+}
+
+pub fn rename_journal_file() -> Result<PathBuf, AppError> {
+    Ok(data_dir()?.join(RENAME_JOURNAL_FILE))
+}
+
+/**
+ * GUI 启动时调用，返回上次是否未正常退出
+ */
+pub fn begin_session() -> Result<bool, AppError> {
+    let p = data_dir()?.join(SESSION_FILE);
+    let unclean = p.exists();
+    std::fs::write(&p, std::process::id().to_string()).map_err(|e| AppError::io(e, &p))?;
+    Ok(unclean)
+}
+
+/**
+ * GUI 正常退出时调用
+ */
+pub fn end_session() -> Result<(), AppError> {
+    let p = data_dir()?.join(SESSION_FILE);
+    if p.exists() {
+        std::fs::remove_file(&p).map_err(|e| AppError::io(e, &p))?;
+    }
+    Ok(())
+}
+
+/**
+ * 锁定数据目录，GUI 与命令行的只读操作持有共享锁，命令行写入时需要独占锁
+ * 返回的 File 被 drop 时释放锁
+ */
+pub fn lock_data_dir(exclusive: bool) -> Result<File, AppError> {
+    let p = data_dir()?.join(LOCK_FILE);
+    let f = OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(&p)
+        .map_err(|e| AppError::io(e, &p))?;
+
+    let r = if exclusive {
+        f.try_lock()
+    } else {
+        f.try_lock_shared()
+    };
+
+    match r {
+        Ok(()) => Ok(f),
+        Err(TryLockError::WouldBlock) => Err(AppError::Locked(format!(
+            "{} is in use by another imgsearch process",
+            p.display()
+        ))),
+        Err(TryLockError::Error(e)) => Err(AppError::io(e, &p)),
+    }
+}
+
 /**
  * 重命名文件
- * target_name: 新的文件名, 不包含后缀
+ * target_name: 第 n 次尝试的文件名, 不包含后缀, 重名时 n 递增
  */
-pub fn rename(current_path: &Path, target_name: &str) -> Result<PathBuf, AppError> {
+pub fn rename<F>(current_path: &Path, target_name: F) -> Result<PathBuf, AppError>
+where
+    F: Fn(usize) -> String,
+{
     let ext = if let Some(ext) = current_path.extension() {
-        format!(".{}", ext.to_str().unwrap())
+        format!(".{}", ext.to_string_lossy())
     } else {
         "".to_string()
     };
 
-    let parent = current_path.parent().unwrap();
+    let parent = current_path
+        .parent()
+        .ok_or_else(|| AppError::Internal(format!("no parent dir: {}", current_path.display())))?;
 
     let new_path = gen_new_valid_path(parent, target_name, &ext);
 
@@ -73,16 +173,19 @@ pub fn rename(current_path: &Path, target_name: &str) -> Result<PathBuf, AppErro
         new_path.display()
     );
 
-    std::fs::rename(current_path, &new_path)?;
+    std::fs::rename(current_path, &new_path).map_err(|e| AppError::io(e, current_path))?;
 
     Ok(new_path)
 }
 
-fn gen_new_valid_path(parent: &Path, target_name: &str, ext: &str) -> PathBuf {
-    let mut new_path = parent.join(format!("{target_name}{ext}"));
+fn gen_new_valid_path<F>(parent: &Path, target_name: F, ext: &str) -> PathBuf
+where
+    F: Fn(usize) -> String,
+{
+    let mut new_path = parent.join(format!("{}{ext}", target_name(0)));
     let mut i = 1;
     while new_path.exists() {
-        new_path = parent.join(format!("{target_name}_{i}{ext}"));
+        new_path = parent.join(format!("{}{ext}", target_name(i)));
 
         i += 1;
     }
@@ -113,16 +216,125 @@ static IMAGE_VALID_SUBFIX: OnceLock<Vec<&str>> = OnceLock::new();
 fn get_valid_subfix() -> &'static Vec<&'static str> {
     IMAGE_VALID_SUBFIX.get_or_init(|| vec!["jpg", "jpeg", "png", "webp"])
 }
-pub fn find_all_images(path: &Path) -> Result<Vec<PathBuf>, AppError> {
+
+/**
+ * imgdir 的扫描规则，目录中的 .imgsearchignore 按 gitignore 语法生效
+ * include / exclude 为相对 root 的 glob，include 为空时包含全部
+ */
+#[derive(Deserialize, Serialize, Clone, Debug, Default)]
+#[serde(rename_all = "camelCase", default)]
+pub struct ScanOptions {
+    pub include: Vec<String>,
+    pub exclude: Vec<String>,
+    // 跳过以 '.' 开头的文件与目录，默认关闭，与之前索引的范围一致
+    pub skip_hidden: bool,
+    pub follow_symlinks: bool,
+    // root 本身为 0
+    pub max_depth: Option<usize>,
+}
+
+impl ScanOptions {
+    fn overrides(&self, root: &Path) -> Result<Override, AppError> {
+        let mut b = OverrideBuilder::new(root);
+        for g in self.include.iter() {
+            b.add(g)?;
+        }
+        for g in self.exclude.iter() {
+            b.add(&format!("!{g}"))?;
+        }
+        Ok(b.build()?)
+    }
+
+    /**
+     * 检查 include / exclude 中的 glob 是否合法
+     */
+    pub fn check(&self, root: &Path) -> Result<(), AppError> {
+        self.overrides(root).map(|_| ())
+    }
+
+    /**
+     * 单个路径是否被规则排除，用于文件监听触发的增量索引
+     */
+    pub fn is_ignored(&self, root: &Path, path: &Path) -> Result<bool, AppError> {
+        let Ok(rel) = path.strip_prefix(root) else {
+            return Ok(true);
+        };
+
+        let depth = rel.components().count();
+        if self.max_depth.is_some_and(|d| depth > d) {
+            return Ok(true);
+        }
+        if self.skip_hidden
+            && rel
+                .components()
+                .any(|c| c.as_os_str().to_string_lossy().starts_with('.'))
+        {
+            return Ok(true);
+        }
+        if !self.follow_symlinks && path.is_symlink() {
+            return Ok(true);
+        }
+
+        let is_dir = path.is_dir();
+        if self.overrides(root)?.matched(path, is_dir).is_ignore() {
+            return Ok(true);
+        }
+
+        // 由深到浅，最近的 .imgsearchignore 优先
+        for dir in path.ancestors().skip(1).take_while(|d| d.starts_with(root)) {
+            let m = ignore_file(dir)?.matched_path_or_any_parents(path, is_dir);
+            if m.is_ignore() {
+                return Ok(true);
+            }
+            if m.is_whitelist() {
+                return Ok(false);
+            }
+        }
+
+        Ok(false)
+    }
+}
+
+fn ignore_file(dir: &Path) -> Result<Gitignore, AppError> {
+    let p = dir.join(IGNORE_FILE);
+    if !p.is_file() {
+        return Ok(Gitignore::empty());
+    }
+
+    let mut b = GitignoreBuilder::new(dir);
+    if let Some(e) = b.add(&p) {
+        log::warn!("invalid ignore file {}, {e}", p.display());
+    }
+    Ok(b.build()?)
+}
+
+pub fn find_all_images(path: &Path, opts: &ScanOptions) -> Result<Vec<PathBuf>, AppError> {
     let subfix = get_valid_subfix();
 
     let mut images = vec![];
 
-    for entry in WalkDir::new(path) {
-        let entry = entry?;
+    let walker = WalkBuilder::new(path)
+        .standard_filters(false)
+        .hidden(opts.skip_hidden)
+        .follow_links(opts.follow_symlinks)
+        .max_depth(opts.max_depth)
+        .add_custom_ignore_filename(IGNORE_FILE)
+        .overrides(opts.overrides(path)?)
+        .build();
+
+    for entry in walker {
+        // 无法读取的子目录不影响其他图片
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(e) => {
+                log::warn!("scan {} error, {e}", path.display());
+                continue;
+            }
+        };
         let path = entry.path();
 
-        if path.is_file() {
+        // 不跟随链接时 file_type 为链接本身，不会索引链接的文件
+        if entry.file_type().is_some_and(|t| t.is_file()) {
             let ext = path.extension().and_then(|s| s.to_str());
 
             if let Some(ext) = ext {
@@ -151,11 +363,196 @@ pub fn is_support_file(path: &Path) -> bool {
 
 impl From<walkdir::Error> for AppError {
     fn from(e: walkdir::Error) -> Self {
-        AppError::Internal(e.to_string())
+        AppError::Io {
+            message: e.to_string(),
+            path: e.path().map(|p| p.display().to_string()),
+        }
+    }
+}
+
+impl From<ignore::Error> for AppError {
+    fn from(e: ignore::Error) -> Self {
+        AppError::Io {
+            message: e.to_string(),
+            path: None,
+        }
     }
 }
 
 pub fn remove_file(ab_path: &Path) -> Result<(), AppError> {
-    std::fs::remove_file(ab_path)?;
+    std::fs::remove_file(ab_path).map_err(|e| AppError::io(e, ab_path))?;
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::uuid_utils;
+
+    /**
+     * 在临时目录中创建文件，返回 root
+     */
+    fn tree(files: &[&str]) -> PathBuf {
+        let root = std::env::temp_dir().join(format!("imgsearch_scan_{}", uuid_utils::get()));
+        for f in files {
+            let p = root.join(f);
+            std::fs::create_dir_all(p.parent().unwrap()).unwrap();
+            std::fs::write(&p, b"x").unwrap();
+        }
+        root
+    }
+
+    fn scan(root: &Path, opts: &ScanOptions) -> Vec<String> {
+        let mut r = find_all_images(root, opts)
+            .unwrap()
+            .into_iter()
+            .map(|p| {
+                p.strip_prefix(root)
+                    .unwrap()
+                    .components()
+                    .map(|c| c.as_os_str().to_string_lossy().to_string())
+                    .collect::<Vec<_>>()
+                    .join("/")
+            })
+            .collect::<Vec<_>>();
+        r.sort();
+        r
+    }
+
+    #[test]
+    fn finds_supported_images() {
+        let root = tree(&[
+            "a.jpg",
+            "b.txt",
+            "sub/c.png",
+            "sub/deep/d.webp",
+            ".hidden/e.jpg",
+        ]);
+
+        assert_eq!(
+            scan(&root, &ScanOptions::default()),
+            [".hidden/e.jpg", "a.jpg", "sub/c.png", "sub/deep/d.webp"]
+        );
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn skip_hidden_and_max_depth() {
+        let root = tree(&[
+            "a.jpg",
+            ".b.jpg",
+            ".hidden/c.jpg",
+            "sub/d.jpg",
+            "sub/deep/e.jpg",
+        ]);
+
+        let opts = ScanOptions {
+            skip_hidden: true,
+            ..Default::default()
+        };
+        assert_eq!(scan(&root, &opts), ["a.jpg", "sub/d.jpg", "sub/deep/e.jpg"]);
+        assert!(opts.is_ignored(&root, &root.join(".hidden/c.jpg")).unwrap());
+
+        let opts = ScanOptions {
+            max_depth: Some(2),
+            ..Default::default()
+        };
+        assert_eq!(
+            scan(&root, &opts),
+            [".b.jpg", ".hidden/c.jpg", "a.jpg", "sub/d.jpg"]
+        );
+        assert!(opts
+            .is_ignored(&root, &root.join("sub/deep/e.jpg"))
+            .unwrap());
+        assert!(!opts.is_ignored(&root, &root.join("sub/d.jpg")).unwrap());
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn include_and_exclude_globs() {
+        let root = tree(&["a.jpg", "b.png", "raw/c.jpg", "sub/d.jpg"]);
+
+        let opts = ScanOptions {
+            include: vec!["*.jpg".to_string()],
+            exclude: vec!["raw/**".to_string()],
+            ..Default::default()
+        };
+        assert_eq!(scan(&root, &opts), ["a.jpg", "sub/d.jpg"]);
+        assert!(opts.is_ignored(&root, &root.join("raw/c.jpg")).unwrap());
+        assert!(opts.is_ignored(&root, &root.join("b.png")).unwrap());
+        assert!(!opts.is_ignored(&root, &root.join("sub/d.jpg")).unwrap());
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn ignore_file_rules() {
+        let root = tree(&["a.jpg", "cache/b.jpg", "sub/c.jpg", "sub/keep.jpg"]);
+        std::fs::write(root.join(IGNORE_FILE), "cache/\nsub/*.jpg\n").unwrap();
+        std::fs::write(root.join("sub").join(IGNORE_FILE), "!keep.jpg\n").unwrap();
+
+        let opts = ScanOptions::default();
+        assert_eq!(scan(&root, &opts), ["a.jpg", "sub/keep.jpg"]);
+        assert!(opts.is_ignored(&root, &root.join("cache/b.jpg")).unwrap());
+        assert!(opts.is_ignored(&root, &root.join("sub/c.jpg")).unwrap());
+        assert!(!opts.is_ignored(&root, &root.join("sub/keep.jpg")).unwrap());
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlinks_follow_option() {
+        let root = tree(&["a.jpg"]);
+        let outside = tree(&["b.jpg", "dir/c.jpg"]);
+        std::os::unix::fs::symlink(outside.join("b.jpg"), root.join("link.jpg")).unwrap();
+        std::os::unix::fs::symlink(outside.join("dir"), root.join("linkdir")).unwrap();
+
+        let opts = ScanOptions::default();
+        assert_eq!(scan(&root, &opts), ["a.jpg"]);
+        assert!(opts.is_ignored(&root, &root.join("link.jpg")).unwrap());
+
+        let opts = ScanOptions {
+            follow_symlinks: true,
+            ..Default::default()
+        };
+        assert_eq!(scan(&root, &opts), ["a.jpg", "link.jpg", "linkdir/c.jpg"]);
+        assert!(!opts.is_ignored(&root, &root.join("link.jpg")).unwrap());
+
+        std::fs::remove_dir_all(&root).unwrap();
+        std::fs::remove_dir_all(&outside).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn unreadable_dir_does_not_abort_scan() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let root = tree(&["a.jpg", "locked/b.jpg"]);
+        let locked = root.join("locked");
+        std::fs::set_permissions(&locked, std::fs::Permissions::from_mode(0o000)).unwrap();
+        // root 用户不受权限限制
+        let readable = std::fs::read_dir(&locked).is_ok();
+
+        let r = scan(&root, &ScanOptions::default());
+        std::fs::set_permissions(&locked, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+        if readable {
+            assert_eq!(r, ["a.jpg", "locked/b.jpg"]);
+        } else {
+            assert_eq!(r, ["a.jpg"]);
+        }
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn supported_file_extensions() {
+        assert!(is_support_file(Path::new("/x/a.jpg")));
+        assert!(is_support_file(Path::new("/x/a.webp")));
+        assert!(!is_support_file(Path::new("/x/a.gif")));
+        assert!(!is_support_file(Path::new("/x/a")));
+    }
+}
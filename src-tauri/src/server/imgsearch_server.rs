@@ -1,4 +1,10 @@
-use std::path::Path;
+use std::{
+    path::Path,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+};
 
 use tauri::http::StatusCode;
 use tauri_plugin_http::reqwest::{self, Response};
@@ -12,6 +18,8 @@ pub struct ImgseachServer {
     apikey: String,
     host: String,
     client: reqwest::Client,
+    // 服务端可接受的单批最大图片数，0 表示未知，复制出的实例共用
+    max_batch: Arc<AtomicUsize>,
 }
 impl ImgseachServer {
     pub fn new(apikey: String, host: String) -> Self {
@@ -19,6 +27,21 @@ impl ImgseachServer {
             apikey,
             host,
             client: reqwest::Client::new(),
+            max_batch: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+}
+
+/**
+ * 复制后与原实例共用连接池，用于在长时间任务中不持有 server 的读锁
+ */
+impl Clone for ImgseachServer {
+    fn clone(&self) -> Self {
+        Self {
+            apikey: self.apikey.clone(),
+            host: self.host.clone(),
+            client: self.client.clone(),
+            max_batch: self.max_batch.clone(),
         }
     }
 }
@@ -57,9 +80,7 @@ impl ImageIndexer for ImgseachServer {
         let mut form = reqwest::multipart::Form::new();
 
         for (i, p) in params.iter().enumerate() {
-            form = form
-                .file(format!("thumbnail_{i}"), p.to_str().unwrap())
-                .await?;
+            form = form.file(format!("thumbnail_{i}"), p).await?;
         }
 
         form = form.text("rename", rename.to_string());
@@ -75,10 +96,28 @@ impl ImageIndexer for ImgseachServer {
         if r.status().is_success() {
             Ok(r.json::<Vec<ImageIndexResp>>().await?)
         } else {
+            if r.status() == StatusCode::PAYLOAD_TOO_LARGE {
+                // 请求过大，后续批次减半；并发的批次只会把限制调得更小
+                let limit = (params.len() / 2).max(1);
+                log::warn!("image_indexes payload too large, max batch = {limit}");
+                let _ = self
+                    .max_batch
+                    .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |n| match n {
+                        0 => Some(limit),
+                        n => Some(n.min(limit)),
+                    });
+            }
             Err(judge_imgsearch_error(r).await)
         }
     }
 
+    fn max_batch_size(&self) -> Option<usize> {
+        match self.max_batch.load(Ordering::Relaxed) {
+            0 => None,
+            n => Some(n),
+        }
+    }
+
     async fn text_vectorize(&self, text: &str) -> Result<Vec<f32>, AppError> {
         let r = self
             .client
@@ -107,6 +146,14 @@ async fn judge_imgsearch_error(r: Response) -> AppError {
             AppError::RightsLimit("image_index count not enough".to_string())
         }
         StatusCode::UNAUTHORIZED => AppError::Auth("apikey has been invalid".to_string()),
+        StatusCode::TOO_MANY_REQUESTS => {
+            let retry_after = r
+                .headers()
+                .get(reqwest::header::RETRY_AFTER)
+                .and_then(|v| v.to_str().ok())
+                .and_then(|v| v.parse::<u64>().ok());
+            AppError::RateLimited { retry_after }
+        }
         _ => {
             let msg = r.text().await;
 
@@ -114,7 +161,10 @@ async fn judge_imgsearch_error(r: Response) -> AppError {
                 AppError::from(e)
             } else {
                 let msg = msg.unwrap();
-                AppError::Internal(format!("unknown error, status: {status}, message: {msg}",))
+                AppError::Http {
+                    status: status.as_u16(),
+                    message: format!("unknown error, message: {msg}"),
+                }
             }
         }
     }
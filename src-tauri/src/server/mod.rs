@@ -27,6 +27,11 @@ pub trait ImageIndexer {
     ) -> Result<Vec<ImageIndexResp>, AppError>;
 
     async fn text_vectorize(&self, text: &str) -> Result<Vec<f32>, AppError>;
+
+    /**
+     * 服务端限制的单批最大图片数，未知时返回 None
+     */
+    fn max_batch_size(&self) -> Option<usize>;
 }
 
 pub fn init_server(auth_store: Arc<Store<Wry>>) -> Result<Option<ImgseachServer>, AppError> {
@@ -37,8 +42,7 @@ pub fn init_server(auth_store: Arc<Store<Wry>>) -> Result<Option<ImgseachServer>
         let apikey = binding.as_str();
 
         if let Some(apikey) = apikey {
-            let host = env!("NEXT_PUBLIC_IMGSEARCH_HOST");
-            Ok(Some(ImgseachServer::new(apikey.to_string(), host.into())))
+            Ok(Some(new_server(apikey.to_string())))
         } else {
             Ok(None)
         }
@@ -46,3 +50,8 @@ pub fn init_server(auth_store: Arc<Store<Wry>>) -> Result<Option<ImgseachServer>
         Ok(None)
     }
 }
+
+pub fn new_server(apikey: String) -> ImgseachServer {
+    let host = env!("NEXT_PUBLIC_IMGSEARCH_HOST");
+    ImgseachServer::new(apikey, host.into())
+}
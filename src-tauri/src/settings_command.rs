@@ -0,0 +1,255 @@
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
+use tauri::{State, Wry};
+use tauri_plugin_store::Store;
+
+use crate::{error::AppError, server::ImageIndexer, uuid_utils, GlobalState};
+
+static INDEX_SETTINGS_KEY: &str = "index";
+static LOCAL_API_SETTINGS_KEY: &str = "localApi";
+static BACKUP_SETTINGS_KEY: &str = "backup";
+static MAINTENANCE_SETTINGS_KEY: &str = "maintenance";
+
+/**
+ * 索引相关的设置，保存在 Settings.json 中
+ */
+#[derive(Deserialize, Serialize, Clone, Debug)]
+#[serde(default, rename_all = "camelCase")]
+pub struct IndexSettings {
+    // 生成缩略图的并行线程数，电池供电时可以调小
+    pub thumbnail_workers: usize,
+    // 每次上传的图片数，会受服务端限制
+    pub batch_size: usize,
+    // 同时进行的上传请求数
+    pub upload_concurrency: usize,
+    // 开启重命名时，不直接改名，先保存为待确认的建议
+    pub stage_renames: bool,
+}
+
+impl Default for IndexSettings {
+    fn default() -> Self {
+        Self {
+            thumbnail_workers: default_workers(),
+            batch_size: 5,
+            upload_concurrency: 2,
+            stage_renames: false,
+        }
+    }
+}
+
+impl IndexSettings {
+    pub fn thumbnail_workers(&self) -> usize {
+        self.thumbnail_workers.max(1)
+    }
+
+    /**
+     * 与服务端限制协商后的批大小
+     */
+    pub fn batch_size<S: ImageIndexer>(&self, server: Option<&S>) -> usize {
+        let size = self.batch_size.max(1);
+        match server.and_then(|s| s.max_batch_size()) {
+            Some(limit) => size.min(limit),
+            None => size,
+        }
+    }
+
+    pub fn upload_concurrency(&self) -> usize {
+        self.upload_concurrency.max(1)
+    }
+}
+
+/**
+ * 本地 http 接口的设置，默认关闭，只监听 127.0.0.1
+ */
+#[derive(Deserialize, Serialize, Clone, Debug)]
+#[serde(default, rename_all = "camelCase")]
+pub struct LocalApiSettings {
+    pub enabled: bool,
+    pub port: u16,
+    // 调用方需携带的 Bearer token，首次读取时生成
+    pub token: String,
+}
+
+impl Default for LocalApiSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            port: 17863,
+            token: "".to_string(),
+        }
+    }
+}
+
+/**
+ * 数据库定期备份的设置
+ */
+#[derive(Deserialize, Serialize, Clone, Copy, Debug)]
+#[serde(default, rename_all = "camelCase")]
+pub struct BackupSettings {
+    pub enabled: bool,
+    pub interval_hours: u64,
+    // 保留的快照数量
+    pub keep: usize,
+}
+
+impl Default for BackupSettings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            interval_hours: 24,
+            keep: 7,
+        }
+    }
+}
+
+/**
+ * 空闲时自动整理数据库的设置
+ */
+#[derive(Deserialize, Serialize, Clone, Copy, Debug)]
+#[serde(default, rename_all = "camelCase")]
+pub struct MaintenanceSettings {
+    pub enabled: bool,
+    // 表没有写入超过该时长后整理
+    pub idle_minutes: u64,
+    // 旧版本保留天数
+    pub keep_versions_days: u64,
+}
+
+impl Default for MaintenanceSettings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            idle_minutes: 10,
+            keep_versions_days: 7,
+        }
+    }
+}
+
+fn default_workers() -> usize {
+    std::thread::available_parallelism()
+        .map(|n| n.get())
+        .unwrap_or(4)
+}
+
+pub fn index_settings(settings_store: &Store<Wry>) -> IndexSettings {
+    let r = settings_store.get(INDEX_SETTINGS_KEY);
+
+    if let Some(v) = r {
+        match serde_json::from_value::<IndexSettings>(v) {
+            Ok(s) => return s,
+            Err(e) => log::warn!("invalid index settings, use default, {e}"),
+        }
+    }
+
+    IndexSettings::default()
+}
+
+fn save_index_settings(
+    settings_store: Arc<Store<Wry>>,
+    settings: &IndexSettings,
+) -> Result<(), AppError> {
+    settings_store.set(INDEX_SETTINGS_KEY, serde_json::to_value(settings)?);
+    settings_store.save()?;
+    Ok(())
+}
+
+pub fn local_api_settings(settings_store: &Store<Wry>) -> Result<LocalApiSettings, AppError> {
+    let mut settings = match settings_store.get(LOCAL_API_SETTINGS_KEY) {
+        Some(v) => serde_json::from_value::<LocalApiSettings>(v).unwrap_or_else(|e| {
+            log::warn!("invalid local api settings, use default, {e}");
+            LocalApiSettings::default()
+        }),
+        None => LocalApiSettings::default(),
+    };
+
+    if settings.token.is_empty() {
+        settings.token = uuid_utils::get();
+        save_local_api_settings(settings_store, &settings)?;
+    }
+
+    Ok(settings)
+}
+
+pub fn save_local_api_settings(
+    settings_store: &Store<Wry>,
+    settings: &LocalApiSettings,
+) -> Result<(), AppError> {
+    settings_store.set(LOCAL_API_SETTINGS_KEY, serde_json::to_value(settings)?);
+    settings_store.save()?;
+    Ok(())
+}
+
+pub fn backup_settings(settings_store: &Store<Wry>) -> BackupSettings {
+    match settings_store.get(BACKUP_SETTINGS_KEY) {
+        Some(v) => serde_json::from_value::<BackupSettings>(v).unwrap_or_else(|e| {
+            log::warn!("invalid backup settings, use default, {e}");
+            BackupSettings::default()
+        }),
+        None => BackupSettings::default(),
+    }
+}
+
+pub fn maintenance_settings(settings_store: &Store<Wry>) -> MaintenanceSettings {
+    match settings_store.get(MAINTENANCE_SETTINGS_KEY) {
+        Some(v) => serde_json::from_value::<MaintenanceSettings>(v).unwrap_or_else(|e| {
+            log::warn!("invalid maintenance settings, use default, {e}");
+            MaintenanceSettings::default()
+        }),
+        None => MaintenanceSettings::default(),
+    }
+}
+
+#[tauri::command]
+pub async fn get_index_settings(state: State<'_, GlobalState>) -> Result<IndexSettings, AppError> {
+    Ok(index_settings(&state.settings_store))
+}
+
+#[tauri::command]
+pub async fn set_index_settings(
+    settings: IndexSettings,
+    state: State<'_, GlobalState>,
+) -> Result<(), AppError> {
+    log::info!("set index settings: {settings:?}");
+    save_index_settings(state.settings_store.clone(), &settings)
+}
+
+#[tauri::command]
+pub async fn get_backup_settings(
+    state: State<'_, GlobalState>,
+) -> Result<BackupSettings, AppError> {
+    Ok(backup_settings(&state.settings_store))
+}
+
+#[tauri::command]
+pub async fn set_backup_settings(
+    settings: BackupSettings,
+    state: State<'_, GlobalState>,
+) -> Result<(), AppError> {
+    log::info!("set backup settings: {settings:?}");
+    state
+        .settings_store
+        .set(BACKUP_SETTINGS_KEY, serde_json::to_value(settings)?);
+    state.settings_store.save()?;
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn get_maintenance_settings(
+    state: State<'_, GlobalState>,
+) -> Result<MaintenanceSettings, AppError> {
+    Ok(maintenance_settings(&state.settings_store))
+}
+
+#[tauri::command]
+pub async fn set_maintenance_settings(
+    settings: MaintenanceSettings,
+    state: State<'_, GlobalState>,
+) -> Result<(), AppError> {
+    log::info!("set maintenance settings: {settings:?}");
+    state
+        .settings_store
+        .set(MAINTENANCE_SETTINGS_KEY, serde_json::to_value(settings)?);
+    state.settings_store.save()?;
+    Ok(())
+}
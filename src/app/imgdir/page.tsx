@@ -11,6 +11,7 @@ import { Trash2, Plus, FolderOpen } from "lucide-react";
 import { useToast } from "@/components/ui/use-toast";
 import { open } from '@tauri-apps/plugin-dialog';
 import CheckGuide from "@/components/check-guide";
+import ImgdirOptions from "@/components/imgdir-options";
 
 export default function ImgdirPage({ afterAdd }: { afterAdd?: () => Promise<void> }) {
   const [imgDirs, setImgDirs] = useState<ImgDir[]>([]);
@@ -74,7 +75,19 @@ export default function ImgdirPage({ afterAdd }: { afterAdd?: () => Promise<void
     }
 
     try {
-      await addImgDir(newDir).catch(e => {
+      await addImgDir(newDir, (overlap) => {
+        const messages = [];
+        if (overlap.parent) {
+          messages.push(`Nested in ${overlap.parent}, its images now belong to ${newDir.root}.`);
+        }
+        if (overlap.children.length > 0) {
+          messages.push(`Contains ${overlap.children.join(", ")}, whose images stay with those directories.`);
+        }
+        toast({
+          title: "Nested directories",
+          description: messages.join(" "),
+        });
+      }).catch(e => {
         toast({
           title: "Failed",
           description: e.message,
@@ -195,7 +208,8 @@ export default function ImgdirPage({ afterAdd }: { afterAdd?: () => Promise<void
                   className="ml-2"
                 />
               </TableCell>
-              <TableCell>
+              <TableCell className="space-x-2">
+                <ImgdirOptions imgDir={dir} afterSave={loadImgDirs} />
                 <Button
                   variant="destructive"
                   size="sm"
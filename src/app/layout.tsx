@@ -10,6 +10,7 @@ import { TailwindIndicator } from "@/components/tailwind-indicator"
 import { ThemeProvider } from "@/components/theme-provider"
 import { Toaster } from "@/components/ui/toaster"
 import Startup from "../components/startup"
+import DbIntegrity from "@/components/db-integrity"
 
 interface ExamplesLayoutProps {
   children: React.ReactNode
@@ -36,6 +37,7 @@ export default function MyApp({ children }: ExamplesLayoutProps) {
           </div>
           <TailwindIndicator />
           <Toaster />
+          <DbIntegrity />
         </ThemeProvider>
         <StyleSwitcher />
       </body>
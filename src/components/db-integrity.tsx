@@ -0,0 +1,71 @@
+'use client';
+
+import { useEffect, useState } from "react";
+import {
+  AlertDialog,
+  AlertDialogAction,
+  AlertDialogCancel,
+  AlertDialogContent,
+  AlertDialogDescription,
+  AlertDialogFooter,
+  AlertDialogHeader,
+  AlertDialogTitle,
+} from "@/components/ui/alert-dialog";
+import { BackupInfo, dbIntegrity, IntegrityReport, listDbBackups, restoreDbBackup, rollbackDb } from "@/data/db";
+
+/**
+ * 启动时数据库完整性检查失败，提示回滚到可读版本或从快照恢复
+ */
+export default function DbIntegrity() {
+  const [report, setReport] = useState<IntegrityReport | null>(null);
+  const [backup, setBackup] = useState<BackupInfo | null>(null);
+
+  useEffect(() => {
+    dbIntegrity().then(r => {
+      if (r.ok) {
+        return;
+      }
+      setReport(r);
+      // 按时间倒序，取最新的快照
+      listDbBackups().then(bs => setBackup(bs[0] ?? null));
+    });
+  }, []);
+
+  if (report == null) {
+    return null;
+  }
+
+  const canRollback = report.rollbackVersion != null;
+
+  return (
+    <AlertDialog open={true} onOpenChange={(open) => !open && setReport(null)}>
+      <AlertDialogContent>
+        <AlertDialogHeader>
+          <AlertDialogTitle>The image index is damaged</AlertDialogTitle>
+          <AlertDialogDescription>
+            {report.error}
+            <br />
+            {canRollback
+              ? `Version ${report.rollbackVersion} is readable. Roll back to it?`
+              : backup
+                ? `Restore the backup ${backup.name}? The app will restart.`
+                : "No readable version or backup was found."}
+          </AlertDialogDescription>
+        </AlertDialogHeader>
+        <AlertDialogFooter>
+          <AlertDialogCancel>Later</AlertDialogCancel>
+          {canRollback && (
+            <AlertDialogAction onClick={() => rollbackDb().then(setReport)}>
+              Roll back
+            </AlertDialogAction>
+          )}
+          {!canRollback && backup && (
+            <AlertDialogAction onClick={() => restoreDbBackup(backup.name)}>
+              Restore
+            </AlertDialogAction>
+          )}
+        </AlertDialogFooter>
+      </AlertDialogContent>
+    </AlertDialog>
+  );
+}
@@ -0,0 +1,259 @@
+'use client';
+
+import { useState } from "react";
+import { Button } from "@/components/ui/button";
+import { Checkbox } from "@/components/ui/checkbox";
+import { Input } from "@/components/ui/input";
+import { Label } from "@/components/ui/label";
+import { Select, SelectContent, SelectItem, SelectTrigger, SelectValue } from "@/components/ui/select";
+import { Textarea } from "@/components/ui/textarea";
+import { Dialog, DialogContent, DialogDescription, DialogHeader, DialogTitle, DialogTrigger } from "@/components/ui/dialog";
+import { useToast } from "@/components/ui/use-toast";
+import {
+  CaseStyle,
+  DEFAULT_RENAME_RULE,
+  DEFAULT_SCAN_OPTIONS,
+  DEFAULT_SIZE_THRESHOLDS,
+  ImgDir,
+  ImgDirOptions,
+  RenameRule,
+  ScanOptions,
+  SizeThresholds,
+  updateImgDirOptions,
+} from "@/data/img-dirs";
+
+// 每行一个 glob
+const toLines = (globs: string[]) => globs.join("\n");
+const fromLines = (text: string) => text.split("\n").map(g => g.trim()).filter(g => g.length > 0);
+import { Settings } from "lucide-react";
+
+/**
+ * imgdir 的设置，保存后在下次索引时生效
+ */
+export default function ImgdirOptions({ imgDir, afterSave }: { imgDir: ImgDir, afterSave?: () => Promise<void> }) {
+  const [open, setOpen] = useState(false);
+  const [options, setOptions] = useState<ImgDirOptions>({});
+  const rule = options.renameRule ?? DEFAULT_RENAME_RULE;
+  const setRule = (changed: Partial<RenameRule>) => setOptions({ ...options, renameRule: { ...rule, ...changed } });
+  const scan = options.scan ?? DEFAULT_SCAN_OPTIONS;
+  const setScan = (changed: Partial<ScanOptions>) => setOptions({ ...options, scan: { ...scan, ...changed } });
+  const thresholds = options.thresholds ?? DEFAULT_SIZE_THRESHOLDS;
+  const setThresholds = (changed: Partial<SizeThresholds>) => setOptions({ ...options, thresholds: { ...thresholds, ...changed } });
+  // glob 按行编辑，保存时再拆分，避免输入时丢失空行
+  const [include, setInclude] = useState("");
+  const [exclude, setExclude] = useState("");
+
+  const { toast } = useToast();
+
+  const handleOpenChange = (open: boolean) => {
+    setOpen(open);
+    if (open) {
+      setOptions({
+        writeXmp: imgDir.writeXmp ?? false,
+        renameRule: { ...DEFAULT_RENAME_RULE, ...imgDir.renameRule },
+        scan: { ...DEFAULT_SCAN_OPTIONS, ...imgDir.scan },
+        thresholds: { ...DEFAULT_SIZE_THRESHOLDS, ...imgDir.thresholds },
+      });
+      setInclude(toLines(imgDir.scan?.include ?? []));
+      setExclude(toLines(imgDir.scan?.exclude ?? []));
+    }
+  };
+
+  const handleSave = async () => {
+    try {
+      await updateImgDirOptions(imgDir.root, {
+        ...options,
+        scan: { ...scan, include: fromLines(include), exclude: fromLines(exclude) },
+      });
+      setOpen(false);
+      toast({
+        title: "Success",
+        description: "Directory options saved, they apply on the next index",
+      });
+      afterSave?.();
+    } catch (error) {
+      toast({
+        title: "Failed to save",
+        description: error instanceof Error ? error.message : String(error),
+        variant: "destructive",
+      });
+    }
+  };
+
+  return (
+    <Dialog open={open} onOpenChange={handleOpenChange}>
+      <DialogTrigger asChild>
+        <Button variant="ghost" size="sm">
+          <Settings className="h-4 w-4" />
+        </Button>
+      </DialogTrigger>
+      <DialogContent className="max-h-[90vh] overflow-y-auto">
+        <DialogHeader>
+          <DialogTitle>Directory Options</DialogTitle>
+          <DialogDescription className="font-mono text-sm">
+            {imgDir.root}
+          </DialogDescription>
+        </DialogHeader>
+
+        <div className="space-y-4">
+          <div className="flex items-center space-x-2">
+            <Checkbox
+              id="writeXmp"
+              checked={options.writeXmp}
+              onCheckedChange={(checked) => setOptions({ ...options, writeXmp: checked as boolean })}
+            />
+            <label htmlFor="writeXmp" className="text-sm font-medium leading-none">
+              Read and write .xmp sidecar files
+            </label>
+          </div>
+
+          <div className="space-y-2">
+            <Label htmlFor="template">Rename template</Label>
+            <Input
+              id="template"
+              placeholder="{ai_name}"
+              value={rule.template}
+              onChange={(e) => setRule({ template: e.target.value })}
+            />
+            <p className="text-xs text-muted-foreground">
+              Supports {"{ai_name}"} {"{orig_name}"} {"{date}"} {"{counter}"}
+            </p>
+          </div>
+
+          <div className="flex gap-2">
+            <div className="flex-1 space-y-2">
+              <Label htmlFor="maxLen">Max name length</Label>
+              <Input
+                id="maxLen"
+                type="number"
+                min={1}
+                value={rule.maxLen}
+                onChange={(e) => setRule({ maxLen: Number(e.target.value) || DEFAULT_RENAME_RULE.maxLen })}
+              />
+            </div>
+            <div className="flex-1 space-y-2">
+              <Label>Case</Label>
+              <Select value={rule.case} onValueChange={(value) => setRule({ case: value as CaseStyle })}>
+                <SelectTrigger>
+                  <SelectValue />
+                </SelectTrigger>
+                <SelectContent>
+                  <SelectItem value="keep">Keep</SelectItem>
+                  <SelectItem value="lower">lower</SelectItem>
+                  <SelectItem value="upper">UPPER</SelectItem>
+                  <SelectItem value="snake">snake_case</SelectItem>
+                  <SelectItem value="kebab">kebab-case</SelectItem>
+                </SelectContent>
+              </Select>
+            </div>
+          </div>
+
+          <div className="flex items-center space-x-2">
+            <Checkbox
+              id="transliterate"
+              checked={rule.transliterate}
+              onCheckedChange={(checked) => setRule({ transliterate: checked as boolean })}
+            />
+            <label htmlFor="transliterate" className="text-sm font-medium leading-none">
+              Transliterate non-ASCII names to ASCII
+            </label>
+          </div>
+
+          <div className="flex gap-2">
+            <div className="flex-1 space-y-2">
+              <Label htmlFor="include">Include globs</Label>
+              <Textarea
+                id="include"
+                placeholder="one per line, e.g. photos/**"
+                value={include}
+                onChange={(e) => setInclude(e.target.value)}
+              />
+            </div>
+            <div className="flex-1 space-y-2">
+              <Label htmlFor="exclude">Exclude globs</Label>
+              <Textarea
+                id="exclude"
+                placeholder="one per line, e.g. **/cache/**"
+                value={exclude}
+                onChange={(e) => setExclude(e.target.value)}
+              />
+            </div>
+          </div>
+          <p className="text-xs text-muted-foreground">
+            Paths are relative to the directory. A .imgsearchignore file is also honored.
+          </p>
+
+          <div className="flex items-center space-x-2">
+            <Checkbox
+              id="skipHidden"
+              checked={scan.skipHidden}
+              onCheckedChange={(checked) => setScan({ skipHidden: checked as boolean })}
+            />
+            <label htmlFor="skipHidden" className="text-sm font-medium leading-none">
+              Skip hidden files and directories
+            </label>
+          </div>
+
+          <div className="flex items-center space-x-2">
+            <Checkbox
+              id="followSymlinks"
+              checked={scan.followSymlinks}
+              onCheckedChange={(checked) => setScan({ followSymlinks: checked as boolean })}
+            />
+            <label htmlFor="followSymlinks" className="text-sm font-medium leading-none">
+              Follow symbolic links
+            </label>
+          </div>
+
+          <div className="space-y-2">
+            <Label htmlFor="maxDepth">Max depth</Label>
+            <Input
+              id="maxDepth"
+              type="number"
+              min={0}
+              placeholder="unlimited"
+              value={scan.maxDepth ?? ""}
+              onChange={(e) => setScan({ maxDepth: e.target.value === "" ? null : Number(e.target.value) })}
+            />
+          </div>
+
+          <div className="grid grid-cols-2 gap-2">
+            {([
+              ["minWidth", "Min width (px)"],
+              ["minHeight", "Min height (px)"],
+              ["minBytes", "Min file size (bytes)"],
+              ["maxBytes", "Max file size (bytes)"],
+            ] as [keyof SizeThresholds, string][]).map(([key, label]) => (
+              <div key={key} className="space-y-2">
+                <Label htmlFor={key}>{label}</Label>
+                <Input
+                  id={key}
+                  type="number"
+                  min={0}
+                  placeholder="0 for no limit"
+                  value={thresholds[key] as number}
+                  onChange={(e) => setThresholds({ [key]: Math.max(0, Number(e.target.value) || 0) })}
+                />
+              </div>
+            ))}
+          </div>
+
+          <div className="flex items-center space-x-2">
+            <Checkbox
+              id="skipTransparent"
+              checked={thresholds.skipTransparent}
+              onCheckedChange={(checked) => setThresholds({ skipTransparent: checked as boolean })}
+            />
+            <label htmlFor="skipTransparent" className="text-sm font-medium leading-none">
+              Skip fully transparent images
+            </label>
+          </div>
+
+          <Button onClick={handleSave} className="w-full">
+            Save
+          </Button>
+        </div>
+      </DialogContent>
+    </Dialog>
+  );
+}
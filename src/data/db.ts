@@ -0,0 +1,32 @@
+import { invoke } from '@tauri-apps/api/core';
+
+export interface IntegrityReport {
+    ok: boolean
+    error?: string
+    rollbackVersion?: number
+}
+
+export interface BackupInfo {
+    name: string
+    createdAt: number
+    size: number
+}
+
+export async function dbIntegrity(): Promise<IntegrityReport> {
+    return invoke("db_integrity");
+}
+
+export async function rollbackDb(): Promise<IntegrityReport> {
+    return invoke("rollback_db");
+}
+
+export async function listDbBackups(): Promise<BackupInfo[]> {
+    return invoke("list_db_backups");
+}
+
+/**
+ * 恢复后应用会重启
+ */
+export async function restoreDbBackup(name: string) {
+    return invoke("restore_db_backup", { name });
+}
@@ -10,6 +10,83 @@ export interface ImgDir {
     root: string
     enableRename: boolean
     createTime: Date
+    // 读写 .xmp 附属文件，默认关闭
+    writeXmp?: boolean
+    renameRule?: RenameRule
+    scan?: ScanOptions
+    thresholds?: SizeThresholds
+}
+
+/**
+ * 跳过小图的阈值，0 表示不限制
+ */
+export interface SizeThresholds {
+    minWidth: number
+    minHeight: number
+    minBytes: number
+    maxBytes: number
+    // 跳过完全透明的图片
+    skipTransparent: boolean
+}
+
+export const DEFAULT_SIZE_THRESHOLDS: SizeThresholds = {
+    minWidth: 0,
+    minHeight: 0,
+    minBytes: 0,
+    maxBytes: 0,
+    skipTransparent: false,
+};
+
+/**
+ * 扫描规则，include / exclude 为相对 root 的 glob，include 为空时包含全部
+ */
+export interface ScanOptions {
+    include: string[]
+    exclude: string[]
+    skipHidden: boolean
+    followSymlinks: boolean
+    // root 本身为 0，不限制时为空
+    maxDepth?: number | null
+}
+
+export const DEFAULT_SCAN_OPTIONS: ScanOptions = {
+    include: [],
+    exclude: [],
+    skipHidden: false,
+    followSymlinks: false,
+    maxDepth: null,
+};
+
+export type CaseStyle = "keep" | "lower" | "upper" | "snake" | "kebab";
+
+/**
+ * 自动重命名的文件名规则
+ */
+export interface RenameRule {
+    // 支持 {ai_name} {orig_name} {date} {counter}
+    template: string
+    // 文件名（不含后缀）的最大字符数
+    maxLen: number
+    case: CaseStyle
+    // 非 ascii 字符转写为 ascii
+    transliterate: boolean
+}
+
+export const DEFAULT_RENAME_RULE: RenameRule = {
+    template: "{ai_name}",
+    maxLen: 120,
+    case: "keep",
+    transliterate: false,
+};
+
+/**
+ * 修改 imgdir 设置时只传需要修改的字段
+ */
+export interface ImgDirOptions {
+    writeXmp?: boolean
+    renameRule?: RenameRule
+    scan?: ScanOptions
+    thresholds?: SizeThresholds
 }
 
 
@@ -17,27 +94,51 @@ export async function getAll(): Promise<ImgDir[]> {
     return ImgDirStore.values();
 }
 
-export async function addImgDir(imgDir: ImgDir) {
-    // 判断imgdirs里面的元素，是否是imgdir的父目录
+/**
+ * 与其他 imgdir 的嵌套关系，图片归属于包含它的最深的 imgdir
+ */
+export interface RootOverlap {
+    parent?: string | null
+    children: string[]
+}
 
-    const ImgDirs = await getAll();
-    ImgDirs.forEach(e => {
-        if (imgDir.root.startsWith(e.root) || e.root.startsWith(imgDir.root)) {
-            throw new Error("Imgdirs already contains a directory that is a parent or child of the specified directory");
-        }
-    });
+/**
+ * 嵌套的 imgdir 由后端处理归属，索引完成后通过 onOverlap 返回嵌套关系
+ */
+export async function addImgDir(imgDir: ImgDir, onOverlap?: (overlap: RootOverlap) => void) {
+    if (await ImgDirStore.has(imgDir.root)) {
+        throw new Error("The directory has already been added");
+    }
 
     await ImgDirStore.set(imgDir.root, imgDir);
-    invoke("after_add_imgdir", { root: imgDir.root, rename: imgDir.enableRename }).then(() => {
+    invoke<RootOverlap>("after_add_imgdir", { root: imgDir.root, rename: imgDir.enableRename }).then((overlap) => {
         watchImgdir(imgDir.root);
+        if (overlap.parent || overlap.children.length > 0) {
+            onOverlap?.(overlap);
+        }
     })
 }
 
+/**
+ * 修改 imgdir 的设置，新的设置在下次索引时生效
+ */
+export async function updateImgDirOptions(root: string, options: ImgDirOptions): Promise<ImgDir> {
+    return invoke("update_imgdir_options", { root, options });
+}
+
 export async function removeImgDir(imgDirPath: string) {
     await ImgDirStore.delete(imgDirPath);
     invoke("after_remove_imgdir", { root: imgDirPath });
 }
 
+/**
+ * imgdir 移动到新位置后，索引改为指向新位置
+ */
+export async function relocateImgDir(oldRoot: string, newRoot: string) {
+    await invoke("relocate_root", { old: oldRoot, new: newRoot });
+    watchImgdir(newRoot);
+}
+
 async function watchImgdir(root: string) {
 
     function isKind<K extends string>(obj: any, kind: K): obj is Record<K, any> {
@@ -65,7 +166,8 @@ async function watchImgdir(root: string) {
 
             if (isKind(type, 'modify')) {
                 if (isKind(type.modify, "rename")) {
-                    invoke("rename", { model: { old: event.paths[0], new: event.paths[1] } })
+                    invoke<string[]>("rename", { model: { old: event.paths[0], new: event.paths[1] } })
+                        .then((roots) => roots.forEach((root) => watchImgdir(root)));
                     console.log("modify rename", event.paths);
                 } else {
 
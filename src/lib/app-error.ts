@@ -0,0 +1,31 @@
+/**
+ * Error returned by tauri commands, see src-tauri/src/error.rs
+ */
+export type AppErrorCode =
+  | "network"
+  | "http"
+  | "rate_limited"
+  | "internal"
+  | "db"
+  | "io"
+  | "image"
+  | "config"
+  | "locked"
+  | "auth"
+  | "server_not_ready"
+  | "rights_limit"
+  | "img_format"
+
+export interface AppError {
+  code: AppErrorCode
+  message: string
+  details: {
+    path?: string
+    status?: number
+    retryAfter?: number
+  }
+}
+
+export function isAppError(e: unknown): e is AppError {
+  return typeof e === "object" && e !== null && "code" in e && "message" in e
+}